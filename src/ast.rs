@@ -28,6 +28,12 @@ pub enum Statement {
     Cycle(Cycle),
     FunctionCall(FunctionCall),
     Print(PrintStatement),
+    Exit(Expression),
+    Read(Vec<String>), // Target variables, filled from one line of input
+    Section(String), // Header text printed verbatim
+    Clear(String), // Reset a variable to 0, 0.0 or false
+    Return(Expression), // Value handed back by a function with a return type
+    VarDeclaration(Vec<VarDeclaration>), // Locals declared partway through a body
 }
 
 #[derive(Debug, Clone)]
@@ -47,6 +53,17 @@ pub enum Expression {
     IntegerLiteral(i32),
     FloatLiteral(f64),
     BooleanLiteral(bool),
+    BuiltinCall {
+        function: Builtin,
+        arguments: Vec<Expression>,
+    },
+    FunctionCall(FunctionCall), // Call of a function with a return type, used for its value
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Builtin {
+    Fmod,
+    FloatCast, // float(x)
 }
 
 #[derive(Debug, Clone)]
@@ -59,6 +76,11 @@ pub enum Operator {
     LessThan,
     Equal,
     NotEqual,
+    BitAnd, // &, ints only
+    BitOr,  // |
+    BitXor, // ^
+    ShiftLeft,  // <<, ints only
+    ShiftRight, // >> (arithmetic)
 }
 
 #[derive(Debug, Clone)]
@@ -83,11 +105,24 @@ pub struct FunctionCall {
 #[derive(Debug, Clone)]
 pub enum PrintStatement {
     Expression(Expression),
+    Hex(Expression),    // Integer printed in base 16, e.g. 0xff
+    Binary(Expression), // Integer printed in base 2, e.g. 0b101
+    Typed(Expression),  // Value followed by its static type, e.g. 5 : int
+    Items(Vec<PrintItem>), // Anything but a single expression, e.g. print(a, sep(" | "), b)
+}
+
+#[derive(Debug, Clone)]
+pub enum PrintItem {
+    Expression(Expression),
+    Text(String),      // String literal, printed as is
+    Separator(String), // sep("..."): printed between the items that follow on the same line
+    Endl,              // Ends the line; the next item starts a new one
 }
 
 #[derive(Debug, Clone)]
 pub struct FunctionDeclaration {
     pub id: String,
+    pub return_type: Option<Type>, // None for void
     pub parameters: Vec<Parameter>,
     pub vars: Vec<VarDeclaration>,
     pub body: Vec<Statement>,
@@ -1,5 +1,5 @@
 use std::collections::HashMap;
-use crate::ast::{Program, FunctionDeclaration, Type};
+use crate::ast::{Program, FunctionDeclaration, Type, VarDeclaration};
 use std::fmt;
 use crate::quadruples::MemoryAddresses;
 
@@ -8,6 +8,7 @@ use crate::quadruples::MemoryAddresses;
 pub enum FunctionDirError {
     DuplicateVariable(String, String), // (var_name, scope_name)
     DuplicateFunction(String),
+    UnknownScope(String),
     // Can add more error types as needed
 }
 
@@ -18,6 +19,8 @@ impl fmt::Display for FunctionDirError {
                 write!(f, "Duplicate variable '{}' in scope '{}'", var, scope),
             FunctionDirError::DuplicateFunction(func) =>
                 write!(f, "Duplicate function name '{}'", func),
+            FunctionDirError::UnknownScope(scope) =>
+                write!(f, "Unknown scope '{}'", scope),
         }
     }
 }
@@ -37,6 +40,7 @@ pub struct FunctionInfo {
     pub local_variables: HashMap<String, VariableInfo>,
     pub is_program: bool,  // Flag to indicate if this is the program entry
     pub start_quad_idx: Option<i32>, // Starting quadruple index for the function
+    pub return_address: Option<i32>, // Cell that RETURN writes the result to, for typed functions
 }
 
 /// Function directory that stores information about all functions in a program
@@ -47,6 +51,8 @@ pub struct FunctionDirectory {
     int_counter: i32,
     float_counter: i32,
     bool_counter: i32,
+    // Non-fatal lints found while building the directory
+    warnings: Vec<String>,
 }
 
 impl FunctionDirectory {
@@ -57,6 +63,7 @@ impl FunctionDirectory {
             int_counter: MemoryAddresses::INT_START,    // Starting at base addresses defined in quadruples.rs
             float_counter: MemoryAddresses::FLOAT_START,
             bool_counter: MemoryAddresses::BOOL_START,   // Using dedicated bool addresses
+            warnings: Vec::new(),
         }
     }
 
@@ -82,6 +89,10 @@ impl FunctionDirectory {
     }
 
     /// Create a function directory from an AST Program
+    ///
+    /// Scoping rules: a name may only be declared once within the same scope (globals,
+    /// or a function's parameters and locals together), but a function scope may shadow
+    /// a global with the same name. Lookups resolve to the nearest scope first.
     pub fn from_program(program: &Program) -> Result<Self, FunctionDirError> {
         let mut directory = Self::new();
 
@@ -92,6 +103,7 @@ impl FunctionDirectory {
             local_variables: HashMap::new(),
             is_program: true,
             start_quad_idx: None,
+            return_address: None,
         });
 
         // Add global variables to a special "global" entry
@@ -120,6 +132,7 @@ impl FunctionDirectory {
             local_variables: global_vars,
             is_program: false,
             start_quad_idx: None,
+            return_address: None,
         });
 
         // Add main function
@@ -131,6 +144,7 @@ impl FunctionDirectory {
             local_variables: main_vars,
             is_program: false,
             start_quad_idx: None, // Will be set during quad generation
+            return_address: None,
         });
 
         // Add all other functions
@@ -153,6 +167,8 @@ impl FunctionDirectory {
 
         // Check for duplicate parameters and assign addresses
         for param in &func.parameters {
+            self.warn_if_shadows_global(&param.id, "Parameter", &func.id);
+
             // Check if parameter name already exists
             if param_names.contains_key(&param.id) {
                 return Err(FunctionDirError::DuplicateVariable(
@@ -188,6 +204,8 @@ impl FunctionDirectory {
                 ));
             }
 
+            self.warn_if_shadows_global(&var.id, "Local variable", &func.id);
+
             // Assign a memory address based on the variable type
             let address = self.get_next_address(&var.var_type);
 
@@ -197,17 +215,138 @@ impl FunctionDirectory {
             });
         }
 
+        // The result lives in its own cell so the caller can copy it out after GOSUB
+        let return_address = func.return_type.as_ref().map(|typ| self.get_next_address(typ));
+
         self.functions.insert(func.id.clone(), FunctionInfo {
-            return_type: None, // BabyDuck doesn't specify return types in the grammar
+            return_type: func.return_type.clone(),
             parameters: params,
             local_variables: local_vars,
             is_program: false,
             start_quad_idx: None, // Will be set during quad generation
+            return_address,
         });
 
         Ok(())
     }
 
+    /// Build the directory of a program linked with modules from other files, merging
+    /// each module's directory into the program's in order
+    pub fn from_programs(program: &Program, modules: &[Program]) -> Result<Self, FunctionDirError> {
+        let mut directory = Self::from_program(program)?;
+        for module in modules {
+            directory.merge(Self::from_program(module)?)?;
+        }
+        Ok(directory)
+    }
+
+    /// Merge the directory of a separately compiled file into this one.
+    ///
+    /// Functions and globals share one namespace across files, so a function or global
+    /// declared in both is an error. The other file's program entry is dropped and its
+    /// `main` locals join this `main`. Its variables are moved past this directory's
+    /// addresses, and start indices are cleared since they belonged to its own quadruples.
+    pub fn merge(&mut self, other: FunctionDirectory) -> Result<(), FunctionDirError> {
+        for (name, info) in &other.functions {
+            if !info.is_program && name != "global" && name != "main" && self.functions.contains_key(name) {
+                return Err(FunctionDirError::DuplicateFunction(name.clone()));
+            }
+        }
+        for scope in ["global", "main"] {
+            if let (Some(mine), Some(theirs)) = (self.functions.get(scope), other.functions.get(scope)) {
+                let mut names: Vec<&String> = theirs.local_variables.keys().collect();
+                names.sort();
+                if let Some(name) = names.into_iter().find(|name| mine.local_variables.contains_key(*name)) {
+                    return Err(FunctionDirError::DuplicateVariable(name.clone(), scope.to_string()));
+                }
+            }
+        }
+
+        // The other directory allocated from the same bases, so shift each segment past ours
+        let int_offset = self.int_counter - MemoryAddresses::INT_START;
+        let float_offset = self.float_counter - MemoryAddresses::FLOAT_START;
+        let bool_offset = self.bool_counter - MemoryAddresses::BOOL_START;
+        let relocate = |var_type: &Type, address: i32| match var_type {
+            Type::Int => address + int_offset,
+            Type::Float => address + float_offset,
+            Type::Bool => address + bool_offset,
+        };
+
+        for (name, mut info) in other.functions {
+            if info.is_program {
+                continue;
+            }
+            for var in info.local_variables.values_mut() {
+                var.address = relocate(&var.var_type, var.address);
+            }
+            for (_, param_type, address) in info.parameters.iter_mut() {
+                *address = relocate(param_type, *address);
+            }
+            if let (Some(return_type), Some(address)) = (&info.return_type, info.return_address.as_mut()) {
+                *address = relocate(return_type, *address);
+            }
+            info.start_quad_idx = None;
+
+            match self.functions.get_mut(&name) {
+                Some(existing) => existing.local_variables.extend(info.local_variables),
+                None => {
+                    self.functions.insert(name, info);
+                }
+            }
+        }
+
+        self.int_counter += other.int_counter - MemoryAddresses::INT_START;
+        self.float_counter += other.float_counter - MemoryAddresses::FLOAT_START;
+        self.bool_counter += other.bool_counter - MemoryAddresses::BOOL_START;
+        self.warnings.extend(other.warnings);
+        Ok(())
+    }
+
+    /// Lint a parameter or local that hides a global of the same name. Shadowing is
+    /// allowed, but it's easy to do by accident.
+    fn warn_if_shadows_global(&mut self, name: &str, kind: &str, func_name: &str) {
+        let shadows = self.get_global_variables().is_some_and(|globals| globals.contains_key(name));
+        if shadows {
+            self.warnings.push(format!(
+                "{} '{}' in function '{}' shadows global variable '{}'", kind, name, func_name, name
+            ));
+        }
+    }
+
+    /// Take the lint warnings collected while building the directory
+    pub fn take_warnings(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.warnings)
+    }
+
+    /// Register a variable declared partway through a body in an existing scope,
+    /// applying the same duplicate rules as the declaration preamble
+    pub fn add_local_variable(&mut self, scope_name: &str, var: &VarDeclaration) -> Result<i32, FunctionDirError> {
+        let func_info = match self.functions.get(scope_name) {
+            Some(info) => info,
+            None => return Err(FunctionDirError::UnknownScope(scope_name.to_string())),
+        };
+
+        if func_info.local_variables.contains_key(&var.id) {
+            return Err(FunctionDirError::DuplicateVariable(var.id.clone(), scope_name.to_string()));
+        }
+        if func_info.parameters.iter().any(|(name, _, _)| name == &var.id) {
+            return Err(FunctionDirError::DuplicateVariable(
+                var.id.clone(),
+                format!("function {} (parameter conflict)", scope_name)
+            ));
+        }
+
+        self.warn_if_shadows_global(&var.id, "Local variable", scope_name);
+        let address = self.get_next_address(&var.var_type);
+        if let Some(info) = self.functions.get_mut(scope_name) {
+            info.local_variables.insert(var.id.clone(), VariableInfo {
+                var_type: var.var_type.clone(),
+                address,
+            });
+        }
+        Ok(address)
+    }
+
     // Debug functions
 
     /// Get information about a function by name
@@ -220,8 +359,18 @@ impl FunctionDirectory {
         self.functions.contains_key(name)
     }
 
+    /// The scope a lookup really searches. The program's own entry declares nothing,
+    /// since the program's variables are the globals, so it resolves to "global".
+    fn lookup_scope<'a>(&self, function_name: &'a str) -> &'a str {
+        match self.functions.get(function_name) {
+            Some(info) if info.is_program => "global",
+            _ => function_name,
+        }
+    }
+
     /// Get a variable's type from a function (or from global if not found)
     pub fn get_variable_type(&self, function_name: &str, variable_name: &str) -> Option<&Type> {
+        let function_name = self.lookup_scope(function_name);
         // Check if variable exists in the function's local scope
         if let Some(func_info) = self.functions.get(function_name) {
             if let Some(var_info) = func_info.local_variables.get(variable_name) {
@@ -249,7 +398,9 @@ impl FunctionDirectory {
     }
 
     /// Get a variable's address from a function (or from global if not found)
+    /// A local or parameter that shadows a global always wins over the global.
     pub fn get_variable_address(&self, function_name: &str, variable_name: &str) -> Option<i32> {
+        let function_name = self.lookup_scope(function_name);
         // Check if variable exists in the function's local scope
         if let Some(func_info) = self.functions.get(function_name) {
             if let Some(var_info) = func_info.local_variables.get(variable_name) {
@@ -305,10 +456,8 @@ impl FunctionDirectory {
             // Int can be assigned to float (but with possible precision loss)
             (Type::Float, Type::Int) => true,
 
-            // A boolean result can be assigned to a boolean variable
-            (Type::Bool, _) => true,  // Allowing any type to bool for comparison results
-
-            // All other combinations are invalid
+            // All other combinations are invalid: a comparison result never converts to a number,
+            // and a bool target takes only a Bool, such as true or a comparison result
             _ => false,
         }
     }
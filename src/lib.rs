@@ -1,4 +1,155 @@
 use lalrpop_util::lalrpop_mod;
 pub mod ast;
+pub mod function_directory;
+pub mod quadruples;
+pub mod vm;
 
-lalrpop_mod!(babyduck);
\ No newline at end of file
+lalrpop_mod!(pub babyduck);
+
+use function_directory::FunctionDirectory;
+use quadruples::{CompileError, QuadrupleGenerator};
+
+/// A BabyDuck value exchanged with embedders: program inputs, printed values and results
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Int(i32),
+    Float(f64),
+    Bool(bool),
+    String(String), // Only printed: section labels and ints printed in hex or binary
+}
+
+impl std::fmt::Display for Value {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Value::Int(i) => write!(f, "{}", i),
+            Value::Float(x) => write!(f, "{}", x),
+            Value::Bool(b) => write!(f, "{}", b),
+            Value::String(text) => write!(f, "{}", text),
+        }
+    }
+}
+
+impl From<i32> for Value {
+    fn from(value: i32) -> Self {
+        Value::Int(value)
+    }
+}
+
+impl From<f64> for Value {
+    fn from(value: f64) -> Self {
+        Value::Float(value)
+    }
+}
+
+impl From<bool> for Value {
+    fn from(value: bool) -> Self {
+        Value::Bool(value)
+    }
+}
+
+/// Parse a BabyDuck program and pretty-print its AST
+pub fn emit_ast(source: &str) -> Result<String, String> {
+    babyduck::ProgramParser::new()
+        .parse(source)
+        .map(|program| format!("{:#?}", program))
+        .map_err(|e| format!("Parsing failed: {:?}", e))
+}
+
+/// The result of compiling a program: its object file text and the lint warnings raised on the way
+#[derive(Debug, Clone)]
+pub struct CompiledProgram {
+    pub obj_content: String,
+    pub warnings: Vec<String>,
+    program_id: String,
+    generator: QuadrupleGenerator, // Kept for the outputs made on request, like to_rust_source
+}
+
+impl CompiledProgram {
+    /// Write the object file text to any writer, e.g. stdout for `-o -`; the bytes match the .obj file
+    pub fn write_obj<W: std::io::Write>(&self, writer: &mut W) -> std::io::Result<()> {
+        writer.write_all(self.obj_content.as_bytes())
+    }
+
+    /// The program transpiled ahead of time into a standalone Rust function, `run() -> Result<i32, String>`,
+    /// for environments without the VM. Paste it into a crate and call it; it needs nothing else.
+    pub fn to_rust_source(&self) -> String {
+        self.generator.to_rust_source(&self.program_id)
+    }
+}
+
+/// Settings for compile_ast_with_options and compile_source_with_options
+#[derive(Debug, Clone, Default)]
+pub struct CompileOptions {
+    /// Fail with a CompileError::Warning for every lint, instead of returning them as warnings
+    pub warnings_as_errors: bool,
+}
+
+/// Compile a program built in memory, without going through the text parser.
+/// The object file loads into the VM exactly like one produced from source.
+pub fn compile_ast(program: ast::Program) -> Result<CompiledProgram, CompileError> {
+    compile_ast_with_options(program, &CompileOptions::default())
+}
+
+/// Compile a program built in memory with the given options
+pub fn compile_ast_with_options(program: ast::Program, options: &CompileOptions) -> Result<CompiledProgram, CompileError> {
+    compile_linked(&program, &[], options)
+}
+
+/// Compile a program together with modules from other files into one object file.
+/// Functions and globals share one namespace, so a name defined in two files is an error.
+/// Only the program's main runs; the modules contribute their functions and globals.
+pub fn compile_linked_sources(source: &str, module_sources: &[&str]) -> Result<CompiledProgram, CompileError> {
+    let parse = |text: &str| babyduck::ProgramParser::new()
+        .parse(text)
+        .map_err(|e| CompileError::Parse(format!("{:?}", e)));
+    let program = parse(source)?;
+    let modules = module_sources.iter().map(|text| parse(text)).collect::<Result<Vec<_>, _>>()?;
+    compile_linked(&program, &modules, &CompileOptions::default())
+}
+
+fn compile_linked(program: &ast::Program, modules: &[ast::Program], options: &CompileOptions) -> Result<CompiledProgram, CompileError> {
+    let mut function_directory = FunctionDirectory::from_programs(program, modules)
+        .map_err(|e| CompileError::Semantic(e.to_string()))?;
+
+    let mut quad_gen = QuadrupleGenerator::new();
+    // The directory keeps its warnings when they are errors, so the generator promotes them too
+    let mut warnings = Vec::new();
+    if options.warnings_as_errors {
+        quad_gen.treat_warnings_as_errors();
+    } else {
+        warnings = function_directory.take_warnings();
+    }
+    quad_gen.set_function_directory(function_directory);
+    if let Err(message) = quad_gen.generate_for_linked_programs(program, modules) {
+        // Keep the structured error when there is exactly one, so callers can match on it
+        return Err(match quad_gen.compile_errors() {
+            [error] => error.clone(),
+            _ => CompileError::Semantic(message),
+        });
+    }
+    warnings.extend(quad_gen.take_warnings());
+
+    let source_name = format!("<ast {}>", program.id);
+    Ok(CompiledProgram {
+        obj_content: quad_gen.to_obj_content(&program.id, &source_name),
+        warnings,
+        program_id: program.id.clone(),
+        generator: quad_gen,
+    })
+}
+
+/// Parse and compile BabyDuck source text. Every failure, including a malformed
+/// program, comes back as a CompileError rather than a panic, so any input is safe to feed in.
+/// Expressions and blocks nested past the generator's limits are rejected before anything
+/// recurses over them.
+pub fn compile_source(source: &str) -> Result<CompiledProgram, CompileError> {
+    compile_source_with_options(source, &CompileOptions::default())
+}
+
+/// Parse and compile BabyDuck source text with the given options
+pub fn compile_source_with_options(source: &str, options: &CompileOptions) -> Result<CompiledProgram, CompileError> {
+    let program = babyduck::ProgramParser::new()
+        .parse(source)
+        .map_err(|e| CompileError::Parse(format!("{:?}", e)))?;
+    compile_ast_with_options(program, options)
+}
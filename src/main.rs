@@ -1,166 +1,426 @@
-use lalrpop_util::lalrpop_mod;
+use std::cell::RefCell;
 use std::env;
 use std::fs;
 use std::io::{Write, BufWriter}; // For writing to file
 use std::path::Path;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
 
-lalrpop_mod!(pub babyduck);
-
-pub mod ast;
-pub mod function_directory;
-pub mod quadruples;
-mod vm;
+pub mod repl;
 
+use compiler::{ast, babyduck, function_directory, quadruples, vm};
 use function_directory::{FunctionDirectory, FunctionDirError};
 use quadruples::{QuadrupleGenerator, OpCode};
 
 
 fn main() {
     let args: Vec<String> = env::args().collect();
+    let strict = args.iter().any(|arg| arg == "--strict");
+    let warn_param_assign = args.iter().any(|arg| arg == "--warn-param-assign");
+    let werror = args.iter().any(|arg| arg == "--werror");
+    let time_phases = args.iter().any(|arg| arg == "--time");
+    let mut args: Vec<String> = args.into_iter()
+        .filter(|arg| arg != "--strict" && arg != "--warn-param-assign" && arg != "--werror" && arg != "--time")
+        .collect();
 
-    if args.len() < 2 {
-        eprintln!("Usage: babyduck_compiler <input_file.bd>");
-        std::process::exit(1);
+    // -O0 (default) to -O2; see QuadrupleGenerator::set_optimization_level for the passes
+    let mut opt_level = 0;
+    if let Some(pos) = args.iter().position(|arg| arg.starts_with("-O")) {
+        match args[pos].as_str() {
+            "-O0" => opt_level = 0,
+            "-O1" => opt_level = 1,
+            "-O2" => opt_level = 2,
+            other => {
+                eprintln!("Unknown optimization level '{}'; expected -O0, -O1 or -O2", other);
+                std::process::exit(1);
+            }
+        }
+        args.remove(pos);
+    }
+
+    // --obj-format=numeric (default) or --obj-format=names for readable opcodes
+    let mut symbolic_obj = false;
+    if let Some(pos) = args.iter().position(|arg| arg.starts_with("--obj-format=")) {
+        match &args[pos]["--obj-format=".len()..] {
+            "numeric" => symbolic_obj = false,
+            "names" => symbolic_obj = true,
+            other => {
+                eprintln!("Unknown object format '{}'; expected numeric or names", other);
+                std::process::exit(1);
+            }
+        }
+        args.remove(pos);
     }
 
-    let input_filename = &args[1];
-    let source_code = match fs::read_to_string(input_filename) {
-        Ok(content) => content,
+    let (output_path, run_vm) = match take_output_args(&mut args) {
+        Ok(options) => options,
         Err(e) => {
-            eprintln!("Error reading file '{}': {}", input_filename, e);
+            eprintln!("{}", e);
             std::process::exit(1);
         }
     };
 
-    println!("Compiling: {}", input_filename);
+    let mut max_memory = None;
+    if let Some(pos) = args.iter().position(|arg| arg == "--max-memory") {
+        match args.get(pos + 1).map(|cells| cells.parse::<usize>()) {
+            Some(Ok(cells)) => max_memory = Some(cells),
+            _ => {
+                eprintln!("--max-memory expects a number of memory cells");
+                std::process::exit(1);
+            }
+        }
+        args.drain(pos..pos + 2);
+    }
+
+    if args.len() < 2 {
+        eprintln!("Usage: babyduck_compiler [--strict] [--warn-param-assign] [--werror] [--time] [-O0|-O1|-O2] [--obj-format=numeric|names] [--max-memory <cells>] [-o <output.obj>|-] [--run|--no-run] <input_file.bd> [<module.bd>...]");
+        eprintln!("       babyduck_compiler --emit-ast <input_file.bd>");
+        eprintln!("       babyduck_compiler [--strict] [--warn-param-assign] [--werror] [-O0|-O1|-O2] --emit-listing <input_file.bd> [<module.bd>...]");
+        eprintln!("       babyduck_compiler --disasm <input_file.obj>");
+        eprintln!("       babyduck_compiler --repl");
+        std::process::exit(1);
+    }
+
+    let options = BuildOptions {
+        strict,
+        warn_param_assign,
+        werror,
+        time_phases,
+        opt_level,
+        symbolic_obj,
+        output_path,
+        run_vm,
+        max_memory,
+    };
+
+    if args[1] == "--repl" {
+        std::process::exit(repl::run_interactive());
+    }
 
-    // 1. Parse the source code
-    let ast_result = babyduck::ProgramParser::new().parse(&source_code);
-    let ast = match ast_result {
-        Ok(program_ast) => {
-            println!("Parsing successful!");
-            program_ast
+    if args[1] == "--disasm" {
+        let Some(obj_filename) = args.get(2) else {
+            eprintln!("Usage: babyduck_compiler --disasm <input_file.obj>");
+            std::process::exit(1);
+        };
+        let mut vm_instance = vm::VM::new();
+        if let Err(e) = vm_instance.load_obj_file(obj_filename) {
+            eprintln!("Error loading object file ('{}'): {}", obj_filename, e);
+            std::process::exit(1);
         }
-        Err(e) => {
-            eprintln!("Parsing failed: {:?}", e);
+        print!("{}", vm_instance.disassemble());
+        return;
+    }
+
+    if args[1] == "--emit-listing" {
+        let Some(input_filename) = args.get(2) else {
+            eprintln!("Usage: babyduck_compiler [--strict] [--warn-param-assign] [--werror] [-O0|-O1|-O2] --emit-listing <input_file.bd> [<module.bd>...]");
             std::process::exit(1);
+        };
+        let generated = generate_quadruples(&options, input_filename, &args[3..], &Console::stdio(), &mut PhaseTimes::default(), &|_| {});
+        match generated {
+            Ok((_, quad_gen)) => print!("{}", quad_gen.format_listing()),
+            Err(e) => {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
         }
-    };
+        return;
+    }
 
-    // 2. Create Function Directory
-    let function_directory_result = FunctionDirectory::from_program(&ast);
-    let function_directory = match function_directory_result {
-        Ok(dir) => {
-            println!("Function Directory created successfully!");
-            dir
+    if args[1] == "--emit-ast" {
+        let Some(input_filename) = args.get(2) else {
+            eprintln!("Usage: babyduck_compiler --emit-ast <input_file.bd>");
+            std::process::exit(1);
+        };
+        let source_code = fs::read_to_string(input_filename).unwrap_or_else(|e| {
+            eprintln!("Error reading file '{}': {}", input_filename, e);
+            std::process::exit(1);
+        });
+        match compiler::emit_ast(&source_code) {
+            Ok(dump) => println!("{}", dump),
+            Err(e) => {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
         }
+        return;
+    }
+
+    match build_and_run(&options, &args[1], &args[2..], &Console::stdio()) {
+        Ok(0) => {}
+        Ok(exit_code) => std::process::exit(exit_code),
         Err(e) => {
-            eprintln!("Failed to create function directory: {}", e);
+            eprintln!("{}", e);
             std::process::exit(1);
         }
+    }
+}
+
+/// The flags of a compile (and maybe run) from the command line
+#[derive(Debug, Default)]
+struct BuildOptions {
+    strict: bool,
+    warn_param_assign: bool,
+    werror: bool,
+    time_phases: bool,
+    opt_level: u8,
+    symbolic_obj: bool,
+    output_path: Option<String>,
+    run_vm: bool,
+    max_memory: Option<usize>,
+}
+
+/// Where the compile pipeline writes: stdout and stderr from the command line, buffers in tests
+struct Console {
+    out: Rc<RefCell<dyn Write>>,
+    err: Rc<RefCell<dyn Write>>,
+}
+
+impl Console {
+    fn stdio() -> Self {
+        Console { out: Rc::new(RefCell::new(std::io::stdout())), err: Rc::new(RefCell::new(std::io::stderr())) }
+    }
+
+    fn print(&self, text: &str) {
+        let _ = self.out.borrow_mut().write_all(text.as_bytes());
+    }
+
+    fn eprint(&self, text: &str) {
+        let _ = self.err.borrow_mut().write_all(text.as_bytes());
+    }
+}
+
+/// Compile `input_filename`, linking in the modules, write its object file and run it when
+/// `options.run_vm` is set. Gives the program's exit code, or the message of the failing step.
+fn build_and_run(options: &BuildOptions, input_filename: &str, module_filenames: &[String], console: &Console) -> Result<i32, String> {
+    // With `-o -` the object file goes to stdout, so progress messages move to stderr
+    let to_stdout = options.output_path.as_deref() == Some("-");
+    let progress = |message: &str| {
+        let line = format!("{}\n", message);
+        if to_stdout { console.eprint(&line) } else { console.print(&line) }
+    };
+
+    let mut timings = PhaseTimes::default();
+    let (ast, quad_gen) = generate_quadruples(options, input_filename, module_filenames, console, &mut timings, &progress)?;
+
+    // 4. Prepare .obj file content
+    let mut obj_content = build_obj_content(&quad_gen, &ast, input_filename);
+    if options.symbolic_obj {
+        obj_content = symbolic_obj_content(&obj_content);
+    }
+
+    // 5. Write to .obj file
+    let output_filename = options.output_path.clone().unwrap_or_else(|| {
+        Path::new(input_filename).with_extension("obj").to_str().unwrap_or("output.obj").to_string()
+    });
+    let output_filename = output_filename.as_str();
+    if to_stdout {
+        console.print(&obj_content);
+    } else {
+        write_obj_file(&obj_content, output_filename)?;
+        console.print(&format!("Compilation successful! Output written to {}\n", output_filename));
+    }
+
+    if !options.run_vm {
+        if options.time_phases {
+            console.eprint(&timings.report());
+        }
+        return Ok(0);
+    }
+
+    progress(&format!("\n--- Attempting to run VM on {} ---", output_filename));
+    let mut vm_instance = vm::VM::new();
+    if let Some(cells) = options.max_memory {
+        vm_instance.set_max_memory(cells);
+    }
+    vm_instance.set_verify_function_table(true);
+    let out = Rc::clone(&console.out);
+    vm_instance.set_print_callback(move |value| {
+        let _ = writeln!(out.borrow_mut(), "{}", value);
+    });
+    // There is no file to load after writing to stdout, so that run goes from memory
+    let loaded = if to_stdout {
+        vm_instance.load_obj_str(&obj_content)
+    } else {
+        vm_instance.load_obj_file(output_filename)
     };
+    if let Err(e) = loaded {
+        console.eprint(&format!("Error loading object file ('{}') into VM: {}\n", output_filename, e));
+        return Ok(0);
+    }
+
+    let started = Instant::now();
+    let run_result = vm_instance.run();
+    timings.record("VM execution", started);
+    if options.time_phases {
+        console.eprint(&timings.report());
+    }
+    match run_result {
+        Ok(exit_code) => {
+            progress("VM execution finished successfully.");
+            Ok(exit_code)
+        }
+        Err(e) => {
+            console.eprint(&format!("VM runtime error: {}\n", e));
+            Ok(0)
+        }
+    }
+}
+
+/// Parse `input_filename` and its modules, build the function directory and generate the
+/// quadruples with the options' checks, as both compiling and `--emit-listing` do. Each phase
+/// is recorded in `timings` and announced through `progress`; warnings go to stderr.
+fn generate_quadruples(
+    options: &BuildOptions,
+    input_filename: &str,
+    module_filenames: &[String],
+    console: &Console,
+    timings: &mut PhaseTimes,
+    progress: &dyn Fn(&str),
+) -> Result<(ast::Program, QuadrupleGenerator), String> {
+    let source_code = fs::read_to_string(input_filename)
+        .map_err(|e| format!("Error reading file '{}': {}", input_filename, e))?;
+    progress(&format!("Compiling: {}", input_filename));
+
+    // 1. Parse the source code
+    let started = Instant::now();
+    let ast = babyduck::ProgramParser::new().parse(&source_code)
+        .map_err(|e| format!("Parsing failed: {:?}", e))?;
+    progress("Parsing successful!");
+
+    // Any further input files are modules: their functions and globals are linked
+    // into this program, whose main stays the only entry point
+    let mut modules = Vec::new();
+    for module_filename in module_filenames {
+        let module_source = fs::read_to_string(module_filename)
+            .map_err(|e| format!("Error reading file '{}': {}", module_filename, e))?;
+        let module = babyduck::ProgramParser::new().parse(&module_source)
+            .map_err(|e| format!("Parsing '{}' failed: {:?}", module_filename, e))?;
+        modules.push(module);
+    }
+    timings.record("Parsing", started);
+
+    // 2. Create Function Directory
+    let started = Instant::now();
+    let mut function_directory = FunctionDirectory::from_programs(&ast, &modules)
+        .map_err(|e| format!("Failed to create function directory: {}", e))?;
+    timings.record("Function directory", started);
+    progress("Function Directory created successfully!");
+
+    // Under --werror the directory keeps its warnings, for the generator to report as errors
+    if !options.werror {
+        for warning in function_directory.take_warnings() {
+            console.eprint(&format!("Warning: {}\n", warning));
+        }
+    }
 
     // 3. Generate Quadruples
     let mut quad_gen = QuadrupleGenerator::new();
     quad_gen.set_function_directory(function_directory.clone());
+    quad_gen.set_strict(options.strict);
+    quad_gen.set_optimization_level(options.opt_level);
+    if options.warn_param_assign {
+        quad_gen.warn_on_parameter_assignment();
+    }
+    if options.werror {
+        quad_gen.treat_warnings_as_errors();
+    }
 
-    let quad_gen_result = quad_gen.generate_for_program(&ast);
-    if let Err(e) = quad_gen_result {
-        eprintln!("Quadruple generation failed: {}", e);
-        std::process::exit(1);
+    let started = Instant::now();
+    let quad_gen_result = quad_gen.generate_for_linked_programs(&ast, &modules);
+    timings.record("Quadruple generation", started);
+    quad_gen_result.map_err(|e| format!("Quadruple generation failed: {}", e))?;
+    progress("Quadruple generation successful!");
+    for warning in quad_gen.take_warnings() {
+        console.eprint(&format!("Warning: {}\n", warning));
     }
-    println!("Quadruple generation successful!");
 
-    // 4. Prepare .obj file content
-    let mut obj_content = String::new();
-    obj_content.push_str("// BabyDuck Object File\n");
-    obj_content.push_str(&format!("// Source: {}\n\n", input_filename));
-
-    // Integer Constants
-    obj_content.push_str("CONSTANTS_INT:\n");
-    for (value, addr) in quad_gen.get_int_constants() {
-        obj_content.push_str(&format!("{},{}\n", value, addr));
-    }
-    obj_content.push_str("END_CONSTANTS_INT\n\n");
-
-    // Float Constants
-    obj_content.push_str("CONSTANTS_FLOAT:\n");
-    for (value, addr) in quad_gen.get_float_constants() {
-        obj_content.push_str(&format!("{},{}\n", value, addr));
-    }
-    obj_content.push_str("END_CONSTANTS_FLOAT\n\n");
-    
-    // Boolean Constants are no longer saved to the obj file
-
-    // Functions
-    obj_content.push_str("FUNCTIONS:\n");
-    if let Some(final_function_directory) = &quad_gen.function_directory {
-        for (name, info) in final_function_directory.get_all_functions() {
-            // Skip "global" scope and the program's own name entry, as they aren't callable functions.
-            // "main" is the entry point and is included.
-            if name == "global" || name == &ast.id { continue; }
-            let start_idx = info.start_quad_idx.unwrap_or(-1);
-            let param_count = info.parameters.len();
-            let local_var_count = info.local_variables.len(); // This counts distinct local variable declarations.
-
-            let mut param_addrs_str = String::new();
-            for (_, _, addr) in &info.parameters { // Iterate over (param_name, param_type, param_address)
-                param_addrs_str.push_str(&format!(",{}", addr));
-            }
-            
-            obj_content.push_str(&format!("{},{},{},{}{}\n", name, start_idx, param_count, local_var_count, param_addrs_str));
+
+    Ok((ast, quad_gen))
+}
+
+/// Take `-o <path>`, `--run` and `--no-run` out of the arguments. The compiled program runs
+/// right away unless an output path is given, as batch builds shouldn't execute what they
+/// compile; `--run` and `--no-run` override that either way. A path of `-` means stdout.
+fn take_output_args(args: &mut Vec<String>) -> Result<(Option<String>, bool), String> {
+    let mut output_path = None;
+    if let Some(pos) = args.iter().position(|arg| arg == "-o") {
+        let Some(path) = args.get(pos + 1).cloned() else {
+            return Err("-o expects the path of the object file to write".to_string());
+        };
+        output_path = Some(path);
+        args.drain(pos..pos + 2);
+    }
+    let mut run_vm = output_path.is_none();
+    for (flag, enabled) in [("--run", true), ("--no-run", false)] {
+        if let Some(pos) = args.iter().position(|arg| arg == flag) {
+            run_vm = enabled;
+            args.remove(pos);
         }
     }
-    obj_content.push_str("END_FUNCTIONS\n\n");
+    Ok((output_path, run_vm))
+}
+
+/// Write the object file text to `output_filename`
+fn write_obj_file(obj_content: &str, output_filename: &str) -> Result<(), String> {
+    let file = fs::File::create(output_filename)
+        .map_err(|e| format!("Error creating object file '{}': {}", output_filename, e))?;
+    let mut writer = BufWriter::new(file);
+    let written = writer.write_all(obj_content.as_bytes()).and_then(|_| writer.flush());
+    written.map_err(|e| format!("Error writing to object file '{}': {}", output_filename, e))
+}
 
+/// How long each phase of a compile-and-run took, reported under `--time`
+#[derive(Debug, Default)]
+struct PhaseTimes {
+    phases: Vec<(&'static str, Duration)>,
+}
 
-    // Quadruples (Machine-readable format)
-    obj_content.push_str("QUADRUPLES:\n");
-    for quad in quad_gen.get_quadruples().iter() {
-        // Output raw op_codes and addresses
-        obj_content.push_str(&format!("{},{},{},{}\n",
-                                      quad.operation,
-                                      quad.arg1,
-                                      quad.arg2,
-                                      quad.result
-        ));
+impl PhaseTimes {
+    /// Record a phase that began at `started` and has just finished
+    fn record(&mut self, phase: &'static str, started: Instant) {
+        self.phases.push((phase, started.elapsed()));
     }
-    obj_content.push_str("END_QUADRUPLES\n");
 
-    // 5. Write to .obj file
-    let output_path = Path::new(input_filename).with_extension("obj");
-    let output_filename = output_path.to_str().unwrap_or("output.obj");
-
-    match fs::File::create(output_filename) {
-        Ok(file) => {
-            let mut writer = BufWriter::new(file);
-            if let Err(e) = writer.write_all(obj_content.as_bytes()) {
-                eprintln!("Error writing to object file '{}': {}", output_filename, e);
-                std::process::exit(1);
-            }
-            println!("Compilation successful! Output written to {}", output_filename);
-        }
-        Err(e) => {
-            eprintln!("Error creating object file '{}': {}", output_filename, e);
-            std::process::exit(1);
+    /// One line per phase in milliseconds, then the total
+    fn report(&self) -> String {
+        let mut report = String::from("--- Timing ---\n");
+        for (phase, elapsed) in &self.phases {
+            report.push_str(&format!("{}: {:.3} ms\n", phase, elapsed.as_secs_f64() * 1000.0));
         }
+        let total: Duration = self.phases.iter().map(|(_, elapsed)| *elapsed).sum();
+        report.push_str(&format!("Total: {:.3} ms\n", total.as_secs_f64() * 1000.0));
+        report
     }
-   
-    println!("\n--- Attempting to run VM on {} ---", output_filename);
-    let mut vm_instance = vm::VM::new();
-    match vm_instance.load_obj_file(output_filename) {
-        Ok(_) => {
-            if let Err(e) = vm_instance.run() {
-                eprintln!("VM runtime error: {}", e);
-            } else {
-                println!("VM execution finished successfully.");
+}
+
+/// Serialize the generated quadruples, constants and function table into the .obj text format
+fn build_obj_content(quad_gen: &QuadrupleGenerator, ast: &ast::Program, input_filename: &str) -> String {
+    quad_gen.to_obj_content(&ast.id, input_filename)
+}
+
+/// Rewrite the opcodes of the QUADRUPLES section as names (ADD, GOTO, ...).
+/// The VM accepts either form, so the result still loads and runs.
+fn symbolic_obj_content(obj_content: &str) -> String {
+    let mut in_quads = false;
+    let mut symbolic = String::new();
+    for line in obj_content.lines() {
+        if line == "QUADRUPLES:" {
+            in_quads = true;
+        } else if line.starts_with("END_") {
+            in_quads = false;
+        } else if in_quads {
+            if let Some((op, args)) = line.split_once(',') {
+                if let Ok(op) = op.parse::<i32>() {
+                    symbolic.push_str(&format!("{},{}\n", vm::opcode_name(op), args));
+                    continue;
+                }
             }
         }
-        Err(e) => {
-            eprintln!("Error loading object file ('{}') into VM: {}", output_filename, e);
-        }
+        symbolic.push_str(line);
+        symbolic.push('\n');
     }
-    
+    symbolic
 }
 
 
@@ -383,7 +643,8 @@ fn test_quadruple_generation() {
     // Create a simple program with arithmetic operations
     let program = r#"
     program example;
-    var A, B, C, D, E, F, G, H, I, J, K, L, R: int;
+    var A, B, C, D, E, F, G, H, I, J, K, L: int;
+    var R: bool;
     main {
        R = ((A + B) * C + D * E * F + K / H * J) + G * L + H + J > (A - C * D) / F;
        print(R);
@@ -507,7 +768,7 @@ fn test_function_call_quadruple_generation() {
     assert_eq!(quads_raw[0].operation, OpCode::GOTO, "Quad 0 should be GOTO main");
     let main_code_start_idx = quads_raw[0].result as usize;
 
-    let multiply_func_info = quad_gen.function_directory.as_ref().unwrap().get_function("multiply").unwrap();
+    let multiply_func_info = quad_gen.function_directory().unwrap().get_function("multiply").unwrap();
     let multiply_start_idx = multiply_func_info.start_quad_idx.unwrap() as usize;
 
     // Validate multiply function body quads
@@ -562,3 +823,3581 @@ fn test_function_call_quadruple_generation() {
 
     println!("\nFunction call quadruple generation test passed successfully!");
 }
+
+#[test]
+fn test_float_constant_deduplication_by_bits() {
+    let mut quad_gen = QuadrupleGenerator::new();
+
+    let zero_addr = quad_gen.get_or_create_float_constant(0.0);
+    let neg_zero_addr = quad_gen.get_or_create_float_constant(-0.0);
+    let value_addr = quad_gen.get_or_create_float_constant(2.5);
+    let value_again_addr = quad_gen.get_or_create_float_constant(2.5);
+    let nan_addr = quad_gen.get_or_create_float_constant(f64::NAN);
+    let nan_again_addr = quad_gen.get_or_create_float_constant(f64::NAN);
+
+    assert_ne!(zero_addr, neg_zero_addr, "0.0 and -0.0 should not share a constant address");
+    assert_eq!(value_addr, value_again_addr, "Repeated value should reuse its constant address");
+    assert_eq!(nan_addr, nan_again_addr, "NaN should only be interned once");
+    assert_eq!(quad_gen.get_float_constants().len(), 4, "Expected 0.0, -0.0, 2.5 and NaN as distinct constants");
+    println!("\nFloat constant deduplication test passed");
+}
+
+/// Compile a BabyDuck program into the text of its .obj file
+#[cfg(test)]
+fn compile_to_obj(program: &str) -> String {
+    let ast = babyduck::ProgramParser::new().parse(program).expect("Failed to parse program");
+    let function_directory = FunctionDirectory::from_program(&ast).expect("Failed to create function directory");
+
+    let mut quad_gen = QuadrupleGenerator::new();
+    quad_gen.set_function_directory(function_directory);
+    quad_gen.generate_for_program(&ast).expect("Quadruple generation failed");
+
+    build_obj_content(&quad_gen, &ast, "test.bd")
+}
+
+/// Compile a BabyDuck program and run it on a VM that captures its printed output
+#[cfg(test)]
+fn compile_and_run(program: &str) -> (Result<i32, String>, Vec<String>) {
+    let obj_content = compile_to_obj(program);
+    let mut vm_instance = vm::VM::new();
+    vm_instance.load_obj_str(&obj_content).expect("Failed to load object content");
+    vm_instance.capture_output();
+    let result = vm_instance.run();
+    (result, vm_instance.take_output())
+}
+
+#[test]
+fn test_exit_statement() {
+    let program = r#"
+    program exit_test;
+    var x: int;
+    main {
+        x = 1;
+        print(x);
+        exit(3);
+        print(x + 1);
+    }
+    end
+    "#;
+
+    let (result, output) = compile_and_run(program);
+    assert_eq!(result, Ok(3), "exit(3) should report exit code 3");
+    assert_eq!(output, vec!["1".to_string()], "No prints should run after exit");
+
+    // A program that runs to completion reports exit code 0
+    let (result, _) = compile_and_run("program p; var x: int; main { x = 1; } end");
+    assert_eq!(result, Ok(0));
+
+    // Non-int exit codes are rejected at compile time
+    let ast = babyduck::ProgramParser::new().parse("program p; var f: float; main { f = 1.5; exit(f); } end").unwrap();
+    let mut quad_gen = QuadrupleGenerator::new();
+    quad_gen.set_function_directory(FunctionDirectory::from_program(&ast).unwrap());
+    let gen_result = quad_gen.generate_for_program(&ast);
+    assert!(gen_result.is_err(), "Expected float exit code to be rejected");
+    println!("\nExit statement test passed");
+}
+
+#[test]
+fn test_scope_shadowing_rules() {
+    // A function local may shadow a global of the same name
+    let shadowing_program = r#"
+    program shadow_test;
+    var x: int;
+
+    void f() [
+        var x: int;
+        {
+            x = 5;
+        }
+    ];
+
+    main {
+        x = 1;
+        f();
+    }
+    end
+    "#;
+
+    let ast = babyduck::ProgramParser::new().parse(shadowing_program).unwrap();
+    let function_directory = FunctionDirectory::from_program(&ast);
+    assert!(function_directory.is_ok(), "Shadowing a global should be allowed: {:?}", function_directory.err());
+    let function_directory = function_directory.unwrap();
+
+    let global_x = function_directory.get_variable_address("global", "x").unwrap();
+    let local_x = function_directory.get_variable_address("f", "x").unwrap();
+    assert_ne!(global_x, local_x, "Local x should get its own address");
+    assert_eq!(function_directory.get_variable_address("main", "x"), Some(global_x), "main should see the global x");
+
+    let mut quad_gen = QuadrupleGenerator::new();
+    quad_gen.set_function_directory(function_directory);
+    assert!(quad_gen.generate_for_program(&ast).is_ok());
+
+    let f_start = quad_gen.function_directory().unwrap().get_function("f").unwrap().start_quad_idx.unwrap() as usize;
+    let quads = quad_gen.get_quadruples();
+    assert_eq!(quads[f_start].operation, OpCode::ASSIGN);
+    assert_eq!(quads[f_start].result, local_x, "Assignment inside f should resolve to the nearest (local) scope");
+
+    // Declaring the same name twice in the same scope is an error
+    let duplicate_global_program = r#"
+    program duplicate_test;
+    var x: int;
+    var x: float;
+    main {
+        x = 1;
+    }
+    end
+    "#;
+
+    let ast = babyduck::ProgramParser::new().parse(duplicate_global_program).unwrap();
+    match FunctionDirectory::from_program(&ast) {
+        Err(FunctionDirError::DuplicateVariable(var_name, scope)) => {
+            assert_eq!(var_name, "x");
+            assert_eq!(scope, "global");
+        }
+        other => panic!("Expected DuplicateVariable error for global x, got: {:?}", other),
+    }
+    println!("\nScope shadowing rules test passed");
+}
+
+#[test]
+fn test_print_in_base() {
+    let program = r#"
+    program base_test;
+    var x: int;
+    main {
+        x = 5;
+        print_hex(255);
+        print_bin(x);
+        print_hex(x * 2);
+    }
+    end
+    "#;
+
+    let (result, output) = compile_and_run(program);
+    assert_eq!(result, Ok(0));
+    assert_eq!(output, vec!["0xff".to_string(), "0b101".to_string(), "0xa".to_string()]);
+
+    // Only int expressions can be printed in another base
+    let ast = babyduck::ProgramParser::new().parse("program p; var f: float; main { f = 1.5; print_hex(f); } end").unwrap();
+    let mut quad_gen = QuadrupleGenerator::new();
+    quad_gen.set_function_directory(FunctionDirectory::from_program(&ast).unwrap());
+    assert!(quad_gen.generate_for_program(&ast).is_err(), "Expected print_hex of a float to be rejected");
+    println!("\nPrint in base test passed");
+}
+
+#[test]
+fn test_zero_init_mode() {
+    let program = r#"
+    program zero_init_test;
+    var x: int;
+    var y: float;
+    var flag: bool;
+    main {
+        print(x);
+        print(y);
+        print(flag);
+    }
+    end
+    "#;
+    let obj_content = compile_to_obj(program);
+
+    // Strict by default: reading a never-assigned global is an error
+    let mut strict_vm = vm::VM::new();
+    strict_vm.load_obj_str(&obj_content).unwrap();
+    strict_vm.capture_output();
+    assert!(strict_vm.run().is_err(), "Expected uninitialized read to fail in strict mode");
+
+    let mut zero_vm = vm::VM::new();
+    zero_vm.set_zero_init(true);
+    zero_vm.load_obj_str(&obj_content).unwrap();
+    zero_vm.capture_output();
+    assert_eq!(zero_vm.run(), Ok(0));
+    assert_eq!(zero_vm.take_output(), vec!["0".to_string(), "0".to_string(), "false".to_string()]);
+    println!("\nZero init mode test passed");
+}
+
+#[test]
+fn test_self_comparison_lint() {
+    let program = r#"
+    program lint_test;
+    var count, a, b: int;
+    main {
+        count = 1;
+        a = 2;
+        b = 3;
+        if (count == count) {
+            print(count);
+        }
+        if (a == b) {
+            print(a);
+        }
+        if (5 == 5) {
+            print(b);
+        }
+    }
+    end
+    "#;
+
+    let ast = babyduck::ProgramParser::new().parse(program).unwrap();
+    let mut quad_gen = QuadrupleGenerator::new();
+    quad_gen.set_function_directory(FunctionDirectory::from_program(&ast).unwrap());
+    assert!(quad_gen.generate_for_program(&ast).is_ok());
+
+    let warnings = quad_gen.take_warnings();
+    // `5 == 5` isn't a self comparison, only a constant condition
+    assert_eq!(warnings.len(), 2, "Expected exactly two warnings, got: {:?}", warnings);
+    assert!(warnings[0].contains("count"), "Warning should name the variable: {}", warnings[0]);
+    assert!(warnings[1].contains("always true"), "Expected the constant condition lint: {}", warnings[1]);
+    assert!(quad_gen.take_warnings().is_empty(), "Warnings should be drained after take_warnings");
+    println!("\nSelf comparison lint test passed");
+}
+
+#[test]
+fn test_read_multiple_values_from_one_line() {
+    let program = r#"
+    program read_test;
+    var a, b, c: int;
+    var f: float;
+    main {
+        read(a, b, c);
+        read(f);
+        print(a);
+        print(b);
+        print(c + f);
+    }
+    end
+    "#;
+
+    let ast = babyduck::ProgramParser::new().parse(program).unwrap();
+    let mut quad_gen = QuadrupleGenerator::new();
+    quad_gen.set_function_directory(FunctionDirectory::from_program(&ast).unwrap());
+    assert!(quad_gen.generate_for_program(&ast).is_ok());
+    let read_quads: Vec<_> = quad_gen.get_quadruples().iter().filter(|q| q.operation == OpCode::READ).collect();
+    assert_eq!(read_quads.len(), 4, "Expected one READ quad per target");
+    assert_eq!((read_quads[0].arg1, read_quads[0].arg2), (0, 3));
+    assert_eq!((read_quads[2].arg1, read_quads[2].arg2), (2, 3));
+
+    let mut vm_instance = vm::VM::new();
+    vm_instance.load_obj_str(&build_obj_content(&quad_gen, &ast, "read_test.bd")).unwrap();
+    vm_instance.set_stdin(Box::new(std::io::Cursor::new("10 20 30\n0.5\n")));
+    vm_instance.capture_output();
+    assert_eq!(vm_instance.run(), Ok(0));
+    assert_eq!(vm_instance.take_output(), vec!["10".to_string(), "20".to_string(), "30.5".to_string()]);
+
+    // A line with the wrong number of values is a runtime error
+    let mut short_input_vm = vm::VM::new();
+    short_input_vm.load_obj_str(&build_obj_content(&quad_gen, &ast, "read_test.bd")).unwrap();
+    short_input_vm.set_stdin(Box::new(std::io::Cursor::new("10 20\n")));
+    short_input_vm.capture_output();
+    assert!(short_input_vm.run().is_err());
+    println!("\nRead multiple values test passed");
+}
+
+#[test]
+fn test_execution_report() {
+    let program = r#"
+    program loop_report;
+    var i: int;
+    main {
+        i = 0;
+        while (i < 3) do {
+            print(i);
+            i = i + 1;
+        };
+    }
+    end
+    "#;
+
+    let mut vm_instance = vm::VM::new();
+    vm_instance.load_obj_str(&compile_to_obj(program)).unwrap();
+    vm_instance.capture_output();
+    assert_eq!(vm_instance.run(), Ok(0));
+
+    // GOTO main + init, 3 iterations of 6 quads, the failing check (LT, GOTOF) and HALT
+    let expected = vm::ExecutionReport {
+        instructions_executed: 2 + 3 * 6 + 2 + 1,
+        max_call_depth: 0,
+        print_count: 3,
+        peak_int_cells: 5, // i, constants 0/3/1 and one temp
+        peak_float_cells: 0,
+        peak_bool_cells: 1,
+    };
+    assert_eq!(vm_instance.execution_report(), expected);
+
+    // Counters start over after a reset, and the program can run again
+    vm_instance.reset();
+    assert_eq!(vm_instance.execution_report().instructions_executed, 0);
+    assert_eq!(vm_instance.run(), Ok(0));
+    assert_eq!(vm_instance.execution_report(), expected);
+    assert_eq!(vm_instance.take_output().len(), 6);
+
+    // A function's cells are released when it returns, but still count towards the peak
+    let program = r#"
+    program call_report;
+    var x: int;
+    void f(n: int) [
+        var t, u, v: int;
+        {
+            t = n;
+            u = t;
+            v = u;
+        }
+    ];
+    main {
+        x = 1;
+        f(x);
+    }
+    end
+    "#;
+    let mut vm_instance = vm::VM::new();
+    vm_instance.load_obj_str(&compile_to_obj(program)).unwrap();
+    assert_eq!(vm_instance.run(), Ok(0));
+    let report = vm_instance.execution_report();
+    assert_eq!(report.peak_int_cells, 6, "x, n, t, u, v and the constant 1: {:?}", report);
+    println!("\nExecution report test passed");
+}
+
+#[test]
+fn test_not_equal_operator() {
+    let program = r#"
+    program not_equal;
+    var x: int;
+    main {
+        x = 3;
+        if (x != 5) {
+            print(1);
+        } else {
+            print(0);
+        }
+    }
+    end
+    "#;
+
+    let ast = babyduck::ProgramParser::new().parse(program).unwrap();
+    match &ast.main_body[1] {
+        ast::Statement::Condition(cond) => assert!(
+            matches!(&cond.condition, ast::Expression::BinaryOp { operator: ast::Operator::NotEqual, .. }),
+            "Expected x != 5 to parse as a NotEqual operation, got {:?}", cond.condition
+        ),
+        other => panic!("Expected a condition statement, got {:?}", other),
+    }
+
+    let function_directory = FunctionDirectory::from_program(&ast).unwrap();
+    let x_addr = function_directory.get_variable_address("main", "x").unwrap();
+    let mut quad_gen = QuadrupleGenerator::new();
+    quad_gen.set_function_directory(function_directory);
+    assert!(quad_gen.generate_for_program(&ast).is_ok());
+    let neq_quad = quad_gen.get_quadruples().iter().find(|q| q.operation == OpCode::NEQ);
+    assert!(neq_quad.is_some_and(|q| q.arg1 == x_addr), "Expected a NEQ quad comparing x");
+
+    let (status, output) = compile_and_run(program);
+    assert_eq!(status, Ok(0));
+    assert_eq!(output, vec!["1".to_string()]);
+    println!("\nNot-equal operator test passed");
+}
+
+#[test]
+fn test_boolean_literal_assignment() {
+    let program = r#"
+    program bool_literals;
+    var x, y: bool;
+    main {
+        x = true;
+        y = false;
+        print(x);
+        print(y);
+    }
+    end
+    "#;
+
+    let ast = babyduck::ProgramParser::new().parse(program).unwrap();
+    let literals: Vec<_> = ast.main_body.iter().filter_map(|stmt| match stmt {
+        ast::Statement::Assignment(assign) => match assign.expression {
+            ast::Expression::BooleanLiteral(value) => Some((assign.id.as_str(), value)),
+            _ => None,
+        },
+        _ => None,
+    }).collect();
+    assert_eq!(literals, vec![("x", true), ("y", false)]);
+
+    let function_directory = FunctionDirectory::from_program(&ast).unwrap();
+    let x_addr = function_directory.get_variable_address("main", "x").unwrap();
+    let y_addr = function_directory.get_variable_address("main", "y").unwrap();
+    let mut quad_gen = QuadrupleGenerator::new();
+    quad_gen.set_function_directory(function_directory);
+    assert!(quad_gen.generate_for_program(&ast).is_ok());
+
+    // Boolean literals are assigned directly, with the value carried in arg2
+    let assigns: Vec<_> = quad_gen.get_quadruples().iter()
+        .filter(|q| q.operation == OpCode::ASSIGN)
+        .map(|q| (q.arg1, q.arg2, q.result))
+        .collect();
+    assert_eq!(assigns, vec![(-1, 1, x_addr), (-1, 0, y_addr)]);
+
+    let (status, output) = compile_and_run(program);
+    assert_eq!(status, Ok(0));
+    assert_eq!(output, vec!["true".to_string(), "false".to_string()]);
+    println!("\nBoolean literal assignment test passed");
+}
+
+#[test]
+fn test_bool_variables_use_bool_segment() {
+    let program = r#"
+    program bool_segment;
+    var flag: bool;
+    var count: int;
+    void check(done: bool) [
+        var seen: bool;
+        {
+            seen = done;
+        }
+    ];
+    main {
+        flag = true;
+    }
+    end
+    "#;
+
+    let ast = babyduck::ProgramParser::new().parse(program).unwrap();
+    assert!(matches!(ast.vars[0].var_type, ast::Type::Bool));
+
+    let function_directory = FunctionDirectory::from_program(&ast).unwrap();
+    let bool_segment = quadruples::MemoryAddresses::BOOL_START..quadruples::MemoryAddresses::CTE_INT_START;
+    for (scope, name) in [("main", "flag"), ("check", "done"), ("check", "seen")] {
+        let addr = function_directory.get_variable_address(scope, name).unwrap();
+        assert!(bool_segment.contains(&addr), "'{}' got address {} outside the bool segment", name, addr);
+        assert!(matches!(function_directory.get_variable_type(scope, name), Some(ast::Type::Bool)));
+    }
+    let count_addr = function_directory.get_variable_address("main", "count").unwrap();
+    assert!(!bool_segment.contains(&count_addr));
+    println!("\nBool segment allocation test passed");
+}
+
+#[test]
+fn test_repl_session() {
+    let mut session = repl::Repl::new();
+    session.vm_mut().capture_output();
+
+    assert_eq!(session.eval_line("var x: int;"), Ok(None));
+    assert_eq!(session.eval_line("x = 4;"), Ok(None));
+    // Declaring more variables later keeps the values already assigned
+    assert_eq!(session.eval_line("var y: float;"), Ok(None));
+    assert_eq!(session.eval_line("y = x * 2.5;"), Ok(None));
+    assert_eq!(session.eval_line("print(x); print(y);"), Ok(None));
+    assert!(session.eval_line("print(z);").is_err(), "Undeclared variables should be rejected");
+    assert_eq!(session.eval_line("while (x > 2) do { x = x - 1; print(x); };"), Ok(None));
+    assert_eq!(session.eval_line("exit(x);"), Ok(Some(2)));
+
+    assert_eq!(session.vm_mut().take_output(), vec!["4", "10", "3", "2"]);
+    println!("\nREPL session test passed");
+}
+
+#[test]
+fn test_emit_ast() {
+    let program = r#"
+    program ast_dump;
+    var x: int;
+    main {
+        x = 1;
+        while (x < 3) do {
+            print(x);
+            x = x + 1;
+        };
+    }
+    end
+    "#;
+
+    let dump = compiler::emit_ast(program).unwrap();
+    for expected in ["id: \"ast_dump\"", "Assignment(", "Cycle(", "Print(", "LessThan", "IntegerLiteral(\n"] {
+        assert!(dump.contains(expected), "AST dump is missing '{}':\n{}", expected, dump);
+    }
+    assert!(compiler::emit_ast("program broken; main {").is_err());
+    println!("\nAST dump test passed");
+}
+
+#[test]
+fn test_classify_address_boundaries() {
+    use vm::{classify_address, Segment};
+
+    let boundaries = [
+        (1000, 1999, Segment::Int),
+        (2000, 2999, Segment::Float),
+        (3000, 3999, Segment::Bool),
+        (4000, 4499, Segment::ConstInt),
+        (4500, 4799, Segment::ConstFloat),
+        (4800, 4999, Segment::ConstBool),
+        (5000, 5999, Segment::TempInt),
+        (6000, 6999, Segment::TempFloat),
+        (7000, 7999, Segment::TempBool),
+    ];
+    for (first, last, segment) in boundaries {
+        assert_eq!(classify_address(first), segment, "start of {:?}", segment);
+        assert_eq!(classify_address(last), segment, "end of {:?}", segment);
+        assert_ne!(classify_address(last + 1), segment, "address after {:?}", segment);
+    }
+
+    // Anything below the first segment or past the last one is unmapped
+    assert_eq!(classify_address(8000), Segment::Unmapped);
+    assert_eq!(classify_address(i32::MAX), Segment::Unmapped);
+    assert_eq!(classify_address(999), Segment::Unmapped);
+    assert_eq!(classify_address(-1), Segment::Unmapped);
+    println!("\nAddress classification test passed");
+}
+
+#[test]
+fn test_uncalled_function_warning() {
+    let program = r#"
+    program dead_code;
+    var x: int;
+    void used(a: int) [
+        {
+            print(a);
+        }
+    ];
+    void unused(b: int) [
+        {
+            print(b);
+        }
+    ];
+    main {
+        x = 1;
+        used(x);
+    }
+    end
+    "#;
+
+    let ast = babyduck::ProgramParser::new().parse(program).unwrap();
+    let mut quad_gen = QuadrupleGenerator::new();
+    quad_gen.set_function_directory(FunctionDirectory::from_program(&ast).unwrap());
+    assert!(quad_gen.generate_for_program(&ast).is_ok());
+
+    let warnings = quad_gen.take_warnings();
+    assert_eq!(warnings.len(), 1, "Expected exactly one warning, got {:?}", warnings);
+    assert!(warnings[0].contains("'unused'"), "Warning should name the uncalled function: {}", warnings[0]);
+    println!("\nUncalled function warning test passed");
+}
+
+#[test]
+fn test_fmod_builtin() {
+    let program = r#"
+    program fmod_test;
+    var r: float;
+    var n: int;
+    main {
+        r = fmod(5.5, 2.0);
+        print(r);
+        n = 7;
+        print(fmod(n, 2.5) + 1.0);
+    }
+    end
+    "#;
+
+    let (status, output) = compile_and_run(program);
+    assert_eq!(status, Ok(0));
+    assert_eq!(output, vec!["1.5".to_string(), "3".to_string()]);
+
+    let zero_divisor = r#"
+    program fmod_zero;
+    var x: float;
+    main {
+        x = 3.0;
+        print(fmod(x, 0.0));
+    }
+    end
+    "#;
+    let (status, output) = compile_and_run(zero_divisor);
+    assert!(status.is_err_and(|e| e.contains("Division by zero")), "Expected a division by zero error");
+    assert!(output.is_empty());
+
+    // Wrong arity is rejected at compile time
+    let ast = babyduck::ProgramParser::new().parse("program bad; var x: float; main { x = fmod(1.0); } end").unwrap();
+    let mut quad_gen = QuadrupleGenerator::new();
+    quad_gen.set_function_directory(FunctionDirectory::from_program(&ast).unwrap());
+    assert!(quad_gen.generate_for_program(&ast).is_err());
+    println!("\nfmod built-in test passed");
+}
+
+#[test]
+fn test_temporaries_stay_within_their_segments() {
+    let program = r#"
+    program example;
+    var A, B, C, D, E, F, G, H, I, J, K, L: int;
+    var R: bool;
+    var X: float;
+    main {
+       R = ((A + B) * C + D * E * F + K / H * J) + G * L + H + J > (A - C * D) / F;
+       X = A * 2.5 + B / 1.5 - C;
+       print(R);
+    }
+    end
+    "#;
+
+    let ast = babyduck::ProgramParser::new().parse(program).unwrap();
+    let mut quad_gen = QuadrupleGenerator::new();
+    quad_gen.set_function_directory(FunctionDirectory::from_program(&ast).unwrap());
+    assert!(quad_gen.generate_for_program(&ast).is_ok());
+
+    // A real program uses a handful of temporaries, far from the 1000 available per segment
+    let temp_ints = quad_gen.get_quadruples().iter()
+        .filter(|q| (quadruples::MemoryAddresses::TEMP_INT_START..quadruples::MemoryAddresses::TEMP_FLOAT_START).contains(&q.result))
+        .count();
+    assert!(temp_ints < 50, "Expected few int temporaries, got {}", temp_ints);
+    println!("\nTemporary segment usage test passed");
+}
+
+#[test]
+fn test_temporary_overflow_is_reported() {
+    // 1001 additions need one more int temporary than the segment holds
+    let long_sum = vec!["1"; 1002].join(" + ");
+    let program = format!("program overflow; var x: int; main {{ x = {}; }} end", long_sum);
+
+    let ast = babyduck::ProgramParser::new().parse(&program).unwrap();
+    let mut quad_gen = QuadrupleGenerator::new();
+    quad_gen.set_function_directory(FunctionDirectory::from_program(&ast).unwrap());
+    let error = quad_gen.generate_for_program(&ast).expect_err("Overflowing the temporaries should be a compile error");
+    assert!(error.contains("more Int temporaries than their memory segment holds"), "{}", error);
+    assert_eq!(quad_gen.compile_errors().len(), 1, "The overflow is reported once, not per extra temporary");
+}
+
+#[test]
+fn test_strict_mode_rejects_implicit_widening() {
+    let compile = |source: &str, strict: bool| {
+        let ast = babyduck::ProgramParser::new().parse(source).unwrap();
+        let mut quad_gen = QuadrupleGenerator::new();
+        quad_gen.set_function_directory(FunctionDirectory::from_program(&ast).unwrap());
+        quad_gen.set_strict(strict);
+        quad_gen.generate_for_program(&ast)
+    };
+    let implicit = "program widen; var f: float; main { f = 3; } end";
+    let explicit = "program widen; var f: float; main { f = float(3); } end";
+    let implicit_arg = r#"
+    program widen;
+    void show(f: float) [
+        {
+            print(f);
+        }
+    ];
+    main {
+        show(3);
+    }
+    end
+    "#;
+
+    assert!(compile(implicit, false).is_ok());
+    assert!(compile(explicit, false).is_ok());
+    assert!(compile(implicit_arg, false).is_ok());
+
+    let strict_result = compile(implicit, true);
+    assert!(strict_result.as_ref().is_err_and(|e| e.contains("strict mode")), "Got {:?}", strict_result);
+    assert!(compile(explicit, true).is_ok());
+    assert!(compile(implicit_arg, true).is_err());
+
+    let (status, output) = compile_and_run("program cast; var f: float; main { f = float(3) / 2; print(f); } end");
+    assert_eq!(status, Ok(0));
+    assert_eq!(output, vec!["1.5".to_string()]);
+    println!("\nStrict mode test passed");
+}
+
+#[test]
+fn test_bool_in_arithmetic_is_rejected() {
+    // A stored comparison result is a bool, and bools don't take part in arithmetic
+    let program = r#"
+    program bool_math;
+    var x, c: int;
+    var b: bool;
+    main {
+        x = 7;
+        b = x > 5;
+        c = b + 1;
+    }
+    end
+    "#;
+    let ast = babyduck::ProgramParser::new().parse(program).unwrap();
+    let mut quad_gen = QuadrupleGenerator::new();
+    quad_gen.set_function_directory(FunctionDirectory::from_program(&ast).unwrap());
+    let result = quad_gen.generate_for_program(&ast);
+    assert!(result.as_ref().is_err_and(|e| e.contains("Bool") && e.contains("Plus")), "Got {:?}", result);
+
+    // Using the stored result as a condition is still fine
+    let (status, output) = compile_and_run(r#"
+    program bool_reuse;
+    var x: int;
+    var b: bool;
+    main {
+        x = 7;
+        b = x > 5;
+        if (b) {
+            print(x + 1);
+        }
+    }
+    end
+    "#);
+    assert_eq!(status, Ok(0));
+    assert_eq!(output, vec!["8".to_string()]);
+
+    // The VM applies the same rule to hand-written object code
+    let mut vm_instance = vm::VM::new();
+    vm_instance.load_obj_str("CONSTANTS_INT:\n1,4000\nEND_CONSTANTS_INT\nQUADRUPLES:\n1,-1,1,3000\n4,3000,4000,5000\n50,-1,-1,-1\nEND_QUADRUPLES\n").unwrap();
+    assert!(vm_instance.run().is_err_and(|e| e.contains("Type mismatch")));
+    println!("\nBool arithmetic test passed");
+}
+
+#[test]
+fn test_disassemble_obj() {
+    let program = r#"
+    program disasm;
+    var x: int;
+    void show(v: int) [
+        {
+            print(v);
+        }
+    ];
+    main {
+        x = 2;
+        show(x * 3);
+        print(1.5);
+    }
+    end
+    "#;
+
+    let mut vm_instance = vm::VM::new();
+    vm_instance.load_obj_str(&compile_to_obj(program)).unwrap();
+    let listing = vm_instance.disassemble();
+
+    // show's body is quads 1..3, so main starts at quad 3
+    assert!(listing.contains("   0: (GOTO, -1, -1, 3)"), "Missing GOTO main:\n{}", listing);
+    assert!(listing.contains("(HALT, -1, -1, -1)"), "Missing HALT:\n{}", listing);
+    assert!(listing.contains("  4000 = 2\n"), "Missing int constant:\n{}", listing);
+    assert!(listing.contains("  4500 = 1.5\n"), "Missing float constant:\n{}", listing);
+    assert!(listing.contains("show @ 1 (1 params: [1001])"), "Missing function entry:\n{}", listing);
+    println!("\nDisassembler test passed");
+}
+
+#[test]
+fn test_bool_function_arguments() {
+    let program = r#"
+    program bool_args;
+    var x: int;
+    var ok: bool;
+    void check(flag: bool) [
+        {
+            if (flag) {
+                print(1);
+            } else {
+                print(0);
+            }
+        }
+    ];
+    main {
+        x = 7;
+        check(x > 5);
+        x = 2;
+        check(x > 5);
+        ok = true;
+        check(ok);
+        check(false);
+    }
+    end
+    "#;
+
+    let ast = babyduck::ProgramParser::new().parse(program).unwrap();
+    let function_directory = FunctionDirectory::from_program(&ast).unwrap();
+    let flag_addr = function_directory.get_variable_address("check", "flag").unwrap();
+    assert!((quadruples::MemoryAddresses::BOOL_START..quadruples::MemoryAddresses::CTE_INT_START).contains(&flag_addr));
+    assert!(function_directory.is_valid_assignment(&ast::Type::Bool, &ast::Type::Bool));
+
+    let (status, output) = compile_and_run(program);
+    assert_eq!(status, Ok(0));
+    assert_eq!(output, vec!["1".to_string(), "0".to_string(), "1".to_string(), "0".to_string()]);
+    println!("\nBool function argument test passed");
+}
+
+#[test]
+fn test_tail_call_optimization() {
+    let program = r#"
+    program tail_calls;
+    void count(n: int, acc: int) [
+        {
+            if (n > 0) {
+                count(n - 1, acc + 2);
+            } else {
+                print(acc);
+            }
+        }
+    ];
+    void swap(a: int, b: int, n: int) [
+        {
+            if (n > 0) {
+                swap(b, a, n - 1);
+            } else {
+                print(a);
+                print(b);
+            }
+        }
+    ];
+    main {
+        count(20000, 0);
+        swap(1, 2, 3);
+    }
+    end
+    "#;
+
+    let run = |optimize: bool| {
+        let ast = babyduck::ProgramParser::new().parse(program).unwrap();
+        let mut quad_gen = QuadrupleGenerator::new();
+        quad_gen.set_function_directory(FunctionDirectory::from_program(&ast).unwrap());
+        if optimize {
+            quad_gen.optimize_tail_calls();
+        }
+        assert!(quad_gen.generate_for_program(&ast).is_ok());
+        let mut vm_instance = vm::VM::new();
+        vm_instance.load_obj_str(&build_obj_content(&quad_gen, &ast, "tail_calls.bd")).unwrap();
+        vm_instance.capture_output();
+        (vm_instance.run(), vm_instance.take_output(), vm_instance.execution_report().max_call_depth)
+    };
+
+    // Without the optimization every recursive call keeps a return address
+    let (status, _, _) = run(false);
+    assert!(status.is_err_and(|e| e.contains("Stack overflow")));
+
+    let (status, output, max_depth) = run(true);
+    assert_eq!(status, Ok(0));
+    assert_eq!(output, vec!["40000".to_string(), "2".to_string(), "1".to_string()]);
+    assert_eq!(max_depth, 1, "Only the calls from main should use the call stack");
+    println!("\nTail call optimization test passed");
+}
+
+#[test]
+fn test_max_memory_guard() {
+    // The last temporary bool address needs a thousand bool cells
+    let hungry_obj = "QUADRUPLES:\n1,-1,1,7999\n50,-1,-1,-1\nEND_QUADRUPLES\n";
+    let mut vm_instance = vm::VM::new();
+    vm_instance.set_max_memory(100);
+    let result = vm_instance.load_obj_str(hungry_obj);
+    assert!(result.is_err_and(|e| e.contains("Memory limit exceeded")));
+
+    // Ordinary programs fit comfortably
+    let mut vm_instance = vm::VM::new();
+    vm_instance.set_max_memory(1_000_000);
+    vm_instance.load_obj_str(&compile_to_obj("program small; var x: int; main { x = 1; print(x); } end")).unwrap();
+    vm_instance.capture_output();
+    assert_eq!(vm_instance.run(), Ok(0));
+    println!("\nMax memory guard test passed");
+}
+
+#[test]
+fn test_section_labels() {
+    let program = r#"
+    program labels;
+    var i: int;
+    main {
+        section("Results:");
+        i = 1;
+        while (i < 3) do {
+            print(i);
+            i = i + 1;
+        };
+        section("Done, with commas");
+        section("Results:");
+    }
+    end
+    "#;
+
+    let (status, output) = compile_and_run(program);
+    assert_eq!(status, Ok(0));
+    assert_eq!(output, vec!["Results:", "1", "2", "Done, with commas", "Results:"]);
+
+    // Repeated labels share one string constant
+    let obj = compile_to_obj(program);
+    let strings: Vec<&str> = obj.lines()
+        .skip_while(|line| *line != "CONSTANTS_STRING:")
+        .skip(1)
+        .take_while(|line| *line != "END_CONSTANTS_STRING")
+        .collect();
+    assert_eq!(strings, vec!["0,Results:", "1,Done, with commas"]);
+    println!("\nSection label test passed");
+}
+
+#[test]
+fn test_function_return_type_designator() {
+    let program = r#"
+    program returns;
+    void show(x: int) [
+        {
+            print(x);
+        }
+    ];
+    int twice(x: int) [
+        {
+            print(x * 2);
+        }
+    ];
+    float half(x: float) [
+        {
+            print(x / 2.0);
+        }
+    ];
+    main {
+        show(1);
+        twice(2);
+        half(3.0);
+    }
+    end
+    "#;
+
+    let ast = babyduck::ProgramParser::new().parse(program).unwrap();
+    assert!(ast.funcs[0].return_type.is_none());
+    assert!(matches!(ast.funcs[1].return_type, Some(ast::Type::Int)));
+    assert!(matches!(ast.funcs[2].return_type, Some(ast::Type::Float)));
+
+    let function_directory = FunctionDirectory::from_program(&ast).unwrap();
+    assert!(function_directory.get_function("show").unwrap().return_type.is_none());
+    assert!(matches!(function_directory.get_function("twice").unwrap().return_type, Some(ast::Type::Int)));
+    assert!(matches!(function_directory.get_function("half").unwrap().return_type, Some(ast::Type::Float)));
+
+    // A function needs either void or a type in front of its name
+    assert!(babyduck::ProgramParser::new().parse("program p; f() [ { } ]; main { } end").is_err());
+    println!("\nFunction return type designator test passed");
+}
+
+#[test]
+fn test_vm_handles_every_generator_opcode() {
+    // A lone quad with dummy operands may fail for other reasons, but never as an unknown opcode
+    for &op in OpCode::ALL {
+        let mut vm_instance = vm::VM::new();
+        vm_instance.capture_output();
+        vm_instance.load_obj_str(&format!("QUADRUPLES:\n{},-1,-1,-1\nEND_QUADRUPLES\n", op)).unwrap();
+        if let Err(e) = vm_instance.run() {
+            assert!(!e.contains("Unknown OpCode"), "The VM has no handler for generator opcode {}: {}", op, e);
+        }
+    }
+
+    // Sanity check that the probe does detect a missing handler
+    let mut vm_instance = vm::VM::new();
+    vm_instance.load_obj_str("QUADRUPLES:\n99,-1,-1,-1\nEND_QUADRUPLES\n").unwrap();
+    assert!(vm_instance.run().is_err_and(|e| e.contains("Unknown OpCode")));
+    println!("\nOpcode coverage test passed");
+}
+
+#[test]
+fn test_mid_body_variable_declaration() {
+    let program = r#"
+    program late_locals;
+    var x: int;
+    void twice(n: int) [
+        {
+            print(n);
+            var doubled: int;
+            doubled = n * 2;
+            print(doubled);
+        }
+    ];
+    main {
+        x = 5;
+        var y, z: float;
+        y = x / 2.0;
+        twice(x);
+        print(y);
+    }
+    end
+    "#;
+
+    let (status, output) = compile_and_run(program);
+    assert_eq!(status, Ok(0));
+    assert_eq!(output, vec!["5", "10", "2.5"]);
+
+    let generate = |source: &str| {
+        let ast = babyduck::ProgramParser::new().parse(source).unwrap();
+        let mut quad_gen = QuadrupleGenerator::new();
+        quad_gen.set_function_directory(FunctionDirectory::from_program(&ast).unwrap());
+        quad_gen.generate_for_program(&ast)
+    };
+
+    // Redeclaring a preamble local or a parameter is still a duplicate
+    let duplicate_local = "program dup; void f(n: int) [ var a: int; { a = n; var a: float; } ]; main { f(1); } end";
+    let result = generate(duplicate_local);
+    assert!(result.as_ref().is_err_and(|e| e.contains("Duplicate variable 'a'")), "Got {:?}", result);
+    let duplicate_param = "program dup; void f(n: int) [ { var n: int; } ]; main { f(1); } end";
+    assert!(generate(duplicate_param).is_err());
+
+    // The variable only exists from its declaration on
+    let use_before = "program early; main { y = 1; var y: int; } end";
+    assert!(generate(use_before).is_err());
+    println!("\nMid-body declaration test passed");
+}
+
+#[test]
+fn test_print_callback_receives_typed_values() {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use compiler::Value;
+
+    let program = r#"
+    program fact;
+    var n, acc: int;
+    main {
+        section("factorial");
+        n = 5;
+        acc = 1;
+        while (n > 1) do {
+            acc = acc * n;
+            n = n - 1;
+        };
+        print(acc);
+        print(acc / 48.0);
+    }
+    end
+    "#;
+
+    let obj_content = compile_to_obj(program);
+    let mut vm_instance = vm::VM::new();
+    vm_instance.load_obj_str(&obj_content).expect("Failed to load object content");
+    let received = Rc::new(RefCell::new(Vec::new()));
+    let sink = Rc::clone(&received);
+    vm_instance.set_print_callback(move |value| sink.borrow_mut().push(value));
+
+    assert_eq!(vm_instance.run(), Ok(0));
+    assert_eq!(*received.borrow(), vec![
+        Value::String("factorial".to_string()),
+        Value::Int(120),
+        Value::Float(2.5),
+    ]);
+    println!("\nPrint callback test passed");
+}
+
+#[test]
+fn test_global_shadowing_lint() {
+    let program = r#"
+    program shadows;
+    var x, y: int;
+    void f(y: int) [
+        var x: float;
+        {
+            x = y * 1.5;
+            print(x);
+        }
+    ];
+    main {
+        x = 2;
+        f(x);
+    }
+    end
+    "#;
+
+    let ast = babyduck::ProgramParser::new().parse(program).unwrap();
+    let mut directory = FunctionDirectory::from_program(&ast).expect("Shadowing a global is allowed");
+    let warnings = directory.take_warnings();
+    assert_eq!(warnings.len(), 2, "Expected one lint per shadowing name: {:?}", warnings);
+    assert!(warnings.iter().any(|w| w.contains("Local variable 'x'") && w.contains("global variable 'x'")), "{:?}", warnings);
+    assert!(warnings.iter().any(|w| w.contains("Parameter 'y'") && w.contains("function 'f'")), "{:?}", warnings);
+
+    // The lint is non-fatal; the program still compiles and runs
+    let (status, output) = compile_and_run(program);
+    assert_eq!(status, Ok(0));
+    assert_eq!(output, vec!["3"]);
+
+    // A local declared partway through the body is linted like one in the preamble
+    let mid_body = r#"
+    program shadows;
+    var x: int;
+    void g(n: int) [
+        {
+            print(n);
+            var x: int;
+            x = n + 1;
+            print(x);
+        }
+    ];
+    main {
+        x = 2;
+        g(x);
+    }
+    end
+    "#;
+    let compiled = compiler::compile_source(mid_body).expect("Shadowing a global is allowed");
+    assert_eq!(compiled.warnings, vec!["Local variable 'x' in function 'g' shadows global variable 'x'".to_string()]);
+    let (status, output) = compile_and_run(mid_body);
+    assert_eq!(status, Ok(0));
+    assert_eq!(output, vec!["2", "3"]);
+    println!("\nShadowing lint test passed");
+}
+
+#[test]
+fn test_optimization_levels() {
+    let program = r#"
+    program opt_levels;
+    var i, total: int;
+    var ratio: float;
+    var done: bool;
+    void sum_to(n: int, acc: int) [
+        {
+            if (n > 0) {
+                sum_to(n - 1, acc + n);
+            } else {
+                print(acc);
+            }
+        }
+    ];
+    main {
+        i = 0;
+        total = 2 * 3 + 4;
+        ratio = 1 / 4.0 + 0.5;
+        while (i < 3) do {
+            total = total + i * (10 - 8);
+            i = i + 1;
+        };
+        done = total > 12;
+        print(total);
+        print(ratio);
+        print(done);
+        sum_to(4, 0);
+        print(7 / 0.0 > 1);
+    }
+    end
+    "#;
+
+    let run = |level: u8| {
+        let ast = babyduck::ProgramParser::new().parse(program).unwrap();
+        let mut quad_gen = QuadrupleGenerator::new();
+        quad_gen.set_function_directory(FunctionDirectory::from_program(&ast).unwrap());
+        quad_gen.set_optimization_level(level);
+        assert!(quad_gen.generate_for_program(&ast).is_ok());
+        let mut vm_instance = vm::VM::new();
+        vm_instance.load_obj_str(&build_obj_content(&quad_gen, &ast, "opt_levels.bd")).unwrap();
+        vm_instance.capture_output();
+        let writes_variable_directly = quad_gen.get_quadruples().iter().any(|quad| {
+            quad.operation == OpCode::ADD && quad.result < quadruples::MemoryAddresses::CTE_INT_START
+        });
+        assert_eq!(writes_variable_directly, level >= 2, "Only the peephole pass drops the temporary in `i = i + 1`");
+        (vm_instance.run(), vm_instance.take_output(), quad_gen.get_quadruples().len())
+    };
+
+    let (status_o0, output_o0, quads_o0) = run(0);
+    let (status_o1, output_o1, quads_o1) = run(1);
+    let (status_o2, output_o2, quads_o2) = run(2);
+
+    // Division by a constant zero isn't folded, so the runtime error is kept
+    assert!(status_o0.as_ref().is_err_and(|e| e.contains("Division by zero")), "Got {:?}", status_o0);
+    assert_eq!(status_o1, status_o0);
+    assert_eq!(status_o2, status_o0);
+    assert_eq!(output_o0, vec!["16", "0.75", "true", "10"]);
+    assert_eq!(output_o1, output_o0);
+    assert_eq!(output_o2, output_o0);
+    assert!(quads_o1 < quads_o0, "-O1 should fold constants: {} vs {}", quads_o1, quads_o0);
+    assert!(quads_o2 < quads_o1, "-O2 should remove temporary copies: {} vs {}", quads_o2, quads_o1);
+    println!("\nOptimization level test passed");
+}
+
+#[test]
+fn test_clear_statement() {
+    let program = r#"
+    program clearing;
+    var x: int;
+    var f: float;
+    var b: bool;
+    main {
+        x = 5;
+        f = 2.5;
+        b = true;
+        print(x);
+        clear(x);
+        clear(f);
+        clear(b);
+        print(x);
+        print(f);
+        print(b);
+    }
+    end
+    "#;
+
+    let (status, output) = compile_and_run(program);
+    assert_eq!(status, Ok(0));
+    assert_eq!(output, vec!["5", "0", "0", "false"]);
+
+    // Only a declared variable can be cleared
+    assert!(babyduck::ProgramParser::new().parse("program p; main { clear(3); } end").is_err());
+    let ast = babyduck::ProgramParser::new().parse("program p; main { clear(y); } end").unwrap();
+    let mut quad_gen = QuadrupleGenerator::new();
+    quad_gen.set_function_directory(FunctionDirectory::from_program(&ast).unwrap());
+    assert!(quad_gen.generate_for_program(&ast).is_err_and(|e| e.contains("'y'")));
+    println!("\nClear statement test passed");
+}
+
+#[test]
+fn test_while_without_do() {
+    let with_do = "program loops; var i: int; main { i = 0; while (i < 3) do { print(i); i = i + 1; }; } end";
+    let without_do = "program loops; var i: int; main { i = 0; while (i < 3) { print(i); i = i + 1; }; } end";
+
+    assert_eq!(compile_to_obj(with_do), compile_to_obj(without_do));
+    let (status, output) = compile_and_run(without_do);
+    assert_eq!(status, Ok(0));
+    assert_eq!(output, vec!["0", "1", "2"]);
+    println!("\nOptional do test passed");
+}
+
+#[test]
+fn test_call_to_later_function_is_a_compile_error() {
+    let program = r#"
+    program forward;
+    void first(n: int) [
+        {
+            second(n);
+        }
+    ];
+    void second(n: int) [
+        {
+            print(n);
+        }
+    ];
+    main {
+        first(1);
+    }
+    end
+    "#;
+
+    let ast = babyduck::ProgramParser::new().parse(program).unwrap();
+    let mut quad_gen = QuadrupleGenerator::new();
+    quad_gen.set_function_directory(FunctionDirectory::from_program(&ast).unwrap());
+    let result = quad_gen.generate_for_program(&ast);
+    assert!(result.as_ref().is_err_and(|e| e.contains("'second' is called before its code is generated")), "Got {:?}", result);
+    assert!(
+        !quad_gen.get_quadruples().iter().any(|quad| (quad.operation == OpCode::ERA || quad.operation == OpCode::GOSUB) && quad.arg1 < 0),
+        "No call should target an unset start index"
+    );
+    println!("\nUnset call target test passed");
+}
+
+#[test]
+fn test_chained_bool_comparisons_use_real_temporaries() {
+    let program = r#"
+    program chained;
+    var a, b, c: bool;
+    main {
+        a = true;
+        b = false;
+        c = (a == true) == (b == false);
+        print(c);
+        c = (a == false) == (b == false);
+        print(c);
+        if ((a != b) == true) {
+            print(1);
+        }
+    }
+    end
+    "#;
+
+    let ast = babyduck::ProgramParser::new().parse(program).unwrap();
+    let mut quad_gen = QuadrupleGenerator::new();
+    quad_gen.set_function_directory(FunctionDirectory::from_program(&ast).unwrap());
+    assert!(quad_gen.generate_for_program(&ast).is_ok());
+
+    // Every comparison reads two real operands and writes a fresh bool temporary
+    let comparisons: Vec<_> = quad_gen.get_quadruples().iter()
+        .filter(|quad| matches!(quad.operation, OpCode::EQ | OpCode::NEQ))
+        .collect();
+    assert_eq!(comparisons.len(), 8);
+    for quad in comparisons {
+        assert!(quad.arg1 >= 0 && quad.arg2 >= 0, "No direct-value markers: {:?}", quad);
+        assert!(quad.result >= quadruples::MemoryAddresses::TEMP_BOOL_START, "Result must be a bool temporary: {:?}", quad);
+        assert_eq!(quad_gen.get_bool_constant_value(quad.result), None, "Result must not alias a literal: {:?}", quad);
+    }
+
+    let (status, output) = compile_and_run(program);
+    assert_eq!(status, Ok(0));
+    assert_eq!(output, vec!["true", "false", "1"]);
+    println!("\nChained bool comparison test passed");
+}
+
+#[test]
+fn test_merge_function_directories() {
+    let directory_for = |source: &str| {
+        let ast = babyduck::ProgramParser::new().parse(source).unwrap();
+        FunctionDirectory::from_program(&ast).unwrap()
+    };
+    let app = "program app; var count: int; var ratio: float; void helper(n: int) [ { print(n); } ]; main { helper(count); } end";
+    let lib = "program lib; var total: int; void scale(x: float, k: int) [ var tmp: int; { print(x); } ]; main { } end";
+    let clashing_function = "program other; void helper(m: int) [ { print(m); } ]; main { } end";
+    let clashing_global = "program other; var count: float; main { } end";
+
+    let mut merged = directory_for(app);
+    merged.merge(directory_for(lib)).expect("Files without shared names should merge");
+    assert!(merged.function_exists("helper") && merged.function_exists("scale"));
+    assert!(!merged.function_exists("lib"), "Only the first file's program entry is kept");
+    assert_eq!(merged.get_variable_type("scale", "total").map(|t| format!("{:?}", t)), Some("Int".to_string()));
+
+    // Every variable still has its own address after the merge
+    let mut addresses: Vec<i32> = merged.get_all_functions().values()
+        .flat_map(|info| {
+            info.local_variables.values().map(|var| var.address)
+                .chain(info.parameters.iter().map(|(_, _, address)| *address))
+                .collect::<Vec<_>>()
+        })
+        .collect();
+    let total = addresses.len();
+    addresses.sort();
+    addresses.dedup();
+    assert_eq!(addresses.len(), total, "Merged variables must not share addresses");
+
+    let mut clash = directory_for(app);
+    assert!(matches!(clash.merge(directory_for(clashing_function)), Err(FunctionDirError::DuplicateFunction(name)) if name == "helper"));
+    let mut clash = directory_for(app);
+    assert!(matches!(
+        clash.merge(directory_for(clashing_global)),
+        Err(FunctionDirError::DuplicateVariable(name, scope)) if name == "count" && scope == "global"
+    ));
+    println!("\nDirectory merge test passed");
+}
+
+#[test]
+fn test_call_function_host_api() {
+    use compiler::Value;
+
+    let program = r#"
+    program host_calls;
+    var total: int;
+    int add(a: int, b: int) [
+        {
+            return a + b;
+        }
+    ];
+    float half(x: int) [
+        {
+            return x / 2.0;
+        }
+    ];
+    void show(n: int) [
+        {
+            print(n);
+        }
+    ];
+    main {
+        total = add(1, add(2, 3)) * 2;
+        show(total);
+    }
+    end
+    "#;
+
+    let mut vm_instance = vm::VM::new();
+    vm_instance.load_obj_str(&compile_to_obj(program)).unwrap();
+    vm_instance.capture_output();
+
+    assert_eq!(vm_instance.call_function("add", &[Value::Int(2), Value::Int(3)]), Ok(Some(Value::Int(5))));
+    assert_eq!(vm_instance.call_function("half", &[Value::Int(5)]), Ok(Some(Value::Float(2.5))));
+    assert_eq!(vm_instance.call_function("show", &[Value::Int(7)]), Ok(None));
+    assert_eq!(vm_instance.take_output(), vec!["7"]);
+    assert!(vm_instance.call_function("add", &[Value::Int(1)]).is_err());
+    assert!(vm_instance.call_function("add", &[Value::Int(1), Value::String("2".to_string())]).is_err());
+    assert!(vm_instance.call_function("missing", &[]).is_err());
+
+    // Direct calls leave the program itself runnable, and calls work in expressions too
+    assert_eq!(vm_instance.run(), Ok(0));
+    assert_eq!(vm_instance.take_output(), vec!["12"]);
+
+    // A void function has no value to use
+    let void_value = "program p; var x: int; void f() [ { print(1); } ]; main { x = f() + 1; } end";
+    let ast = babyduck::ProgramParser::new().parse(void_value).unwrap();
+    let mut quad_gen = QuadrupleGenerator::new();
+    quad_gen.set_function_directory(FunctionDirectory::from_program(&ast).unwrap());
+    assert!(quad_gen.generate_for_program(&ast).is_err_and(|e| e.contains("void")));
+    println!("\nHost function call test passed");
+}
+
+#[test]
+fn test_empty_program() {
+    let program = "program p; var x: int; main { } end";
+    let ast = babyduck::ProgramParser::new().parse(program).unwrap();
+    assert!(ast.funcs.is_empty() && ast.main_body.is_empty());
+
+    let mut quad_gen = QuadrupleGenerator::new();
+    quad_gen.set_function_directory(FunctionDirectory::from_program(&ast).unwrap());
+    assert!(quad_gen.generate_for_program(&ast).is_ok());
+    let quads: Vec<(i32, i32)> = quad_gen.get_quadruples().iter().map(|quad| (quad.operation, quad.result)).collect();
+    assert_eq!(quads, vec![(OpCode::GOTO, 1), (OpCode::HALT, -1)], "GOTO main must point at main's HALT");
+
+    let (status, output) = compile_and_run(program);
+    assert_eq!(status, Ok(0));
+    assert!(output.is_empty());
+    println!("\nEmpty program test passed");
+}
+
+#[test]
+fn test_constant_condition_lint() {
+    let warnings_for = |source: &str| {
+        let ast = babyduck::ProgramParser::new().parse(source).unwrap();
+        let mut quad_gen = QuadrupleGenerator::new();
+        quad_gen.set_function_directory(FunctionDirectory::from_program(&ast).unwrap());
+        assert!(quad_gen.generate_for_program(&ast).is_ok());
+        quad_gen.take_warnings()
+    };
+
+    let warnings = warnings_for("program p; var x: int; main { if (2 > 5) { x = 1; } } end");
+    assert_eq!(warnings.len(), 1, "{:?}", warnings);
+    assert!(warnings[0].contains("'if' is always false"), "{}", warnings[0]);
+
+    let warnings = warnings_for("program p; var x: int; main { if (1 + 1 == 2.0) { x = 1; } else { x = 2; } } end");
+    assert!(warnings.iter().any(|w| w.contains("always true") && w.contains("else branch is never taken")), "{:?}", warnings);
+
+    let warnings = warnings_for("program p; var x: int; main { x = 0; while (true) do { x = x + 1; exit(0); }; } end");
+    assert!(warnings.iter().any(|w| w.contains("'while' is always true") && w.ends_with("exit or return")), "{:?}", warnings);
+
+    // Conditions that read a variable aren't constant
+    assert!(warnings_for("program p; var x: int; main { x = 1; if (x > 5) { x = 2; } } end").is_empty());
+    println!("\nConstant condition lint test passed");
+}
+
+#[test]
+fn test_provide_input_values() {
+    use compiler::vm::{VMValue, VM};
+    use compiler::Value;
+
+    let program = r#"
+    program typed_io;
+    var n: int;
+    var f: float;
+    var flag: bool;
+    main {
+        read(n, f);
+        read(flag);
+        print(n * 2);
+        print(f);
+        print(flag);
+    }
+    end
+    "#;
+
+    let run = |inputs: Vec<Value>| {
+        let mut vm_instance = VM::new();
+        vm_instance.load_obj_str(&compile_to_obj(program)).unwrap();
+        vm_instance.capture_output();
+        vm_instance.provide_input(inputs);
+        (vm_instance.run(), vm_instance.take_output())
+    };
+
+    let (status, output) = run(vec![Value::Int(21), Value::from(1.5), true.into()]);
+    assert_eq!(status, Ok(0));
+    assert_eq!(output, vec!["42", "1.5", "true"]);
+
+    // An int may be read into a float, but the values must otherwise match the targets
+    let (status, output) = run(vec![3.into(), 4.into(), false.into()]);
+    assert_eq!(status, Ok(0));
+    assert_eq!(output, vec!["6", "4", "false"]);
+    let (status, _) = run(vec![Value::Float(2.5), Value::Float(1.0), Value::Bool(true)]);
+    assert!(status.is_err_and(|e| e.contains("Cannot read")));
+    let (status, _) = run(vec![Value::Int(1)]);
+    assert!(status.is_err_and(|e| e.contains("No provided input value left")));
+    let (status, _) = run(vec![Value::String("21".to_string())]);
+    assert!(status.is_err_and(|e| e.contains("Cannot read")));
+
+    // Results from the VM convert to the public type as well
+    assert_eq!(Value::from(VMValue::Int(5)), Value::Int(5));
+    println!("\nProvided input test passed");
+}
+
+#[test]
+fn test_missing_return_detection() {
+    let generate = |function: &str| {
+        let source = format!("program returns; var r: int; {} main {{ r = sign(3); print(r); }} end", function);
+        let ast = babyduck::ProgramParser::new().parse(&source).unwrap();
+        let mut quad_gen = QuadrupleGenerator::new();
+        quad_gen.set_function_directory(FunctionDirectory::from_program(&ast).unwrap());
+        let result = quad_gen.generate_for_program(&ast);
+        (result, quad_gen.compile_errors().to_vec())
+    };
+
+    let both_branches = "int sign(n: int) [ { if (n > 0) { return 1; } else { return 0 - 1; } } ];";
+    let (result, errors) = generate(both_branches);
+    assert!(result.is_ok(), "Got {:?}", errors);
+
+    let missing_else = "int sign(n: int) [ { if (n > 0) { return 1; } } ];";
+    let (result, errors) = generate(missing_else);
+    assert!(result.is_err_and(|e| e.contains("'sign' can reach its end without returning")));
+    assert_eq!(errors, vec![quadruples::CompileError::MissingReturn { function: "sign".to_string() }]);
+
+    // A return after the if covers the fall-through path
+    let trailing_return = "int sign(n: int) [ { if (n > 0) { return 1; } return 0; } ];";
+    assert!(generate(trailing_return).0.is_ok());
+    println!("\nMissing return test passed");
+}
+
+#[test]
+fn test_void_call_in_expression_is_rejected() {
+    let generate = |main_body: &str| {
+        let source = format!(
+            "program values; var x: int; void greet() [ {{ print(1); }} ]; int one() [ {{ return 1; }} ]; main {{ {} }} end",
+            main_body
+        );
+        let ast = babyduck::ProgramParser::new().parse(&source).unwrap();
+        let mut quad_gen = QuadrupleGenerator::new();
+        quad_gen.set_function_directory(FunctionDirectory::from_program(&ast).unwrap());
+        let result = quad_gen.generate_for_program(&ast);
+        (result, quad_gen.compile_errors().to_vec())
+    };
+    let void_error = vec![quadruples::CompileError::VoidInExpression { function: "greet".to_string() }];
+
+    let (result, errors) = generate("print(greet());");
+    assert!(result.is_err());
+    assert_eq!(errors, void_error);
+    let (result, errors) = generate("x = greet() + 1;");
+    assert!(result.is_err());
+    assert_eq!(errors, void_error);
+
+    assert!(generate("print(one()); x = one() + 1; greet();").0.is_ok());
+    println!("\nVoid call in expression test passed");
+}
+
+#[test]
+fn test_symbolic_obj_format_round_trip() {
+    let program = r#"
+    program symbolic;
+    var i, total: int;
+    void add(n: int) [ { total = total + n; } ];
+    main {
+        i = 0;
+        total = 0;
+        while (i < 4) do {
+            add(i);
+            i = i + 1;
+        };
+        if (total > 5) { print(total); } else { print(0); }
+        print(2.5 * 2.0);
+    }
+    end
+    "#;
+
+    let numeric = compile_to_obj(program);
+    let symbolic = symbolic_obj_content(&numeric);
+    assert!(symbolic.contains("\nGOTOF,"), "Opcodes should be written as names: {}", symbolic);
+    assert!(symbolic.contains("\nGOSUB,"));
+    assert!(!symbolic.lines().skip_while(|line| *line != "QUADRUPLES:").skip(1)
+        .take_while(|line| !line.starts_with("END_"))
+        .any(|line| line.starts_with(|c: char| c.is_ascii_digit())), "No numeric opcodes should remain");
+
+    let run = |obj: &str| {
+        let mut vm_instance = vm::VM::new();
+        vm_instance.load_obj_str(obj).expect("Failed to load object content");
+        vm_instance.capture_output();
+        let result = vm_instance.run();
+        (result, vm_instance.take_output())
+    };
+    let numeric_run = run(&numeric);
+    assert_eq!(numeric_run, run(&symbolic));
+    assert_eq!(numeric_run.1, vec!["6".to_string(), "5".to_string()]);
+
+    let mut vm_instance = vm::VM::new();
+    let bad = symbolic.replacen("\nGOSUB,", "\nJUMP,", 1);
+    assert!(vm_instance.load_obj_str(&bad).is_err(), "Unknown opcode names should be rejected");
+    println!("\nSymbolic obj format test passed");
+}
+
+#[test]
+fn test_bool_function_in_conditions() {
+    let program = r#"
+    program predicates;
+    var x, n: int;
+    bool isPositive(v: int) [ { return v > 0; } ];
+    main {
+        x = 3;
+        if (isPositive(x)) { print(1); } else { print(0); }
+        n = 0;
+        while (isPositive(x)) do {
+            n = n + 1;
+            x = x - 1;
+        };
+        print(n);
+        if (isPositive(x)) { print(1); } else { print(0); }
+    }
+    end
+    "#;
+
+    let (result, output) = compile_and_run(program);
+    assert_eq!(result, Ok(0));
+    assert_eq!(output, vec!["1".to_string(), "3".to_string(), "0".to_string()]);
+    println!("\nBool function in conditions test passed");
+}
+
+#[test]
+fn test_patch_function_starts_relocation() {
+    let program = r#"
+    program relocate;
+    var total: int;
+    void bump(n: int) [ { total = total + n; print(total); } ];
+    main {
+        total = 1;
+        bump(2);
+        bump(3);
+    }
+    end
+    "#;
+    let ast = babyduck::ProgramParser::new().parse(program).unwrap();
+    let mut quad_gen = QuadrupleGenerator::new();
+    quad_gen.set_function_directory(FunctionDirectory::from_program(&ast).unwrap());
+    quad_gen.generate_for_program(&ast).unwrap();
+
+    // Copy bump's straight-line body past the end and turn the original into HALTs,
+    // so the program only behaves if every reference follows the move
+    let old_start = quad_gen.function_directory().unwrap()
+        .get_function("bump").unwrap().start_quad_idx.unwrap();
+    let quads = quad_gen.get_quadruples_mut();
+    let body_len = quads.iter().skip(old_start as usize)
+        .position(|quad| quad.operation == OpCode::ENDFUNC).unwrap() + 1;
+    let offset = quads.len() as i32 - old_start;
+    for idx in old_start as usize..old_start as usize + body_len {
+        let quad = quads[idx].clone();
+        quads.push_back(quad);
+        quads[idx] = quadruples::Quadruple::new(OpCode::HALT, -1, -1, -1);
+    }
+
+    let moves = std::collections::HashMap::from([(old_start, old_start + offset)]);
+    quad_gen.patch_function_starts(&moves);
+
+    let new_start = old_start + offset;
+    assert_eq!(quad_gen.function_directory().unwrap()
+        .get_function("bump").unwrap().start_quad_idx, Some(new_start));
+    let calls: Vec<i32> = quad_gen.get_quadruples().iter()
+        .filter(|quad| quad.operation == OpCode::ERA || quad.operation == OpCode::GOSUB)
+        .map(|quad| quad.arg1)
+        .collect();
+    assert_eq!(calls, vec![new_start; 4], "Every ERA and GOSUB should point at the moved code");
+
+    let mut vm_instance = vm::VM::new();
+    vm_instance.load_obj_str(&build_obj_content(&quad_gen, &ast, "relocate.bd")).unwrap();
+    vm_instance.capture_output();
+    assert_eq!(vm_instance.run(), Ok(0));
+    assert_eq!(vm_instance.take_output(), vec!["3".to_string(), "6".to_string()]);
+    println!("\nPatch function starts test passed");
+}
+
+#[test]
+fn test_deeply_nested_blocks_are_rejected() {
+    let nested = |depth: usize| {
+        let body = format!("{}x = 1;{}", "if (x > 0) { while (x < 0) do { ".repeat(depth / 2), " }; }".repeat(depth / 2));
+        format!("program deep; var x: int; main {{ {} }} end", body)
+    };
+
+    match compiler::compile_source(&nested(4000)) {
+        Err(quadruples::CompileError::NestingTooDeep { depth, limit }) => {
+            assert_eq!((depth, limit), (4000, quadruples::MAX_NESTING_DEPTH));
+        }
+        other => panic!("Expected NestingTooDeep, got {:?}", other.map(|compiled| compiled.obj_content)),
+    }
+
+    // Modules are checked before they are linked in
+    let module = format!("program lib; var y: int; int one(n: int) [ {{ {}y = 1;{} return(1); }} ]; main {{ }} end",
+        "if (y > 0) { ".repeat(3000), " }".repeat(3000));
+    let linked = compiler::compile_linked_sources("program app; var x: int; main { x = 1; } end", &[&module]);
+    assert!(matches!(linked, Err(quadruples::CompileError::NestingTooDeep { depth: 3000, .. })), "{:?}", linked.err());
+
+    assert!(compiler::compile_source(&nested(quadruples::MAX_NESTING_DEPTH)).is_ok());
+    println!("\nDeeply nested blocks test passed");
+}
+
+#[test]
+fn test_deeply_nested_expression_is_rejected() {
+    let generate = |depth: usize| {
+        let expression = format!("{}1{}", "(1 + ".repeat(depth), ")".repeat(depth));
+        let source = format!("program deep; var x: int; main {{ x = {}; }} end", expression);
+        let ast = babyduck::ProgramParser::new().parse(&source).unwrap();
+        let mut quad_gen = QuadrupleGenerator::new();
+        quad_gen.set_function_directory(FunctionDirectory::from_program(&ast).unwrap());
+        let result = quad_gen.generate_for_program(&ast);
+        (result, quad_gen.compile_errors().to_vec())
+    };
+
+    let (result, errors) = generate(5000);
+    assert!(result.is_err(), "A 5000-level expression should be rejected");
+    assert_eq!(errors, vec![quadruples::CompileError::ExpressionTooDeep {
+        depth: 5001,
+        limit: quadruples::DEFAULT_MAX_EXPRESSION_DEPTH,
+    }]);
+
+    let (result, errors) = generate(500);
+    assert!(result.is_ok(), "Nesting within the limit should still compile: {:?}", errors);
+    println!("\nDeeply nested expression test passed");
+}
+
+#[test]
+fn test_bitwise_operators() {
+    let program = r#"
+    program bits;
+    var a, b: int;
+    main {
+        print(6 & 3);
+        print(5 | 2);
+        print(5 ^ 1);
+        a = 12;
+        b = 10;
+        print(a & b | 1);
+        print(a ^ b + 2);
+        if (a & 4 == 4) { print(1); } else { print(0); }
+    }
+    end
+    "#;
+    let (result, output) = compile_and_run(program);
+    assert_eq!(result, Ok(0));
+    // a & b | 1 groups left to right; + binds tighter than ^; & binds tighter than ==
+    assert_eq!(output, vec!["2", "7", "4", "9", "0", "1"]);
+
+    let ast = babyduck::ProgramParser::new().parse("program bits; var f: float; var x: int; main { x = f & 1; } end").unwrap();
+    let mut quad_gen = QuadrupleGenerator::new();
+    quad_gen.set_function_directory(FunctionDirectory::from_program(&ast).unwrap());
+    let result = quad_gen.generate_for_program(&ast);
+    assert!(result.as_ref().is_err_and(|e| e.contains("BitAnd")), "Bitwise ops on floats should be rejected: {:?}", result);
+    println!("\nBitwise operators test passed");
+}
+
+#[test]
+fn test_shift_operators() {
+    let program = r#"
+    program shifts;
+    var n: int;
+    main {
+        print(1 << 4);
+        print(256 >> 2);
+        n = 2;
+        print(1 << n + 1);
+        print(0 - 16 >> 2);
+    }
+    end
+    "#;
+    let (result, output) = compile_and_run(program);
+    assert_eq!(result, Ok(0));
+    // + binds tighter than <<, and >> keeps the sign
+    assert_eq!(output, vec!["16", "64", "8", "-4"]);
+
+    let (result, output) = compile_and_run("program shifts; var n: int; main { n = 1 << 40; print(n); } end");
+    assert!(result.is_err_and(|e| e.contains("Invalid shift amount 40")), "An oversized shift should be a runtime error");
+    assert!(output.is_empty());
+    let (result, _) = compile_and_run("program shifts; var n: int; main { n = 8 >> (0 - 1); } end");
+    assert!(result.is_err_and(|e| e.contains("Invalid shift amount -1")), "A negative shift should be a runtime error");
+    println!("\nShift operators test passed");
+}
+
+#[test]
+fn test_verify_function_table_on_load() {
+    let program = r#"
+    program table;
+    var x: int;
+    void first() [ { x = 1; } ];
+    void second() [ { x = 2; } ];
+    main {
+        first();
+        second();
+        print(x);
+    }
+    end
+    "#;
+    let obj_content = compile_to_obj(program);
+    let load = |obj: &str| {
+        let mut vm_instance = vm::VM::new();
+        vm_instance.set_verify_function_table(true);
+        vm_instance.capture_output();
+        vm_instance.load_obj_str(obj).map(|_| vm_instance)
+    };
+    let mut vm_instance = load(&obj_content).expect("A complete function table should load");
+    assert_eq!(vm_instance.run(), Ok(0));
+
+    // Drop second's entry from the FUNCTIONS section
+    let truncated: String = obj_content.lines()
+        .filter(|line| !line.starts_with("second,"))
+        .map(|line| format!("{}\n", line))
+        .collect();
+    let second_start = obj_content.lines().find_map(|line| line.strip_prefix("second,"))
+        .and_then(|rest| rest.split(',').next()).unwrap();
+    let error = load(&truncated).err().expect("A missing function entry should fail to load");
+    assert!(error.ends_with(&format!("start indices {}", second_start)), "Unexpected error: {}", error);
+
+    // Without the check the same object still loads and only fails at the call
+    let mut unchecked = vm::VM::new();
+    unchecked.capture_output();
+    unchecked.load_obj_str(&truncated).unwrap();
+    assert!(unchecked.run().is_err());
+    println!("\nFunction table verification test passed");
+}
+
+#[test]
+fn test_global_constant_propagation() {
+    let compile = |main_body: &str| {
+        let source = format!(
+            "program propagate; var i, limit: int; var scale: float; void show() [ {{ print(limit * scale); }} ]; main {{ {} }} end",
+            main_body
+        );
+        let ast = babyduck::ProgramParser::new().parse(&source).unwrap();
+        let mut quad_gen = QuadrupleGenerator::new();
+        quad_gen.set_function_directory(FunctionDirectory::from_program(&ast).unwrap());
+        quad_gen.propagate_global_constants();
+        quad_gen.generate_for_program(&ast).unwrap();
+        let directory = quad_gen.function_directory().unwrap();
+        let limit = directory.get_variable_address("global", "limit").unwrap();
+        // Quads that read `limit` (anything but its own assignment)
+        let reads = quad_gen.get_quadruples().iter()
+            .filter(|quad| quad.result != limit && (quad.arg1 == limit || quad.arg2 == limit))
+            .count();
+
+        let mut vm_instance = vm::VM::new();
+        vm_instance.load_obj_str(&build_obj_content(&quad_gen, &ast, "propagate.bd")).unwrap();
+        vm_instance.capture_output();
+        assert_eq!(vm_instance.run(), Ok(0));
+        (reads, vm_instance.take_output())
+    };
+
+    // Written once up front: the loop condition and the function read the constant
+    let (reads, output) = compile("limit = 3; scale = 0.5; i = 0; while (i < limit) { i = i + 1; }; show(); print(i);");
+    assert_eq!(reads, 0, "Reads of a write-once global should use the constant");
+    assert_eq!(output, vec!["1.5", "3"]);
+
+    // Reassigned later: every read must keep loading the variable
+    let (reads, output) = compile("limit = 3; scale = 0.5; show(); limit = 4; show();");
+    assert_eq!(reads, 1);
+    assert_eq!(output, vec!["1.5", "2"]);
+
+    // Assigned once, but inside a branch
+    let (reads, output) = compile("i = 1; scale = 2.0; if (i > 0) { limit = 5; } show();");
+    assert_eq!(reads, 1);
+    assert_eq!(output, vec!["10"]);
+    println!("\nGlobal constant propagation test passed");
+}
+
+#[test]
+fn test_printd_shows_static_type() {
+    let program = r#"
+    program typed;
+    var n: int;
+    var f: float;
+    main {
+        n = 5;
+        f = 1.5;
+        printd(2 + 3.0);
+        printd(n);
+        printd(n * f);
+        printd(n > 2);
+        printd(n / 2);
+    }
+    end
+    "#;
+    let (result, output) = compile_and_run(program);
+    assert_eq!(result, Ok(0));
+    assert_eq!(output, vec!["5 : float", "5 : int", "7.5 : float", "true : bool", "2 : int"]);
+    println!("\nprintd test passed");
+}
+
+#[test]
+fn test_functions_are_generated_before_main_regardless_of_source_order() {
+    use ast::{Expression, FunctionCall, FunctionDeclaration, Parameter, PrintStatement, Program, Statement, Type, VarDeclaration};
+
+    // Built by hand, as a grammar that allowed `main` before the functions would produce it
+    let call = |id: &str, arg: i32| Statement::FunctionCall(FunctionCall {
+        id: id.to_string(),
+        arguments: vec![Expression::IntegerLiteral(arg)],
+    });
+    let printer = |id: &str, offset: i32| FunctionDeclaration {
+        id: id.to_string(),
+        return_type: None,
+        parameters: vec![Parameter { id: "n".to_string(), param_type: Type::Int }],
+        vars: Vec::new(),
+        body: vec![Statement::Print(PrintStatement::Expression(Expression::BinaryOp {
+            left: Box::new(Expression::Identifier("n".to_string())),
+            operator: ast::Operator::Plus,
+            right: Box::new(Expression::IntegerLiteral(offset)),
+        }))],
+    };
+    let program = Program {
+        id: "main_first".to_string(),
+        vars: vec![VarDeclaration { id: "x".to_string(), var_type: Type::Int }],
+        main_body: vec![call("later", 1), call("last", 2)],
+        funcs: vec![printer("later", 10), printer("last", 20)],
+    };
+
+    let mut quad_gen = QuadrupleGenerator::new();
+    quad_gen.set_function_directory(FunctionDirectory::from_program(&program).unwrap());
+    quad_gen.generate_for_program(&program).unwrap();
+
+    let directory = quad_gen.function_directory().unwrap();
+    let start = |name: &str| directory.get_function(name).unwrap().start_quad_idx.unwrap();
+    let quads = quad_gen.get_quadruples();
+    assert_eq!(quads[0].operation, OpCode::GOTO);
+    assert_eq!(quads[0].result, start("main"), "GOTO main should target main's first quad");
+    assert_eq!(start("later"), 1, "Functions come right after GOTO main");
+    assert!(start("later") < start("last") && start("last") < start("main"));
+    assert_eq!(quads[start("main") as usize - 1].operation, OpCode::ENDFUNC, "main starts after the last function");
+
+    let mut vm_instance = vm::VM::new();
+    vm_instance.load_obj_str(&build_obj_content(&quad_gen, &program, "main_first.bd")).unwrap();
+    vm_instance.capture_output();
+    assert_eq!(vm_instance.run(), Ok(0));
+    assert_eq!(vm_instance.take_output(), vec!["11", "22"]);
+    println!("\nGeneration order test passed");
+}
+
+#[test]
+fn test_vm_snapshot_and_restore() {
+    let program = r#"
+    program checkpoint;
+    var i, total: int;
+    void add(n: int) [ { total = total + n; } ];
+    main {
+        i = 0;
+        total = 0;
+        while (i < 5) do {
+            add(i);
+            print(total);
+            i = i + 1;
+        };
+        exit(total);
+    }
+    end
+    "#;
+    let mut vm_instance = vm::VM::new();
+    vm_instance.load_obj_str(&compile_to_obj(program)).unwrap();
+    vm_instance.capture_output();
+
+    // Step into the loop, up to the first print
+    let mut printed = Vec::new();
+    while printed.is_empty() {
+        assert_eq!(vm_instance.run_step(), Ok(None));
+        printed = vm_instance.take_output();
+    }
+    assert_eq!(printed, vec!["0"]);
+    let snapshot = vm_instance.snapshot();
+
+    let first_result = vm_instance.run();
+    let first_output = vm_instance.take_output();
+    let first_report = vm_instance.execution_report();
+    assert_eq!(first_result, Ok(10));
+    assert_eq!(first_output, vec!["1", "3", "6", "10"]);
+
+    // The snapshot is a deep copy: running on didn't change it
+    assert_ne!(vm_instance.snapshot(), snapshot);
+    vm_instance.restore(&snapshot).unwrap();
+    assert_eq!(vm_instance.snapshot(), snapshot);
+    assert_eq!(vm_instance.run(), first_result);
+    assert_eq!(vm_instance.take_output(), first_output);
+    assert_eq!(vm_instance.execution_report(), first_report);
+
+    // A snapshot of a different program can't be restored
+    let mut other = vm::VM::new();
+    other.load_obj_str(&compile_to_obj("program other; var a, b, c, d: int; main { a = 1; } end")).unwrap();
+    assert!(other.restore(&snapshot).is_err());
+
+    // Tokens a READ left over from its input line come back with the snapshot
+    let mut reader = vm::VM::new();
+    reader.load_obj_str(&compile_to_obj("program pair; var a, b: int; main { read(a, b); print(a + b); } end")).unwrap();
+    reader.set_stdin(Box::new(std::io::Cursor::new("10 20\n")));
+    reader.capture_output();
+    while reader.snapshot().pending_input.is_empty() {
+        assert_eq!(reader.run_step(), Ok(None));
+    }
+    let snapshot = reader.snapshot();
+    assert_eq!(reader.run(), Ok(0));
+    assert_eq!(reader.take_output(), vec!["30"]);
+    reader.restore(&snapshot).unwrap();
+    assert_eq!(reader.run(), Ok(0));
+    assert_eq!(reader.take_output(), vec!["30"]);
+    println!("\nVM snapshot test passed");
+}
+
+#[test]
+fn test_load_rejects_unmapped_addresses() {
+    let load = |obj: &str| vm::VM::new().load_obj_str(obj);
+
+    // A mistyped result address used to grow the temporary bool segment to fit it
+    let error = load("QUADRUPLES:\n1,-1,1,99999\n50,-1,-1,-1\nEND_QUADRUPLES\n").unwrap_err();
+    assert_eq!(error, "QUADRUPLES line '1,-1,1,99999': address 99999 is outside every memory segment");
+    let error = load("QUADRUPLES:\n4,1000,999,5000\nEND_QUADRUPLES\n").unwrap_err();
+    assert!(error.contains("address 999 is outside"), "{}", error);
+    let error = load("CONSTANTS_INT:\n7,4500\nEND_CONSTANTS_INT\n").unwrap_err();
+    assert!(error.contains("outside the int constant segment"), "{}", error);
+    let error = load("FUNCTIONS:\nf,1,1,0,123\nEND_FUNCTIONS\n").unwrap_err();
+    assert!(error.contains("parameter address 123"), "{}", error);
+
+    // Jump targets, parameter indices and print bases aren't addresses
+    let obj = "QUADRUPLES:\n30,-1,-1,9000\n41,4000,-1,2\n20,4000,16,-1\nEND_QUADRUPLES\n";
+    assert!(load(&format!("CONSTANTS_INT:\n5,4000\nEND_CONSTANTS_INT\n{}", obj)).is_ok());
+    println!("\nUnmapped address test passed");
+}
+
+#[test]
+fn test_braceless_bodies_and_dangling_else() {
+    let program = r#"
+    program braceless;
+    var x, n: int;
+    main {
+        n = 3;
+        if (n > 2) x = 1; else x = 2;
+        print(x);
+        while (n > 0) n = n - 1;
+        print(n);
+        if (n == 0) if (x == 2) print(20); else print(10);
+        if (x > 5) print(0); else if (x > 0) print(1); else print(2);
+        while (x < 4) do {
+            x = x + 1;
+        };
+        print(x);
+    }
+    end
+    "#;
+    let ast = babyduck::ProgramParser::new().parse(program).expect("Braceless bodies should parse");
+    let ast::Statement::Condition(simple) = &ast.main_body[1] else { panic!("Expected an if: {:?}", ast.main_body[1]) };
+    assert_eq!(simple.if_body.len(), 1);
+    assert_eq!(simple.else_body.as_ref().map(|body| body.len()), Some(1));
+
+    // The else belongs to the nearest if: the outer if has no else
+    let ast::Statement::Condition(outer) = &ast.main_body[5] else { panic!("Expected an if: {:?}", ast.main_body[5]) };
+    assert!(outer.else_body.is_none(), "else should bind to the inner if");
+    let [ast::Statement::Condition(inner)] = outer.if_body.as_slice() else { panic!("Expected a nested if: {:?}", outer.if_body) };
+    assert!(inner.else_body.is_some());
+
+    let (result, output) = compile_and_run(program);
+    assert_eq!(result, Ok(0));
+    assert_eq!(output, vec!["1", "0", "10", "1", "4"]);
+
+    // A braced while body still needs its semicolon; a single statement takes none
+    assert!(babyduck::ProgramParser::new().parse("program p; var n: int; main { while (n > 0) { n = n - 1; } } end").is_err());
+    assert!(babyduck::ProgramParser::new().parse("program p; var n: int; main { while (n > 0) n = n - 1; ; } end").is_err());
+    println!("\nBraceless bodies test passed");
+}
+
+#[test]
+fn test_emit_listing_shares_the_build_steps() {
+    let dir = std::env::temp_dir();
+    let source_path = dir.join(format!("babyduck_listing_{}.bd", std::process::id()));
+    let module_path = dir.join(format!("babyduck_listing_lib_{}.bd", std::process::id()));
+    fs::write(&source_path, "program listing; var x: int; void bump(a: int) [ { a = a + 1; } ]; main { x = twice(3); bump(x); } end").unwrap();
+    fs::write(&module_path, "program lib; int twice(n: int) [ { return(n * 2); } ]; main { } end").unwrap();
+    let modules = vec![module_path.to_str().unwrap().to_string()];
+    let listing = |options: &BuildOptions| {
+        let err = Rc::new(RefCell::new(Vec::new()));
+        let console = Console { out: Rc::new(RefCell::new(Vec::new())), err: err.clone() };
+        let generated = generate_quadruples(options, source_path.to_str().unwrap(), &modules, &console, &mut PhaseTimes::default(), &|_| {});
+        let warnings = String::from_utf8(err.take()).unwrap();
+        (generated.map(|(_, quad_gen)| quad_gen.format_listing()), warnings)
+    };
+
+    // The module's function is linked in, and the listing gets the same lint as a build
+    let (generated, warnings) = listing(&BuildOptions { warn_param_assign: true, ..Default::default() });
+    assert!(generated.unwrap().contains("GOSUB"));
+    assert!(warnings.contains("Parameter 'a' of function 'bump' is reassigned"), "{}", warnings);
+    let (generated, _) = listing(&BuildOptions { warn_param_assign: true, werror: true, ..Default::default() });
+    let error = generated.unwrap_err();
+    assert!(error.contains("Warning treated as an error"), "{}", error);
+
+    fs::remove_file(&source_path).unwrap();
+    fs::remove_file(&module_path).unwrap();
+    println!("\nEmit listing build steps test passed");
+}
+
+#[test]
+fn test_format_listing_aligns_columns() {
+    let program = r#"
+    program listing;
+    var counter: int;
+    var ratio: float;
+    main {
+        counter = 10;
+        ratio = counter * 2.5;
+        print(ratio);
+    }
+    end
+    "#;
+    let ast = babyduck::ProgramParser::new().parse(program).unwrap();
+    let mut quad_gen = QuadrupleGenerator::new();
+    quad_gen.set_function_directory(FunctionDirectory::from_program(&ast).unwrap());
+    quad_gen.generate_for_program(&ast).unwrap();
+
+    let listing = quad_gen.format_listing();
+    let lines: Vec<&str> = listing.lines().collect();
+    assert_eq!(lines.len(), quad_gen.get_quadruples().len() + 1, "One header line plus one per quad");
+    assert!(lines[0].trim_start().starts_with("#  op"));
+    assert!(lines[1].trim_start().starts_with("0  GOTO"), "The index comes first: {}", lines[1]);
+
+    // Every column starts at the same offset on every line
+    let column_starts = |line: &str| -> Vec<usize> {
+        let chars: Vec<char> = line.chars().collect();
+        (1..chars.len()).filter(|&i| chars[i] != ' ' && chars[i - 1] == ' ' && i >= 2 && chars[i - 2] == ' ').collect()
+    };
+    let header_starts = column_starts(lines[0]);
+    assert_eq!(header_starts.len(), 4);
+    for line in &lines[1..] {
+        let starts = column_starts(line);
+        assert!(starts.iter().all(|start| header_starts.contains(start)), "Misaligned line: {}\n{}", line, listing);
+    }
+    let multiply = lines.iter().find(|line| line.contains("  *  ")).expect("Listing should contain the multiplication");
+    assert_eq!(column_starts(multiply), header_starts, "A quad using every operand fills every column");
+    println!("\nAligned listing test passed");
+}
+
+#[test]
+fn test_compile_ast_through_the_library() {
+    use compiler::ast::{Assignment, Cycle, Expression, Operator, PrintStatement, Program, Statement, Type, VarDeclaration};
+    use compiler::quadruples::CompileError;
+
+    // i = 0; while (i < 3) { print(i * 10); i = i + 1; }
+    let i = || Box::new(Expression::Identifier("i".to_string()));
+    let program = Program {
+        id: "generated".to_string(),
+        vars: vec![VarDeclaration { id: "i".to_string(), var_type: Type::Int }],
+        funcs: Vec::new(),
+        main_body: vec![
+            Statement::Assignment(Assignment { id: "i".to_string(), expression: Expression::IntegerLiteral(0) }),
+            Statement::Cycle(Cycle {
+                condition: Expression::BinaryOp { left: i(), operator: Operator::LessThan, right: Box::new(Expression::IntegerLiteral(3)) },
+                body: vec![
+                    Statement::Print(PrintStatement::Expression(Expression::BinaryOp {
+                        left: i(), operator: Operator::Multiply, right: Box::new(Expression::IntegerLiteral(10)),
+                    })),
+                    Statement::Assignment(Assignment {
+                        id: "i".to_string(),
+                        expression: Expression::BinaryOp { left: i(), operator: Operator::Plus, right: Box::new(Expression::IntegerLiteral(1)) },
+                    }),
+                ],
+            }),
+        ],
+    };
+
+    let compiled = compiler::compile_ast(program.clone()).expect("A well-formed AST should compile");
+    assert!(compiled.warnings.is_empty(), "Unexpected warnings: {:?}", compiled.warnings);
+    let mut vm_instance = vm::VM::new();
+    vm_instance.load_obj_str(&compiled.obj_content).unwrap();
+    vm_instance.capture_output();
+    assert_eq!(vm_instance.run(), Ok(0));
+    assert_eq!(vm_instance.take_output(), vec!["0", "10", "20"]);
+
+    // Semantic errors come back as CompileError rather than a parse failure
+    let mut broken = program;
+    broken.main_body.push(Statement::Assignment(Assignment { id: "missing".to_string(), expression: Expression::IntegerLiteral(1) }));
+    match compiler::compile_ast(broken) {
+        Err(CompileError::Semantic(message)) => assert!(message.contains("missing"), "{}", message),
+        other => panic!("Expected a semantic error, got {:?}", other.map(|c| c.obj_content)),
+    }
+    println!("\nLibrary compile_ast test passed");
+}
+
+#[test]
+fn test_compile_never_panics_on_malformed_asts() {
+    use compiler::ast::*;
+
+    // A tiny deterministic generator, so a failure reproduces from its seed
+    struct Fuzz(u64);
+    impl Fuzz {
+        fn next(&mut self, n: usize) -> usize {
+            self.0 = self.0.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            ((self.0 >> 33) as usize) % n
+        }
+        fn name(&mut self) -> String {
+            ["a", "f", "b", "missing", "g", "v", "x"][self.next(7)].to_string()
+        }
+        fn expression(&mut self, depth: usize) -> Expression {
+            let kinds = if depth == 0 { 4 } else { 8 };
+            match self.next(kinds) {
+                0 => Expression::Identifier(self.name()),
+                1 => Expression::IntegerLiteral([0, 1, -1, 31, 32, i32::MAX, i32::MIN][self.next(7)]),
+                2 => Expression::FloatLiteral([0.0, -0.0, 1.5, f64::NAN][self.next(4)]),
+                3 => Expression::BooleanLiteral(self.next(2) == 0),
+                4 | 5 => {
+                    let operator = match self.next(13) {
+                        0 => Operator::Plus, 1 => Operator::Minus, 2 => Operator::Multiply, 3 => Operator::Divide,
+                        4 => Operator::GreaterThan, 5 => Operator::LessThan, 6 => Operator::Equal, 7 => Operator::NotEqual,
+                        8 => Operator::BitAnd, 9 => Operator::BitOr, 10 => Operator::BitXor,
+                        11 => Operator::ShiftLeft, _ => Operator::ShiftRight,
+                    };
+                    Expression::BinaryOp { left: Box::new(self.expression(depth - 1)), operator, right: Box::new(self.expression(depth - 1)) }
+                }
+                6 => Expression::BuiltinCall {
+                    function: if self.next(2) == 0 { Builtin::Fmod } else { Builtin::FloatCast },
+                    arguments: (0..self.next(4)).map(|_| self.expression(depth - 1)).collect(),
+                },
+                _ => Expression::FunctionCall(self.call(depth - 1)),
+            }
+        }
+        fn call(&mut self, depth: usize) -> FunctionCall {
+            FunctionCall { id: self.name(), arguments: (0..self.next(3)).map(|_| self.expression(depth)).collect() }
+        }
+        fn statements(&mut self, depth: usize) -> Vec<Statement> {
+            (0..self.next(5)).map(|_| self.statement(depth)).collect()
+        }
+        fn statement(&mut self, depth: usize) -> Statement {
+            let kinds = if depth == 0 { 9 } else { 11 };
+            match self.next(kinds) {
+                0 => Statement::Assignment(Assignment { id: self.name(), expression: self.expression(3) }),
+                1 => Statement::FunctionCall(self.call(2)),
+                2 => Statement::Print(match self.next(4) {
+                    0 => PrintStatement::Expression(self.expression(3)),
+                    1 => PrintStatement::Hex(self.expression(3)),
+                    2 => PrintStatement::Binary(self.expression(3)),
+                    _ => PrintStatement::Typed(self.expression(3)),
+                }),
+                3 => Statement::Exit(self.expression(2)),
+                4 => Statement::Read((0..self.next(3)).map(|_| self.name()).collect()),
+                5 => Statement::Section("header, with \"quotes\"\n".to_string()),
+                6 => Statement::Clear(self.name()),
+                7 => Statement::Return(self.expression(2)),
+                8 => Statement::VarDeclaration(vec![VarDeclaration { id: self.name(), var_type: Type::Int }]),
+                9 => Statement::Condition(Condition {
+                    condition: self.expression(2),
+                    if_body: self.statements(depth - 1),
+                    else_body: if self.next(2) == 0 { Some(self.statements(depth - 1)) } else { None },
+                }),
+                _ => Statement::Cycle(Cycle { condition: self.expression(2), body: self.statements(depth - 1) }),
+            }
+        }
+        fn program(&mut self) -> Program {
+            let function = |fuzz: &mut Fuzz, id: &str, return_type: Option<Type>| FunctionDeclaration {
+                id: id.to_string(),
+                return_type,
+                parameters: (0..fuzz.next(3)).map(|k| Parameter { id: format!("p{}", k), param_type: Type::Int }).collect(),
+                vars: Vec::new(),
+                body: fuzz.statements(2),
+            };
+            Program {
+                id: "fuzz".to_string(),
+                vars: vec![
+                    VarDeclaration { id: "a".to_string(), var_type: Type::Int },
+                    VarDeclaration { id: "f".to_string(), var_type: Type::Float },
+                    VarDeclaration { id: "b".to_string(), var_type: Type::Bool },
+                ],
+                funcs: vec![function(self, "g", Some(Type::Int)), function(self, "v", None)],
+                main_body: self.statements(3),
+            }
+        }
+    }
+
+    for seed in 0..2000 {
+        let program = Fuzz(seed).program();
+        let outcome = std::panic::catch_unwind(|| compiler::compile_ast(program));
+        assert!(outcome.is_ok(), "compile_ast panicked for seed {}", seed);
+    }
+
+    // Source text: truncations and byte swaps of a valid program, plus literals that do not fit
+    let source = "program p; var a: int; int g(x: int) [ { return(x << 2); } ]; main { a = g(3) * 2; while (a > 0) a = a - 5; printd(a); } end";
+    let mut fuzz = Fuzz(7);
+    for _ in 0..500 {
+        let mut bytes = source.as_bytes().to_vec();
+        bytes.truncate(1 + fuzz.next(bytes.len()));
+        let at = fuzz.next(bytes.len());
+        bytes[at] = b"(){};=+<a1 "[fuzz.next(11)];
+        let mutated = String::from_utf8(bytes).unwrap();
+        assert!(std::panic::catch_unwind(|| compiler::compile_source(&mutated)).is_ok(), "compile_source panicked on {:?}", mutated);
+    }
+    match compiler::compile_source("program p; var a: int; main { a = 99999999999; } end") {
+        Err(compiler::quadruples::CompileError::Parse(message)) => assert!(message.contains("32-bit"), "{}", message),
+        other => panic!("An out-of-range literal should be a parse error, got {:?}", other.map(|c| c.obj_content)),
+    }
+    assert!(compiler::compile_source(source).is_ok());
+    println!("\nPanic-free compile test passed");
+}
+
+#[test]
+fn test_address_names_match_a_linear_scan() {
+    let program = r#"
+    program names;
+    var total: int; ratio: float;
+    int scale(n: int, k: int) [
+        var doubled: int;
+        {
+            doubled = n * 2;
+            return(doubled * k);
+        }
+    ];
+    main {
+        total = scale(2, 3);
+        var late: bool;
+        late = total > 4;
+        ratio = 1.5;
+        print(ratio);
+    }
+    end
+    "#;
+    let ast = babyduck::ProgramParser::new().parse(program).unwrap();
+    let mut quad_gen = QuadrupleGenerator::new();
+    quad_gen.set_function_directory(FunctionDirectory::from_program(&ast).unwrap());
+    quad_gen.generate_for_program(&ast).unwrap();
+
+    // The lookup the index replaced: locals of a scope first, then its parameters
+    let linear = |quad_gen: &QuadrupleGenerator, address: i32| -> Option<String> {
+        for (scope, info) in quad_gen.function_directory().unwrap().get_all_functions() {
+            if let Some((name, _)) = info.local_variables.iter().find(|(_, var)| var.address == address) {
+                return Some(format!("{}.{} ({})", scope, name, address));
+            }
+            if let Some((name, _, _)) = info.parameters.iter().find(|(_, _, addr)| *addr == address) {
+                return Some(format!("{}.{} (param) ({})", scope, name, address));
+            }
+        }
+        None
+    };
+    let mut checked = 0;
+    for address in 1000..3100 {
+        if let Some(expected) = linear(&quad_gen, address) {
+            assert_eq!(quad_gen.get_name_by_address(address), expected);
+            checked += 1;
+        }
+    }
+    assert!(checked >= 6, "Expected globals, params, locals and the mid-body local, checked {}", checked);
+    let late = linear(&quad_gen, 3000).expect("The mid-body local should have a bool address");
+    assert!(late.starts_with("main.late"), "{}", late);
+
+    // A new directory invalidates the cached index
+    let first_global = quad_gen.get_name_by_address(1000);
+    let renamed = babyduck::ProgramParser::new().parse("program other; var renamed: int; main { renamed = 1; } end").unwrap();
+    quad_gen.set_function_directory(FunctionDirectory::from_program(&renamed).unwrap());
+    assert_ne!(quad_gen.get_name_by_address(1000), first_global);
+    assert_eq!(quad_gen.get_name_by_address(1000), linear(&quad_gen, 1000).unwrap());
+    println!("\nAddress name index test passed");
+}
+
+#[test]
+fn test_explain_last_loop() {
+    let program = r#"
+    program loops;
+    var i: int;
+    main {
+        i = 0;
+        while (i < 3) {
+            print(i);
+            i = i + 1;
+        };
+        print(i);
+    }
+    end
+    "#;
+    let ast = babyduck::ProgramParser::new().parse(program).unwrap();
+    let mut quad_gen = QuadrupleGenerator::new();
+    quad_gen.set_function_directory(FunctionDirectory::from_program(&ast).unwrap());
+    assert_eq!(quad_gen.explain_last_loop(), "No while loop has been generated");
+    quad_gen.generate_for_program(&ast).unwrap();
+
+    // 0 GOTO main, 1 i = 0, 2 i < 3, 3 GOTOF, 4 print, 5 +, 6 =, 7 GOTO 2, 8 print
+    let lowering = quad_gen.last_loop().expect("The program has a loop");
+    assert_eq!(lowering, quadruples::LoopLowering { condition_start: 2, exit_jump: 3, exit_target: 8, back_jump: 7 });
+    let quads = quad_gen.get_quadruples();
+    assert_eq!(quads[lowering.condition_start].operation, OpCode::LT);
+    assert_eq!(quads[lowering.exit_jump].result, lowering.exit_target as i32);
+    assert_eq!(quads[lowering.back_jump].result, lowering.condition_start as i32);
+
+    let explanation = quad_gen.explain_last_loop();
+    assert!(explanation.contains("3: GOTOF leaves the loop for quad 8"), "{}", explanation);
+    assert!(explanation.contains("7: GOTO returns to the condition at quad 2"), "{}", explanation);
+
+    // A tail call's backward GOTO after the loop is not taken for the loop's
+    let program = r#"
+    program loop_then_tail;
+    var total: int;
+    void count(n: int) [
+        var i: int;
+        {
+            i = 0;
+            while (i < n) do {
+                i = i + 1;
+            };
+            if (n > 0) {
+                count(n - 1);
+            } else {
+                total = total + i;
+            }
+        }
+    ];
+    main {
+        total = 0;
+        count(3);
+        print(total);
+    }
+    end
+    "#;
+    let ast = babyduck::ProgramParser::new().parse(program).unwrap();
+    let mut quad_gen = QuadrupleGenerator::new();
+    quad_gen.set_function_directory(FunctionDirectory::from_program(&ast).unwrap());
+    quad_gen.optimize_tail_calls();
+    quad_gen.generate_for_program(&ast).unwrap();
+    let quads = quad_gen.get_quadruples();
+    let lowering = quad_gen.last_loop().expect("The loop comes before the tail call");
+    // count starts at quad 1, right after GOTO main
+    let tail_jump = quads.iter().rposition(|quad| quad.operation == OpCode::GOTO && quad.result == 1).unwrap();
+    assert!(tail_jump > lowering.back_jump, "{:?}", quads);
+    assert_eq!(quads[lowering.condition_start].operation, OpCode::LT);
+    assert_eq!(quads[lowering.exit_jump].result, lowering.exit_target as i32);
+    assert_eq!(quads[lowering.back_jump].result, lowering.condition_start as i32);
+    println!("\nLoop lowering explanation test passed");
+}
+
+#[test]
+fn test_constant_sections_are_deterministic() {
+    let program = r#"
+    program constants;
+    var a: int; f: float;
+    main {
+        a = 7 + 300;
+        f = 2.5 * 0.125;
+        a = a - 7 + 42 * 1000;
+        f = f + 2.5 - 9.75;
+        print(a);
+        print(f);
+    }
+    end
+    "#;
+    let section = |obj: &str, name: &str| -> Vec<String> {
+        obj.lines()
+            .skip_while(|line| *line != format!("{}:", name))
+            .skip(1)
+            .take_while(|line| !line.starts_with("END_"))
+            .map(str::to_string)
+            .collect()
+    };
+    let first = compile_to_obj(program);
+    for _ in 0..5 {
+        let again = compile_to_obj(program);
+        assert_eq!(section(&again, "CONSTANTS_INT"), section(&first, "CONSTANTS_INT"));
+        assert_eq!(section(&again, "CONSTANTS_FLOAT"), section(&first, "CONSTANTS_FLOAT"));
+    }
+
+    // Each section lists constants by ascending address, in first-use order, without repeats
+    assert_eq!(section(&first, "CONSTANTS_INT"), vec!["7,4000", "300,4001", "42,4002", "1000,4003"]);
+    assert_eq!(section(&first, "CONSTANTS_FLOAT"), vec!["2.5,4500", "0.125,4501", "9.75,4502"]);
+    println!("\nDeterministic constant sections test passed");
+}
+
+#[test]
+fn test_bool_written_to_int_cell_reads_back_as_bool() {
+    // A bool literal and a comparison result written into int cells, then an int over one of them
+    let obj = "CONSTANTS_INT:\n5,4000\n3,4001\nEND_CONSTANTS_INT\n\
+               QUADRUPLES:\n\
+               ASSIGN,-1,1,1000\n\
+               PRINT,1000,-1,-1\n\
+               GT,4001,4000,7000\n\
+               ASSIGN,7000,-1,1001\n\
+               PRINT,1001,-1,-1\n\
+               ASSIGN,4000,-1,1000\n\
+               PRINT,1000,-1,-1\n\
+               HALT,-1,-1,-1\n\
+               END_QUADRUPLES\n";
+    let mut vm_instance = vm::VM::new();
+    vm_instance.load_obj_str(obj).unwrap();
+    vm_instance.capture_output();
+
+    // Step to just after the first print, where cell 1000 holds a bool
+    let mut printed = Vec::new();
+    while printed.is_empty() {
+        assert_eq!(vm_instance.run_step(), Ok(None));
+        printed = vm_instance.take_output();
+    }
+    assert_eq!(printed, vec!["true"]);
+    let snapshot = vm_instance.snapshot();
+    assert_eq!(snapshot.int_cells_holding_bools, vec![0]);
+
+    assert_eq!(vm_instance.run(), Ok(0));
+    // Reading by segment alone used to print 1 for both bools
+    assert_eq!(vm_instance.take_output(), vec!["false", "5"]);
+
+    vm_instance.restore(&snapshot).unwrap();
+    assert_eq!(vm_instance.run(), Ok(0));
+    assert_eq!(vm_instance.take_output(), vec!["false", "5"], "The tags come back with a snapshot");
+    println!("\nInt cell type tag test passed");
+}
+
+#[test]
+fn test_return_from_main_sets_exit_code() {
+    let program = r#"
+    program status;
+    var x: int;
+    main {
+        x = 3;
+        print(x);
+        return x + 4;
+    }
+    end
+    "#;
+    let (result, output) = compile_and_run(program);
+    assert_eq!(result, Ok(7), "main's return value is the exit code");
+    assert_eq!(output, vec!["3"]);
+
+    // A return inside a branch ends the program there; falling off the end still gives 0
+    let early = r#"
+    program status;
+    var x: int;
+    main {
+        x = 0;
+        if (x == 0) {
+            return 2;
+        }
+        print(x);
+    }
+    end
+    "#;
+    let (result, output) = compile_and_run(early);
+    assert_eq!(result, Ok(2));
+    assert!(output.is_empty(), "Nothing after the return runs: {:?}", output);
+    let (result, _) = compile_and_run("program status; var x: int; main { x = 1; } end");
+    assert_eq!(result, Ok(0));
+
+    // Like exit, the status has to be an int
+    let ast = babyduck::ProgramParser::new().parse("program status; var x: int; main { return 1.5; } end").unwrap();
+    let mut quad_gen = QuadrupleGenerator::new();
+    quad_gen.set_function_directory(FunctionDirectory::from_program(&ast).unwrap());
+    assert!(quad_gen.generate_for_program(&ast).is_err(), "A float status should be rejected");
+    println!("\nReturn from main test passed");
+}
+
+#[test]
+fn test_parameter_assignment_lint() {
+    let program = r#"
+    program byvalue;
+    var n: int;
+    void bump(a: int) [
+        var b: int;
+        {
+            b = a;
+            a = a + 1;
+            print(a);
+        }
+    ];
+    main {
+        n = 1;
+        bump(n);
+        print(n);
+    }
+    end
+    "#;
+    let warnings = |enabled: bool| {
+        let ast = babyduck::ProgramParser::new().parse(program).unwrap();
+        let mut quad_gen = QuadrupleGenerator::new();
+        quad_gen.set_function_directory(FunctionDirectory::from_program(&ast).unwrap());
+        if enabled {
+            quad_gen.warn_on_parameter_assignment();
+        }
+        quad_gen.generate_for_program(&ast).expect("The lint is not an error");
+        quad_gen.take_warnings()
+    };
+
+    let lint = warnings(true);
+    assert_eq!(lint.len(), 1, "Only the parameter assignment is flagged: {:?}", lint);
+    assert!(lint[0].contains("Parameter 'a' of function 'bump' is reassigned"), "{}", lint[0]);
+    assert!(lint[0].contains("passed by value"), "{}", lint[0]);
+    assert!(warnings(false).is_empty(), "The lint is off unless enabled");
+
+    // The caller's variable really is unchanged
+    let (_, output) = compile_and_run(program);
+    assert_eq!(output, vec!["2", "1"]);
+    println!("\nParameter assignment lint test passed");
+}
+
+#[test]
+fn test_peephole_nop_padding_keeps_positions() {
+    let program = r#"
+    program padded;
+    var i, total: int;
+    void add_twice(n: int) [
+        {
+            total = total + n;
+            total = total + n;
+        }
+    ];
+    main {
+        i = 0;
+        total = 0;
+        while (i < 4) {
+            add_twice(i);
+            i = i + 1;
+        };
+        print(total);
+    }
+    end
+    "#;
+    let ast = babyduck::ProgramParser::new().parse(program).unwrap();
+    let generate = |level: u8, nops: bool| {
+        let mut quad_gen = QuadrupleGenerator::new();
+        quad_gen.set_function_directory(FunctionDirectory::from_program(&ast).unwrap());
+        quad_gen.set_optimization_level(level);
+        if nops {
+            quad_gen.pad_removed_quads_with_nops();
+        }
+        quad_gen.generate_for_program(&ast).unwrap();
+        quad_gen
+    };
+    let plain = generate(0, false);
+    let padded = generate(2, true);
+    let compacted = generate(2, false);
+
+    // Every quad keeps its index: jumps and calls target the same positions as before
+    let (plain_quads, padded_quads) = (plain.get_quadruples(), padded.get_quadruples());
+    assert_eq!(padded_quads.len(), plain_quads.len());
+    let nops = padded_quads.iter().filter(|quad| quad.operation == OpCode::NOP).count();
+    assert_eq!(nops, plain_quads.len() - compacted.get_quadruples().len(), "One NOP per quad the pass removes");
+    assert!(nops >= 3, "Expected the three copies into total and i to be removed, got {}", nops);
+    for (plain_quad, padded_quad) in plain_quads.iter().zip(padded_quads) {
+        if matches!(plain_quad.operation, OpCode::GOTO | OpCode::GOTOF | OpCode::GOTOT) {
+            assert_eq!(padded_quad.result, plain_quad.result, "Jump targets are unaffected");
+        }
+        if matches!(plain_quad.operation, OpCode::ERA | OpCode::GOSUB) {
+            assert_eq!(padded_quad.arg1, plain_quad.arg1, "Function starts are unaffected");
+        }
+        if padded_quad.operation == OpCode::NOP {
+            assert_eq!(plain_quad.operation, OpCode::ASSIGN, "Only the removed copies become NOPs");
+        }
+    }
+
+    // All three run identically
+    let run = |quad_gen: &QuadrupleGenerator| {
+        let mut vm_instance = vm::VM::new();
+        vm_instance.load_obj_str(&build_obj_content(quad_gen, &ast, "padded.bd")).unwrap();
+        vm_instance.capture_output();
+        (vm_instance.run(), vm_instance.take_output())
+    };
+    assert_eq!(run(&plain), (Ok(0), vec!["12".to_string()]));
+    assert_eq!(run(&padded), run(&plain));
+    assert_eq!(run(&compacted), run(&plain));
+    assert!(padded.format_listing().contains("NOP"));
+    println!("\nNOP padding test passed");
+}
+
+#[test]
+fn test_print_items_with_separators_and_endl() {
+    let program = r#"
+    program printing;
+    var a, b, c: int; f: float;
+    main {
+        a = 1;
+        b = 2;
+        c = 3;
+        f = 0.5;
+        print(a, sep(" | "), b, c);
+        print("a =", a, "and f =", f);
+        print(a, sep(", "), b, endl, c, a > b);
+        print(sep("-"), "x", "y", endl);
+        print(endl);
+        print(a);
+    }
+    end
+    "#;
+    let (result, output) = compile_and_run(program);
+    assert_eq!(result, Ok(0));
+    assert_eq!(output, vec![
+        "1 | 2 | 3",
+        "a = 1 and f = 0.5",
+        "1, 2",
+        "3, false",
+        "x-y",
+        "",
+        "1",
+    ]);
+    println!("\nPrint items test passed");
+}
+
+#[test]
+fn test_param_indices_are_contiguous() {
+    let program = r#"
+    program params;
+    var total: int;
+    int sum3(a: int, b: int, c: int) [
+        {
+            return(a + b + c);
+        }
+    ];
+    main {
+        total = sum3(1, sum3(2, 3, 4), 5);
+        print(total);
+    }
+    end
+    "#;
+    let ast = babyduck::ProgramParser::new().parse(program).unwrap();
+    let mut quad_gen = QuadrupleGenerator::new();
+    quad_gen.set_function_directory(FunctionDirectory::from_program(&ast).unwrap());
+    quad_gen.generate_for_program(&ast).unwrap();
+
+    // Both calls, the nested one included, stage exactly PARAM 0, 1, 2 between ERA and GOSUB
+    let quads = quad_gen.get_quadruples();
+    let eras: Vec<usize> = (0..quads.len()).filter(|&idx| quads[idx].operation == OpCode::ERA).collect();
+    assert_eq!(eras.len(), 2);
+    for era in eras {
+        let indices: Vec<i32> = quads.iter().skip(era + 1)
+            .take_while(|quad| quad.operation == OpCode::PARAM)
+            .map(|quad| quad.result)
+            .collect();
+        assert_eq!(indices, vec![0, 1, 2]);
+        assert_eq!(quads[era + 4].operation, OpCode::GOSUB);
+    }
+    let (_, output) = compile_and_run(program);
+    assert_eq!(output, vec!["15"]);
+    println!("\nContiguous PARAM indices test passed");
+}
+
+#[test]
+fn test_rust_source_matches_the_vm() {
+    let program = r#"
+    program transpiled;
+    var i, total: int;
+    var ratio: float;
+    var done: bool;
+    int square(n: int) [
+        {
+            return(n * n);
+        }
+    ];
+    void report(label: int, value: float) [
+        {
+            print("report", label, sep(": "), value);
+        }
+    ];
+    int fact(n: int) [
+        {
+            if (n < 2) {
+                return(1);
+            }
+            return(n * fact(n - 1));
+        }
+    ];
+    main {
+        print(fact(5));
+        i = 0;
+        total = 0;
+        while (i < 5) do {
+            total = total + square(i);
+            i = i + 1;
+        };
+        ratio = total / 4.0;
+        done = total > 20;
+        report(total, ratio);
+        print(done, fmod(7.5, 2.0), endl, 6 << 2);
+        if (done) {
+            exit(total - 28);
+        }
+    }
+    end
+    "#;
+    let (vm_result, vm_output) = compile_and_run(program);
+    assert_eq!(vm_result, Ok(2));
+    assert_eq!(vm_output[0], "120");
+
+    // Compile the transpiled function into a small executable and run it next to the VM
+    let compiled = compiler::compile_source(program).expect("Program should compile");
+    let dir = std::env::temp_dir().join(format!("babyduck_transpiled_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let source_path = dir.join("transpiled.rs");
+    let main_fn = "fn main() {\n    match run() {\n        Ok(code) => std::process::exit(code),\n        Err(e) => { eprintln!(\"{}\", e); std::process::exit(101) }\n    }\n}\n";
+    std::fs::write(&source_path, format!("{}\n{}", compiled.to_rust_source(), main_fn)).unwrap();
+    let binary_path = dir.join("transpiled");
+    let rustc = std::env::var("RUSTC").unwrap_or_else(|_| "rustc".to_string());
+    let build = std::process::Command::new(rustc)
+        .arg(&source_path).arg("-o").arg(&binary_path)
+        .output().expect("rustc should be available");
+    assert!(build.status.success(), "Transpiled source should compile:\n{}", String::from_utf8_lossy(&build.stderr));
+    let diagnostics = String::from_utf8_lossy(&build.stderr);
+    assert!(!diagnostics.contains("warning"), "Transpiled source should compile without warnings:\n{}", diagnostics);
+
+    let run = std::process::Command::new(&binary_path).output().unwrap();
+    let output: Vec<String> = String::from_utf8_lossy(&run.stdout).lines().map(|line| line.to_string()).collect();
+    std::fs::remove_dir_all(&dir).ok();
+    assert_eq!(output, vm_output);
+    assert_eq!(run.status.code(), Some(2));
+    println!("\nRust source transpilation test passed");
+}
+
+#[test]
+fn test_program_scope_resolves_to_globals() {
+    let program = r#"
+    program scoped;
+    var x: int;
+    var y: float;
+    void f(x: float) [
+        {
+            y = x;
+        }
+    ];
+    main {
+        x = 1;
+    }
+    end
+    "#;
+    let ast = babyduck::ProgramParser::new().parse(program).unwrap();
+    let directory = FunctionDirectory::from_program(&ast).unwrap();
+
+    let global_x = directory.get_variable_address("global", "x");
+    assert!(global_x.is_some());
+    assert_eq!(directory.get_variable_address("scoped", "x"), global_x);
+    assert!(matches!(directory.get_variable_type("scoped", "x"), Some(ast::Type::Int)));
+    assert_eq!(directory.get_variable_address("scoped", "y"), directory.get_variable_address("global", "y"));
+    assert_eq!(directory.get_variable_address("scoped", "missing"), None);
+    // A real function scope still sees its own parameter first
+    assert_ne!(directory.get_variable_address("f", "x"), global_x);
+    println!("\nProgram scope lookup test passed");
+}
+
+#[test]
+fn test_warnings_as_errors() {
+    use compiler::quadruples::CompileError;
+    let program = r#"
+    program lint_only;
+    var x: int;
+    void unused() [
+        {
+            x = 2;
+        }
+    ];
+    main {
+        x = 1;
+        print(x);
+    }
+    end
+    "#;
+
+    let compiled = compiler::compile_source(program).expect("A lint alone shouldn't fail the compilation");
+    assert_eq!(compiled.warnings.len(), 1, "{:?}", compiled.warnings);
+
+    let options = compiler::CompileOptions { warnings_as_errors: true };
+    match compiler::compile_source_with_options(program, &options) {
+        Err(CompileError::Warning(message)) => assert!(message.contains("'unused'"), "{}", message),
+        other => panic!("Expected the lint as an error, got {:?}", other.map(|c| c.warnings)),
+    }
+
+    // The directory's warnings are promoted too, as long as they are left in it
+    let shadowing = "program p; var x: int; void f(x: int) [ { print(x); } ]; main { f(1); } end";
+    assert!(compiler::compile_source(shadowing).is_ok_and(|c| c.warnings.len() == 1));
+    let ast = babyduck::ProgramParser::new().parse(shadowing).unwrap();
+    let mut quad_gen = QuadrupleGenerator::new();
+    quad_gen.set_function_directory(FunctionDirectory::from_program(&ast).unwrap());
+    quad_gen.treat_warnings_as_errors();
+    let result = quad_gen.generate_for_program(&ast);
+    assert!(result.as_ref().is_err_and(|e| e.contains("Warning treated as an error") && e.contains("'x'")), "{:?}", result);
+    assert!(quad_gen.take_warnings().is_empty());
+    println!("\nWarnings as errors test passed");
+}
+
+#[test]
+fn test_int_literal_assigned_to_float() {
+    let program = r#"
+    program widen;
+    var f: float;
+    main {
+        f = 3;
+        print(f);
+        print(f / 2);
+    }
+    end
+    "#;
+    let ast = babyduck::ProgramParser::new().parse(program).unwrap();
+    let mut quad_gen = QuadrupleGenerator::new();
+    quad_gen.set_function_directory(FunctionDirectory::from_program(&ast).unwrap());
+    quad_gen.generate_for_program(&ast).unwrap();
+
+    // The literal goes through the int pool and is widened by the assignment itself
+    let assign = quad_gen.get_quadruples().iter().find(|quad| quad.operation == OpCode::ASSIGN).unwrap();
+    assert_eq!(quad_gen.get_int_constant_value(assign.arg1), Some(3));
+    assert!(quad_gen.get_float_constants().is_empty());
+    assert!(quad_gen.get_name_by_address(assign.arg1).starts_with("3 (cte_int)"));
+    assert_eq!(quad_gen.get_name_by_address(assign.result), "global.f (2000)");
+
+    let (result, output) = compile_and_run(program);
+    assert_eq!(result, Ok(0));
+    assert_eq!(output, vec!["3", "1.5"]);
+    println!("\nInt literal assigned to float test passed");
+}
+
+#[test]
+fn test_builtin_call_errors_name_the_signature() {
+    let compile = |main_body: &str| {
+        let source = format!("program p; var x: float; var b: bool; main {{ {} }} end", main_body);
+        let ast = babyduck::ProgramParser::new().parse(&source).unwrap();
+        let mut quad_gen = QuadrupleGenerator::new();
+        quad_gen.set_function_directory(FunctionDirectory::from_program(&ast).unwrap());
+        quad_gen.generate_for_program(&ast)
+    };
+
+    let too_few = compile("x = fmod(1.5);").unwrap_err();
+    assert_eq!(too_few, "Built-in 'fmod' called with 1 arguments, but expected 2. Signature: fmod(Int|Float, Int|Float) -> Float.");
+
+    let wrong_type = compile("b = true; x = float(b);").unwrap_err();
+    assert_eq!(wrong_type, "Type mismatch for argument 1 of built-in 'float'. Expected Int or Float, got Bool. Signature: float(Int|Float) -> Float.");
+
+    let second_argument = compile("x = fmod(7, true);").unwrap_err();
+    assert!(second_argument.contains("argument 2 of built-in 'fmod'"), "{}", second_argument);
+
+    assert!(compile("x = fmod(7, 2) + float(1);").is_ok());
+    println!("\nBuilt-in call error test passed");
+}
+
+#[test]
+fn test_link_two_files_into_one_obj() {
+    let app = r#"
+    program app;
+    var x: int;
+    int wrap(n: int) [
+        {
+            return(twice(n) + 1);
+        }
+    ];
+    main {
+        x = 4;
+        greet(x);
+        print(twice(x));
+        print(wrap(x));
+    }
+    end
+    "#;
+    let shapes = r#"
+    program shapes;
+    var calls: int;
+    int twice(n: int) [
+        {
+            return(n * 2);
+        }
+    ];
+    void greet(n: int) [
+        {
+            calls = n;
+            print("hello", calls);
+        }
+    ];
+    main {
+        print(0);
+    }
+    end
+    "#;
+
+    let linked = compiler::compile_linked_sources(app, &[shapes]).expect("The files should link");
+    let mut vm_instance = vm::VM::new();
+    vm_instance.load_obj_str(&linked.obj_content).unwrap();
+    vm_instance.capture_output();
+    assert_eq!(vm_instance.run(), Ok(0));
+    // Only app's main runs, as the single entry point, and app's own functions can call the modules'
+    assert_eq!(vm_instance.take_output(), vec!["hello 4", "8", "9"]);
+
+    // Compiled alone, app can't find the functions it calls
+    assert!(compiler::compile_source(app).is_err());
+
+    let clashing = "program other; int twice(m: int) [ { return(m + m); } ]; main { } end";
+    let error = compiler::compile_linked_sources(app, &[shapes, clashing]).unwrap_err();
+    assert!(error.to_string().contains("twice"), "{}", error);
+    println!("\nLinked files test passed");
+}
+
+#[test]
+fn test_gosub_rejects_a_gap_in_params() {
+    let program = r#"
+    program gap;
+    void show(a: int, b: int, c: int) [
+        {
+            print(a + b + c);
+        }
+    ];
+    main {
+        show(1, 2, 3);
+    }
+    end
+    "#;
+    let ast = babyduck::ProgramParser::new().parse(program).unwrap();
+    let mut quad_gen = QuadrupleGenerator::new();
+    quad_gen.set_function_directory(FunctionDirectory::from_program(&ast).unwrap());
+    quad_gen.generate_for_program(&ast).unwrap();
+
+    // Simulate a generator bug: the second argument goes to index 2, leaving index 1 unset
+    let quads = quad_gen.get_quadruples_mut();
+    let second = quads.iter().position(|quad| quad.operation == OpCode::PARAM && quad.result == 1).unwrap();
+    quads[second].result = 2;
+
+    let mut vm_instance = vm::VM::new();
+    vm_instance.load_obj_str(&build_obj_content(&quad_gen, &ast, "gap.bd")).unwrap();
+    vm_instance.capture_output();
+    let result = vm_instance.run();
+    assert_eq!(result, Err("GOSUB: Parameter 1 of function 'show' was never set by a PARAM".to_string()));
+    assert!(vm_instance.take_output().is_empty());
+    println!("\nPARAM gap test passed");
+}
+
+#[test]
+fn test_memory_write_log() {
+    use vm::{MemoryWrite, VMValue};
+    let program = r#"
+    program writes;
+    var x: int;
+    var f: float;
+    main {
+        x = 1;
+        x = x + 2;
+        f = x;
+    }
+    end
+    "#;
+    let obj_content = compile_to_obj(program);
+    let mut vm_instance = vm::VM::new();
+    vm_instance.load_obj_str(&obj_content).unwrap();
+    vm_instance.capture_output();
+    assert_eq!(vm_instance.run(), Ok(0));
+    assert!(vm_instance.write_log().is_empty(), "Logging is off by default");
+
+    vm_instance.reset();
+    vm_instance.set_write_logging(true);
+    assert_eq!(vm_instance.run(), Ok(0));
+    let write = |address, old, new| MemoryWrite { address, old, new };
+    assert_eq!(vm_instance.write_log(), [
+        write(1000, None, VMValue::Int(1)),
+        write(5000, None, VMValue::Int(3)),
+        write(1000, Some(VMValue::Int(1)), VMValue::Int(3)),
+        // The float cell logs the promoted value
+        write(2000, None, VMValue::Float(3.0)),
+    ]);
+
+    // A new run starts a new log
+    vm_instance.reset();
+    assert!(vm_instance.write_log().is_empty());
+    println!("\nMemory write log test passed");
+}
+
+#[test]
+fn test_current_function_name_follows_calls() {
+    let program = r#"
+    program stepping;
+    var x: int;
+    void inner(n: int) [ { x = n; } ];
+    void outer(n: int) [ { inner(n + 1); print(x); } ];
+    main {
+        outer(1);
+        print(x);
+    }
+    end
+    "#;
+    let mut vm_instance = vm::VM::new();
+    vm_instance.load_obj_str(&compile_to_obj(program)).unwrap();
+    vm_instance.capture_output();
+    assert_eq!(vm_instance.current_function_name(), "main");
+
+    // Record the function after every step where it changes
+    let mut names = vec![vm_instance.current_function_name().to_string()];
+    while vm_instance.run_step().unwrap().is_none() {
+        let name = vm_instance.current_function_name();
+        if names.last().map(String::as_str) != Some(name) {
+            names.push(name.to_string());
+        }
+    }
+    assert_eq!(names, vec!["main", "outer", "inner", "outer", "main"]);
+    assert_eq!(vm_instance.take_output(), vec!["2", "2"]);
+    println!("\nCurrent function name test passed");
+}
+
+#[test]
+fn test_negative_values_print_as_numbers() {
+    let program = r#"
+    program negatives;
+    var i, zero: int;
+    var f: float;
+    var b: bool;
+    main {
+        zero = 0;
+        i = 0 - 7;
+        f = 0.0 - 3.5;
+        b = i < zero;
+        print(i);
+        print(f);
+        print(zero - 7);
+        print(0.5 - 4.0);
+        print(i * 1, f / 1, zero - 1);
+        print(b);
+    }
+    end
+    "#;
+    let (result, output) = compile_and_run(program);
+    assert_eq!(result, Ok(0));
+    assert_eq!(output, vec!["-7", "-3.5", "-7", "-3.5", "-7 -3.5 -1", "true"]);
+    println!("\nNegative values print test passed");
+}
+
+#[test]
+fn test_operator_precedence_and_associativity() {
+    // Rebuild the expression from the quadruples, fully parenthesized, to see how it was grouped
+    let grouping = |expression: &str| {
+        let target = if expression.split_whitespace().any(|token| ["==", "!=", ">", "<"].contains(&token)) { "r" } else { "x" };
+        let source = format!("program p; var a, b, c, d, x: int; var r: bool; main {{ {} = {}; }} end", target, expression);
+        let ast = babyduck::ProgramParser::new().parse(&source).unwrap();
+        let directory = FunctionDirectory::from_program(&ast).unwrap();
+        let mut quad_gen = QuadrupleGenerator::new();
+        quad_gen.set_function_directory(directory.clone());
+        quad_gen.generate_for_program(&ast).unwrap();
+
+        let mut rendered: std::collections::HashMap<i32, String> = ["a", "b", "c", "d"].iter()
+            .map(|name| (directory.get_variable_address("global", name).unwrap(), name.to_string()))
+            .collect();
+        let operand = |rendered: &std::collections::HashMap<i32, String>, address: i32| rendered.get(&address).cloned()
+            .or_else(|| quad_gen.get_int_constant_value(address).map(|value| value.to_string()))
+            .unwrap_or_else(|| panic!("Unknown operand {} in {}", address, expression));
+        for quad in quad_gen.get_quadruples() {
+            let symbol = match quad.operation {
+                OpCode::ADD => "+", OpCode::SUB => "-", OpCode::MULT => "*", OpCode::DIV => "/",
+                OpCode::GT => ">", OpCode::LT => "<", OpCode::EQ => "==", OpCode::NEQ => "!=",
+                OpCode::BAND => "&", OpCode::BOR => "|", OpCode::BXOR => "^", OpCode::SHL => "<<", OpCode::SHR => ">>",
+                OpCode::ASSIGN => return operand(&rendered, quad.arg1),
+                _ => continue,
+            };
+            let text = format!("({} {} {})", operand(&rendered, quad.arg1), symbol, operand(&rendered, quad.arg2));
+            rendered.insert(quad.result, text);
+        }
+        panic!("No assignment generated for {}", expression);
+    };
+
+    let cases = [
+        ("a + b * c", "(a + (b * c))"),
+        ("a - b - c", "((a - b) - c)"),
+        ("a / b * c", "((a / b) * c)"),
+        ("(a + b) * c", "((a + b) * c)"),
+        ("a - (b - c)", "(a - (b - c))"),
+        ("a * b + c / d - a", "(((a * b) + (c / d)) - a)"),
+        ("a << b + c", "(a << (b + c))"),
+        ("a << b << c", "((a << b) << c)"),
+        ("a | b & c", "((a | b) & c)"),
+        ("a & b << c", "(a & (b << c))"),
+        ("a ^ b * 2 | c", "((a ^ (b * 2)) | c)"),
+        ("a + b > c * d", "((a + b) > (c * d))"),
+        ("a ^ b == c | d", "((a ^ b) == (c | d))"),
+        ("a >> 1 != b & 2", "((a >> 1) != (b & 2))"),
+        ("(a < b) == (c > d)", "((a < b) == (c > d))"),
+    ];
+    for (expression, expected) in cases {
+        assert_eq!(grouping(expression), expected, "Grouping of {}", expression);
+    }
+    println!("\nOperator precedence test passed");
+}
+
+/// Run the command line pipeline on `input_filename`, giving its result and what it wrote
+/// to stdout and stderr
+#[cfg(test)]
+fn build_and_run_captured(options: &BuildOptions, input_filename: &str) -> (Result<i32, String>, String, String) {
+    let out = Rc::new(RefCell::new(Vec::new()));
+    let err = Rc::new(RefCell::new(Vec::new()));
+    let console = Console { out: out.clone(), err: err.clone() };
+    let result = build_and_run(options, input_filename, &[], &console);
+    let text = |buffer: Rc<RefCell<Vec<u8>>>| String::from_utf8(buffer.take()).unwrap();
+    (result, text(out), text(err))
+}
+
+#[test]
+fn test_output_path_turns_off_the_automatic_run() {
+    let parse = |flags: &[&str]| {
+        let mut args: Vec<String> = flags.iter().map(|flag| flag.to_string()).collect();
+        let options = take_output_args(&mut args);
+        (options, args)
+    };
+    assert_eq!(parse(&["compiler", "prog.bd"]), (Ok((None, true)), vec!["compiler".to_string(), "prog.bd".to_string()]));
+    assert_eq!(parse(&["compiler", "-o", "out.obj", "prog.bd"]).0, Ok((Some("out.obj".to_string()), false)));
+    assert_eq!(parse(&["compiler", "-o", "out.obj", "--run", "prog.bd"]).0, Ok((Some("out.obj".to_string()), true)));
+    assert_eq!(parse(&["compiler", "--no-run", "prog.bd"]).0, Ok((None, false)));
+    assert!(parse(&["compiler", "prog.bd", "-o"]).0.is_err());
+
+    // Compiling with -o writes the object file and runs nothing
+    let source_path = std::env::temp_dir().join(format!("babyduck_no_run_{}.bd", std::process::id()));
+    let obj_path = source_path.with_extension("obj");
+    fs::write(&source_path, "program quiet; var x: int; main { x = 5; print(x); } end").unwrap();
+    let options = BuildOptions { output_path: Some(obj_path.to_str().unwrap().to_string()), run_vm: false, ..Default::default() };
+    let (result, out, err) = build_and_run_captured(&options, source_path.to_str().unwrap());
+    fs::remove_file(&source_path).unwrap();
+    assert_eq!(result, Ok(0), "{}", err);
+    let obj_content = fs::read_to_string(&obj_path).unwrap();
+    fs::remove_file(&obj_path).unwrap();
+    assert!(obj_content.contains("QUADRUPLES:"), "{}", obj_content);
+    assert!(out.ends_with(&format!("Output written to {}\n", obj_path.display())), "{}", out);
+    assert!(!out.lines().any(|line| line == "5" || line.contains("VM")), "{}", out);
+
+    // The object file left behind still loads and runs
+    let mut vm_instance = vm::VM::new();
+    vm_instance.load_obj_str(&obj_content).unwrap();
+    vm_instance.capture_output();
+    assert_eq!(vm_instance.run(), Ok(0));
+    assert_eq!(vm_instance.take_output(), vec!["5"]);
+    println!("\nOutput path and run flags test passed");
+}
+
+#[test]
+fn test_literals_share_constants_across_scopes() {
+    let program = r#"
+    program shared;
+    var x: int;
+    var f: float;
+    int bump(n: int) [
+        {
+            f = 2.5;
+            return(n + 5);
+        }
+    ];
+    main {
+        x = 5;
+        x = bump(x) + 5;
+        f = f * 2.5;
+        print(x);
+    }
+    end
+    "#;
+    let ast = babyduck::ProgramParser::new().parse(program).unwrap();
+    let mut quad_gen = QuadrupleGenerator::new();
+    quad_gen.set_function_directory(FunctionDirectory::from_program(&ast).unwrap());
+    quad_gen.generate_for_program(&ast).unwrap();
+
+    // The function and main read the one constant cell for each literal
+    assert_eq!(quad_gen.get_int_constants(), vec![(5, quadruples::MemoryAddresses::CTE_INT_START)]);
+    assert_eq!(quad_gen.get_float_constants(), vec![(2.5, quadruples::MemoryAddresses::CTE_FLOAT_START)]);
+    let obj_content = build_obj_content(&quad_gen, &ast, "shared.bd");
+    let section = |name: &str| obj_content.split(&format!("{}:\n", name)).nth(1).unwrap()
+        .lines().take_while(|line| !line.starts_with("END_")).count();
+    assert_eq!((section("CONSTANTS_INT"), section("CONSTANTS_FLOAT")), (1, 1));
+
+    let (result, output) = compile_and_run(program);
+    assert_eq!(result, Ok(0));
+    assert_eq!(output, vec!["15"]);
+    println!("\nShared constants across scopes test passed");
+}
+
+#[test]
+fn test_obj_comments_inside_sections_are_ignored() {
+    let obj_content = compile_to_obj("program notes; var x: int; main { x = 4; print(x * 10); } end");
+
+    // Annotate every section, including the data and quadruple lines
+    let mut annotated = String::new();
+    for line in obj_content.lines() {
+        annotated.push_str(line);
+        annotated.push('\n');
+        if line.ends_with(':') {
+            annotated.push_str(&format!("// first line of {}\n", line));
+        } else if !line.is_empty() && !line.starts_with("//") && !line.starts_with("END_") {
+            annotated.push_str("   // explains the line above\n");
+        }
+    }
+    assert!(annotated.contains("CONSTANTS_INT:\n// first line of CONSTANTS_INT:\n"));
+    assert!(annotated.contains("QUADRUPLES:\n// first line of QUADRUPLES:\n"));
+
+    let mut vm_instance = vm::VM::new();
+    vm_instance.load_obj_str(&annotated).unwrap();
+    vm_instance.capture_output();
+    assert_eq!(vm_instance.run(), Ok(0));
+    assert_eq!(vm_instance.take_output(), vec!["40"]);
+    println!("\nComments inside obj sections test passed");
+}
+
+#[test]
+fn test_operator_on_a_bool_call_result_names_the_function() {
+    let compile = |main_body: &str| {
+        let source = format!(r#"
+        program calls;
+        var x: int;
+        var f: float;
+        bool isPositive(v: int) [ {{ return(v > 0); }} ];
+        float half(v: int) [ {{ return(v / 2.0); }} ];
+        main {{ {} }}
+        end
+        "#, main_body);
+        let ast = babyduck::ProgramParser::new().parse(&source).unwrap();
+        let mut quad_gen = QuadrupleGenerator::new();
+        quad_gen.set_function_directory(FunctionDirectory::from_program(&ast).unwrap());
+        quad_gen.generate_for_program(&ast)
+    };
+
+    assert_eq!(compile("x = isPositive(x) + 1;").unwrap_err(),
+        "Function 'isPositive' returns a Bool, which can't be added: Bool + Int");
+    assert_eq!(compile("x = 3 * isPositive(x);").unwrap_err(),
+        "Function 'isPositive' returns a Bool, which can't be multiplied: Int * Bool");
+    assert_eq!(compile("x = half(x) & 1;").unwrap_err(),
+        "Function 'half' returns a Float, which can't be used with '&': Float & Int");
+    let comparison = compile("if (isPositive(x) > 0) { x = 1; }").unwrap_err();
+    assert!(comparison.contains("'isPositive'") && comparison.contains("'>'"), "{}", comparison);
+
+    // Operands that aren't calls keep the general message
+    let general = compile("x = (x > 1) + 1;").unwrap_err();
+    assert!(general.starts_with("Type mismatch"), "{}", general);
+    assert!(compile("f = half(x) + 1; if (isPositive(x) == true) { x = 1; }").is_ok());
+    println!("\nCall result operator error test passed");
+}
+
+#[test]
+fn test_emit_obj_to_a_writer() {
+    let mut args: Vec<String> = ["compiler", "-o", "-", "prog.bd"].iter().map(|arg| arg.to_string()).collect();
+    assert_eq!(take_output_args(&mut args), Ok((Some("-".to_string()), false)));
+
+    let compiled = compiler::compile_source("program piped; var x: int; main { x = 7; print(x * 2); } end").unwrap();
+    let mut emitted: Vec<u8> = Vec::new();
+    compiled.write_obj(&mut emitted).unwrap();
+    let emitted = String::from_utf8(emitted).unwrap();
+    assert_eq!(emitted, compiled.obj_content);
+
+    // The writer gets the same bytes the .obj file would hold
+    let path = std::env::temp_dir().join(format!("babyduck_piped_{}.obj", std::process::id()));
+    write_obj_file(&compiled.obj_content, path.to_str().unwrap()).unwrap();
+    let written = fs::read_to_string(&path).unwrap();
+    fs::remove_file(&path).unwrap();
+    assert_eq!(emitted, written);
+
+    let mut vm_instance = vm::VM::new();
+    vm_instance.load_obj_str(&emitted).unwrap();
+    vm_instance.capture_output();
+    assert_eq!(vm_instance.run(), Ok(0));
+    assert_eq!(vm_instance.take_output(), vec!["14"]);
+
+    // With --run, stdout holds the object text and then the program's output; status lines go to stderr
+    let source_path = std::env::temp_dir().join(format!("babyduck_piped_{}.bd", std::process::id()));
+    fs::write(&source_path, "program piped; var x: int; main { x = 7; print(x * 2); } end").unwrap();
+    let options = BuildOptions { output_path: Some("-".to_string()), run_vm: true, ..Default::default() };
+    let (result, out, err) = build_and_run_captured(&options, source_path.to_str().unwrap());
+    fs::remove_file(&source_path).unwrap();
+    assert_eq!(result, Ok(0), "{}", err);
+    let obj_text = out.strip_suffix("14\n").expect("The program's output follows the object text");
+    assert!(obj_text.contains("QUADRUPLES:") && obj_text.ends_with('\n'), "{}", out);
+    assert!(err.ends_with("VM execution finished successfully.\n"), "{}", err);
+    println!("\nEmit obj to a writer test passed");
+}
+
+#[test]
+fn test_comparison_assigned_to_a_number_is_rejected() {
+    let program = r#"
+    program mixup;
+    var x, a, b: int;
+    var f: float;
+    main {
+        a = 2;
+        b = 1;
+        x = a > b;
+        f = a < b;
+    }
+    end
+    "#;
+
+    let ast = babyduck::ProgramParser::new().parse(program).unwrap();
+    let function_directory = FunctionDirectory::from_program(&ast).unwrap();
+    assert!(!function_directory.is_valid_assignment(&ast::Type::Int, &ast::Type::Bool));
+    assert!(!function_directory.is_valid_assignment(&ast::Type::Float, &ast::Type::Bool));
+
+    let mut quad_gen = QuadrupleGenerator::new();
+    quad_gen.set_function_directory(function_directory);
+    assert!(quad_gen.generate_for_program(&ast).is_err());
+    let errors: Vec<String> = quad_gen.compile_errors().iter().map(|e| e.to_string()).collect();
+    assert_eq!(errors.len(), 2, "{:?}", errors);
+    assert!(errors[0].contains("Type mismatch in assignment to 'x'. Expected Int, got Bool."), "{}", errors[0]);
+    assert!(errors[1].contains("Type mismatch in assignment to 'f'. Expected Float, got Bool."), "{}", errors[1]);
+    println!("\nComparison assigned to a number test passed");
+}
+
+#[test]
+fn test_bool_variables_take_only_bools() {
+    let accepted = r#"
+    program flags;
+    var a, b: int;
+    var flag: bool;
+    main {
+        a = 3;
+        b = 2;
+        flag = true;
+        print(flag);
+        flag = a > b;
+        print(flag);
+    }
+    end
+    "#;
+    let (result, output) = compile_and_run(accepted);
+    assert_eq!(result, Ok(0));
+    assert_eq!(output, vec!["true", "true"]);
+
+    let rejected = [("flag = 3.5;", "Expected Bool, got Float."), ("flag = 2;", "Expected Bool, got Int.")];
+    for (statement, expected) in rejected {
+        let program = format!("program flags; var flag: bool; main {{ {} }} end", statement);
+        match compiler::compile_source(&program) {
+            Err(error) => assert!(error.to_string().contains(&format!("Type mismatch in assignment to 'flag'. {}", expected)), "{}", error),
+            Ok(_) => panic!("'{}' should not compile", statement),
+        }
+    }
+    println!("\nBool assignment test passed");
+}
+
+#[test]
+fn test_time_report_names_each_phase() {
+    let source_path = std::env::temp_dir().join(format!("babyduck_timed_{}.bd", std::process::id()));
+    let obj_path = source_path.with_extension("obj");
+    fs::write(&source_path, "program timed; var x: int; main { x = 6; print(x * 7); } end").unwrap();
+    let options = BuildOptions {
+        time_phases: true,
+        output_path: Some(obj_path.to_str().unwrap().to_string()),
+        run_vm: true,
+        ..Default::default()
+    };
+    let (result, out, report) = build_and_run_captured(&options, source_path.to_str().unwrap());
+    fs::remove_file(&source_path).unwrap();
+    fs::remove_file(&obj_path).unwrap();
+    assert_eq!(result, Ok(0), "{}", report);
+    assert!(out.lines().any(|line| line == "42"), "{}", out);
+
+    // The durations vary from run to run, so only the labels and the unit are checked
+    let labels: Vec<&str> = report.lines().map(|line| line.split(':').next().unwrap()).collect();
+    assert_eq!(labels, vec!["--- Timing ---", "Parsing", "Function directory", "Quadruple generation", "VM execution", "Total"]);
+    assert!(report.lines().skip(1).all(|line| line.ends_with(" ms")), "{}", report);
+    println!("\nTime report test passed");
+}
+
+#[test]
+fn test_one_generator_compiles_programs_in_turn() {
+    let first = r#"
+    program first;
+    var x: int;
+    var f: float;
+    int twice(n: int) [
+        {
+            return(n * 2);
+        }
+    ];
+    main {
+        f = 1.5;
+        x = twice(21);
+        print(x, f);
+    }
+    end
+    "#;
+    let second = "program second; var y: int; main { y = 9; print(y + 1); } end";
+
+    let mut quad_gen = QuadrupleGenerator::new();
+    let mut compile = |source: &str| {
+        let ast = babyduck::ProgramParser::new().parse(source).unwrap();
+        quad_gen.reset_for(FunctionDirectory::from_program(&ast).unwrap());
+        quad_gen.generate_for_program(&ast).unwrap();
+        let obj_content = build_obj_content(&quad_gen, &ast, "test.bd");
+        let mut vm_instance = vm::VM::new();
+        vm_instance.load_obj_str(&obj_content).unwrap();
+        vm_instance.capture_output();
+        assert_eq!(vm_instance.run(), Ok(0));
+        (obj_content, quad_gen.get_quadruples().len(), vm_instance.take_output())
+    };
+
+    let (first_obj, _, first_output) = compile(first);
+    assert_eq!(first_output, vec!["42 1.5"]);
+    let (second_obj, second_quads, second_output) = compile(second);
+    assert_eq!(second_output, vec!["10"]);
+
+    // Nothing from the first program survives into the second
+    assert_eq!(second_obj, compile_to_obj(second));
+    assert_ne!(first_obj, second_obj);
+    let section = |obj: &str, name: &str| obj.lines()
+        .skip_while(|line| *line != format!("{}:", name)).skip(1)
+        .take_while(|line| !line.starts_with("END_"))
+        .map(str::to_string).collect::<Vec<_>>();
+    assert!(section(&second_obj, "CONSTANTS_FLOAT").is_empty());
+    assert_eq!(section(&second_obj, "CONSTANTS_INT"), vec!["9,4000", "1,4001"]);
+    assert_eq!(second_quads, 5, "GOTO, ASSIGN, ADD, PRINT and HALT");
+    println!("\nGenerator reuse test passed");
+}
+
+#[test]
+fn test_verify_structure() {
+    let program = r#"
+    program framed;
+    var x: int;
+    void show(n: int) [
+        {
+            print(n);
+        }
+    ];
+    main {
+        x = 4;
+        show(x);
+    }
+    end
+    "#;
+    let ast = babyduck::ProgramParser::new().parse(program).unwrap();
+    let mut quad_gen = QuadrupleGenerator::new();
+    quad_gen.set_function_directory(FunctionDirectory::from_program(&ast).unwrap());
+    quad_gen.generate_for_program(&ast).unwrap();
+    assert_eq!(quad_gen.verify_structure(), Ok(()));
+
+    // GOTO main pointing into show instead of main
+    let goto_main = quad_gen.get_quadruples()[0].clone();
+    quad_gen.get_quadruples_mut()[0].result = 1;
+    let error = quad_gen.verify_structure().expect_err("A GOTO into show should fail");
+    assert!(error.contains("jumps to 1, but main starts at"), "{}", error);
+    quad_gen.get_quadruples_mut()[0].result = 999;
+    let error = quad_gen.verify_structure().expect_err("A GOTO past the end should fail");
+    assert!(error.contains("outside the"), "{}", error);
+    quad_gen.get_quadruples_mut()[0] = goto_main;
+
+    // Quad 0 that isn't a GOTO, then a program that runs off the end without HALT
+    quad_gen.get_quadruples_mut().push_front(quadruples::Quadruple::new(OpCode::NOP, -1, -1, -1));
+    let error = quad_gen.verify_structure().expect_err("A NOP at quad 0 should fail");
+    assert!(error.starts_with("Quadruple 0 should be GOTO main"), "{}", error);
+    quad_gen.get_quadruples_mut().pop_front();
+    quad_gen.get_quadruples_mut().pop_back();
+    let error = quad_gen.verify_structure().expect_err("A missing HALT should fail");
+    assert!(error.starts_with("The last quadruple should be HALT"), "{}", error);
+    println!("\nVerify structure test passed");
+}
+
+#[test]
+fn test_mixed_parameter_types_get_their_own_conversions() {
+    let program = r#"
+    program mixed;
+    var i: int;
+    void f(a: float, b: int) [
+        {
+            print(a / 2);
+            print(b / 2);
+            print(a, b);
+        }
+    ];
+    main {
+        f(1, 2);
+        i = 7;
+        f(i, i);
+        f(2.5, 3);
+    }
+    end
+    "#;
+    let (result, output) = compile_and_run(program);
+    assert_eq!(result, Ok(0));
+    // a holds a float even when passed an int, so a / 2 keeps its fraction; b stays an int
+    assert_eq!(output, vec!["0.5", "1", "1 2", "3.5", "3", "7 7", "1.25", "1", "2.5 3"]);
+    println!("\nMixed parameter types test passed");
+}
+
+#[test]
+fn test_recursive_functions_get_their_own_frames() {
+    let program = r#"
+    program recursion;
+    var r: int;
+    int fact(n: int) [
+        {
+            if (n < 2) {
+                return(1);
+            } else {
+                return(n * fact(n - 1));
+            }
+        }
+    ];
+    int fib(n: int) [
+        var a: int;
+        {
+            if (n < 2) {
+                return(n);
+            }
+            a = fib(n - 1);
+            return(a + fib(n - 2));
+        }
+    ];
+    void countdown(n: int) [
+        var shown: int;
+        {
+            shown = n * 10;
+            if (n > 0) {
+                countdown(n - 1);
+            }
+            print(shown);
+        }
+    ];
+    main {
+        r = fact(5);
+        print(r);
+        print(fib(10));
+        countdown(2);
+    }
+    end
+    "#;
+    let (result, output) = compile_and_run(program);
+    assert_eq!(result, Ok(0));
+    // Each activation keeps its own n, locals and partial results across the calls it makes
+    assert_eq!(output, vec!["120", "55", "0", "10", "20"]);
+
+    let obj_content = compile_to_obj(program);
+    let frames: Vec<&str> = obj_content.lines()
+        .skip_while(|line| *line != "FUNCTION_FRAMES:").skip(1)
+        .take_while(|line| !line.starts_with("END_"))
+        .collect();
+    assert_eq!(frames.iter().map(|line| line.split(',').next().unwrap()).collect::<Vec<_>>(), vec!["countdown", "fact", "fib"]);
+
+    // The host API runs a function in a frame of its own as well
+    let mut vm_instance = vm::VM::new();
+    vm_instance.load_obj_str(&obj_content).unwrap();
+    assert_eq!(vm_instance.call_function("fact", &[compiler::Value::Int(6)]), Ok(Some(compiler::Value::Int(720))));
+    println!("\nRecursive function frames test passed");
+}
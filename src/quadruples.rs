@@ -1,6 +1,7 @@
-use std::collections::{VecDeque};
-use crate::ast::{Statement, Expression, Operator, Type, PrintStatement};
-use crate::function_directory::FunctionDirectory;
+use std::cell::OnceCell;
+use std::collections::{HashMap, HashSet, VecDeque};
+use crate::ast::{Statement, Expression, Operator, Type, PrintStatement, PrintItem, Builtin, VarDeclaration};
+use crate::function_directory::{FunctionDirectory, FunctionInfo};
 
 /// Represents a quadruple instruction in the intermediate code with memory addresses
 #[derive(Debug, Clone)]
@@ -14,6 +15,7 @@ pub struct Quadruple {
 /// Operation codes for quadruples
 pub struct OpCode;
 impl OpCode {
+    pub const NOP: i32 = 0;     // Does nothing; stands in for a removed quadruple so positions don't shift
     pub const ASSIGN: i32 = 1;
     pub const ADD: i32 = 4;
     pub const SUB: i32 = 5;
@@ -23,7 +25,17 @@ impl OpCode {
     pub const LT: i32 = 9;
     pub const EQ: i32 = 10;
     pub const NEQ: i32 = 11;
-    pub const PRINT: i32 = 20;
+    pub const FMOD: i32 = 12;   // Float remainder, result is always a float
+    pub const BAND: i32 = 13;   // Bitwise and of two ints
+    pub const BOR: i32 = 14;    // Bitwise or of two ints
+    pub const BXOR: i32 = 15;   // Bitwise xor of two ints
+    pub const SHL: i32 = 16;    // Left shift of an int; the amount must be 0..32
+    pub const SHR: i32 = 17;    // Arithmetic right shift of an int; the amount must be 0..32
+    pub const PRINT: i32 = 20; // (PRINT, value, base or -1, suffix string constant or -1)
+    pub const READ: i32 = 21;  // (READ, position, count, target) - one per target of a read statement
+    pub const LABEL: i32 = 22; // (LABEL, string constant, -1, -1) - prints the text verbatim
+    pub const PRINT_PART: i32 = 23; // (PRINT_PART, value or -1, -1, string constant or -1) - adds to the current line
+    pub const PRINT_LINE: i32 = 24; // (PRINT_LINE, -1, -1, -1) - prints the current line and starts a new one
     pub const GOTO: i32 = 30;
     pub const GOTOF: i32 = 31;
     pub const GOTOT: i32 = 32; 
@@ -33,7 +45,27 @@ impl OpCode {
     pub const PARAM: i32 = 41;  // Parameter passing
     pub const GOSUB: i32 = 42;  // Go to Subroutine / Function Call
     pub const ENDFUNC: i32 = 43; // End of Function / Return
+    pub const RETURN: i32 = 44;  // (RETURN, value, -1, result cell) - store the result and return
     pub const HALT: i32 = 50; // End of Program
+    pub const EXIT: i32 = 51; // End of Program with an explicit exit code
+
+    /// Every opcode the generator can emit; the VM must handle each of them
+    pub const ALL: &'static [i32] = &[
+        Self::NOP, Self::ASSIGN, Self::ADD, Self::SUB, Self::MULT, Self::DIV,
+        Self::GT, Self::LT, Self::EQ, Self::NEQ, Self::FMOD,
+        Self::BAND, Self::BOR, Self::BXOR, Self::SHL, Self::SHR,
+        Self::PRINT, Self::READ, Self::LABEL, Self::PRINT_PART, Self::PRINT_LINE,
+        Self::GOTO, Self::GOTOF, Self::GOTOT,
+        Self::ERA, Self::PARAM, Self::GOSUB, Self::ENDFUNC, Self::RETURN,
+        Self::HALT, Self::EXIT,
+    ];
+}
+
+/// Base indicators carried in arg2 of PRINT quadruples (-1 prints the value as is)
+pub struct PrintBase;
+impl PrintBase {
+    pub const HEX: i32 = 16;
+    pub const BIN: i32 = 2;
 }
 
 /// Memory address ranges
@@ -47,6 +79,7 @@ impl MemoryAddresses {
     pub const TEMP_INT_START: i32 = 5000;
     pub const TEMP_FLOAT_START: i32 = 6000;
     pub const TEMP_BOOL_START: i32 = 7000; // Temporary boolean variables
+    pub const TEMP_BOOL_END: i32 = 8000;   // First address past every segment
 }
 
 impl Quadruple {
@@ -62,6 +95,7 @@ impl Quadruple {
     pub fn to_string(&self) -> String {
         // Map operation code back to readable string for debugging
         let op_str = match self.operation {
+            OpCode::NOP => "NOP",
             OpCode::ASSIGN => "=",
             OpCode::ADD => "+",
             OpCode::SUB => "-",
@@ -71,7 +105,17 @@ impl Quadruple {
             OpCode::LT => "<",
             OpCode::EQ => "==",
             OpCode::NEQ => "!=",
+            OpCode::FMOD => "FMOD",
+            OpCode::BAND => "&",
+            OpCode::BOR => "|",
+            OpCode::BXOR => "^",
+            OpCode::SHL => "<<",
+            OpCode::SHR => ">>",
             OpCode::PRINT => "PRINT",
+            OpCode::READ => "READ",
+            OpCode::LABEL => "LABEL",
+            OpCode::PRINT_PART => "PRINT_PART",
+            OpCode::PRINT_LINE => "PRINT_LINE",
             OpCode::GOTO => "GOTO",
             OpCode::GOTOF => "GOTOF",
             OpCode::GOTOT => "GOTOT",
@@ -79,7 +123,9 @@ impl Quadruple {
             OpCode::PARAM => "PARAM",
             OpCode::GOSUB => "GOSUB",
             OpCode::ENDFUNC => "ENDFUNC",
+            OpCode::RETURN => "RETURN",
             OpCode::HALT => "HALT",
+            OpCode::EXIT => "EXIT",
             _ => "UNKNOWN_OP",
         };
 
@@ -87,8 +133,15 @@ impl Quadruple {
     }
 
     pub fn to_string_with_names(&self, qg: &QuadrupleGenerator) -> String {
+        let [op_str, arg1_name, arg2_name, result_name] = self.named_columns(qg);
+        format!("({}, {}, {}, {})", op_str, arg1_name, arg2_name, result_name)
+    }
+
+    /// Operator and operands with variable names and constant values filled in
+    fn named_columns(&self, qg: &QuadrupleGenerator) -> [String; 4] {
         // Map operation code back to readable string for debugging
         let op_str = match self.operation {
+            OpCode::NOP => "NOP",
             OpCode::ASSIGN => "=",
             OpCode::ADD => "+",
             OpCode::SUB => "-",
@@ -98,7 +151,17 @@ impl Quadruple {
             OpCode::LT => "<",
             OpCode::EQ => "==",
             OpCode::NEQ => "!=",
+            OpCode::FMOD => "FMOD",
+            OpCode::BAND => "&",
+            OpCode::BOR => "|",
+            OpCode::BXOR => "^",
+            OpCode::SHL => "<<",
+            OpCode::SHR => ">>",
             OpCode::PRINT => "PRINT",
+            OpCode::READ => "READ",
+            OpCode::LABEL => "LABEL",
+            OpCode::PRINT_PART => "PRINT_PART",
+            OpCode::PRINT_LINE => "PRINT_LINE",
             OpCode::GOTO => "GOTO",
             OpCode::GOTOF => "GOTOF",
             OpCode::GOTOT => "GOTOT",
@@ -106,30 +169,97 @@ impl Quadruple {
             OpCode::PARAM => "PARAM",
             OpCode::GOSUB => "GOSUB",
             OpCode::ENDFUNC => "ENDFUNC",
+            OpCode::RETURN => "RETURN",
             OpCode::HALT => "HALT",
+            OpCode::EXIT => "EXIT",
             _ => "UNKNOWN_OP",
         };
 
         // Get variable names or values for the addresses
         let arg1_name = if self.operation == OpCode::ERA || self.operation == OpCode::GOSUB {
             qg.get_function_name_by_start_idx(self.arg1).unwrap_or_else(|| qg.get_name_by_address(self.arg1))
+        } else if self.operation == OpCode::READ {
+            self.arg1.to_string() // Position within the read statement
+        } else if self.operation == OpCode::LABEL {
+            qg.get_string_constant_value(self.arg1).map(|text| format!("{:?}", text)).unwrap_or_else(|| self.arg1.to_string())
         } else {
             qg.get_name_by_address(self.arg1)
         };
         // For PARAM, result is param_index. For GOTO/GOTOF/GOTOT, result is jump target.
         let result_name = if self.operation == OpCode::PARAM || self.operation == OpCode::GOTO || self.operation == OpCode::GOTOF || self.operation == OpCode::GOTOT {
             self.result.to_string() // Show raw number for index/target
+        } else if (self.operation == OpCode::PRINT || self.operation == OpCode::PRINT_PART) && self.result >= 0 {
+            qg.get_string_constant_value(self.result).map(|text| format!("{:?}", text)).unwrap_or_else(|| self.result.to_string())
         } else {
             qg.get_name_by_address(self.result)
         };
-        let arg2_name = qg.get_name_by_address(self.arg2); // Usually -1 for these ops
+        let arg2_name = if self.operation == OpCode::READ {
+            self.arg2.to_string() // Number of targets in the read statement
+        } else {
+            qg.get_name_by_address(self.arg2) // Usually -1 for these ops
+        };
+
+        [op_str.to_string(), arg1_name, arg2_name, result_name]
+    }
+}
 
+/// A semantic error that rejects the program
+#[derive(Debug, Clone, PartialEq)]
+pub enum CompileError {
+    /// A function with a return type can reach its end without a `return`
+    MissingReturn { function: String },
+    /// A void function's call is used as a value
+    VoidInExpression { function: String },
+    /// An expression nests deeper than the generator's limit
+    ExpressionTooDeep { depth: usize, limit: usize },
+    /// If, else and while blocks nest deeper than MAX_NESTING_DEPTH
+    NestingTooDeep { depth: usize, limit: usize },
+    /// The source text is not a valid BabyDuck program
+    Parse(String),
+    /// A broken generator invariant, reported instead of panicking
+    Internal(String),
+    /// A lint warning, promoted to an error by QuadrupleGenerator::treat_warnings_as_errors
+    Warning(String),
+    /// Any other semantic error, described by its message
+    Semantic(String),
+}
 
-        format!("({}, {}, {}, {})", op_str, arg1_name, arg2_name, result_name)
+impl std::fmt::Display for CompileError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CompileError::MissingReturn { function } =>
+                write!(f, "Function '{}' can reach its end without returning a value", function),
+            CompileError::VoidInExpression { function } =>
+                write!(f, "Function '{}' is void and has no value to use in an expression", function),
+            CompileError::ExpressionTooDeep { depth, limit } =>
+                write!(f, "Expression is nested {} levels deep, more than the limit of {}", depth, limit),
+            CompileError::NestingTooDeep { depth, limit } =>
+                write!(f, "Blocks are nested {} levels deep, more than the limit of {}", depth, limit),
+            CompileError::Parse(message) => write!(f, "Parsing failed: {}", message),
+            CompileError::Internal(message) => write!(f, "Internal compiler error: {}", message),
+            CompileError::Warning(message) => write!(f, "Warning treated as an error: {}", message),
+            CompileError::Semantic(message) => write!(f, "{}", message),
+        }
     }
 }
 
+/// How a `while` was lowered to jumps, as found by QuadrupleGenerator::last_loop
+#[derive(Debug, Clone, PartialEq)]
+pub struct LoopLowering {
+    pub condition_start: usize, // First quad of the condition, where the back jump lands
+    pub exit_jump: usize,       // The GOTOF that leaves the loop when the condition is false
+    pub exit_target: usize,     // First quad after the loop
+    pub back_jump: usize,       // The GOTO at the end of the body
+}
+
+/// Default limit for QuadrupleGenerator::set_max_expression_depth
+pub const DEFAULT_MAX_EXPRESSION_DEPTH: usize = 2000;
+
+/// How deeply if, else and while blocks may nest; the generator recurses once per block
+pub const MAX_NESTING_DEPTH: usize = 500;
+
 /// Handles the generation of quadruples for intermediate code
+#[derive(Debug, Clone)]
 pub struct QuadrupleGenerator {
     // Stacks for compilation - renamed to match the image
     p_oper: Vec<i32>,            // operator stack 
@@ -148,13 +278,52 @@ pub struct QuadrupleGenerator {
     // Constant pools for storing literals - use address as index
     int_constants: Vec<i32>,       // Value stored at index [address - CTE_INT_START]
     float_constants: Vec<f64>,     // Value stored at index [address - CTE_FLOAT_START]
-    bool_constants: Vec<bool>,     // New constant pool for booleans
+    // Lookup for deduplication only; the vectors above fix the address and emission order
+    int_constant_index: HashMap<i32, usize>,
+    float_constant_index: HashMap<u64, usize>, // Keyed by bit pattern
+    bool_constants: Vec<Option<bool>>, // Bool literals by TEMP_BOOL index; None for other bool temporaries
+    string_constants: Vec<String>, // Text for LABEL; a string's address is its index here
 
     // Current function scope for variable lookup
     scope_stack: Vec<String>,
 
     // Reference to function directory
     pub(crate) function_directory: Option<FunctionDirectory>,
+
+    // Variable and parameter names by address, built on first use; reset whenever the directory changes
+    address_names: OnceCell<HashMap<i32, String>>,
+    // Temporaries holding a function call's result, with the function's name, for error messages
+    call_results: HashMap<i32, String>,
+
+    // Semantic errors that must abort the compilation
+    semantic_errors: Vec<CompileError>,
+
+    // Non-fatal lint warnings collected during generation
+    warnings: Vec<String>,
+
+    // Reject implicit int-to-float widening; an explicit float(x) is required instead
+    strict: bool,
+    // Lint assignments to parameters, for readers expecting call-by-reference
+    warn_param_assignments: bool,
+    // Fail the compilation on any lint warning
+    warnings_as_errors: bool,
+
+    // Self-recursive calls in tail position become a parameter rebind and a GOTO
+    tail_calls: bool,
+    // Set while generating a statement that is the last thing its function executes
+    tail_position: bool,
+
+    // Arithmetic on two constants is computed at compile time
+    fold_constants: bool,
+    // Run the peephole pass over the finished program
+    peephole: bool,
+    // The peephole pass leaves a NOP where it removes a quadruple instead of renumbering
+    peephole_nops: bool,
+    // Replace reads of write-once globals with their constant value
+    propagate_globals: bool,
+
+    // Deepest expression tree accepted; process_expression recurses once per level
+    max_expression_depth: usize,
 }
 
 impl QuadrupleGenerator {
@@ -170,16 +339,171 @@ impl QuadrupleGenerator {
             temp_bool_counter: MemoryAddresses::TEMP_BOOL_START,
             int_constants: Vec::new(),
             float_constants: Vec::new(),
+            int_constant_index: HashMap::new(),
+            float_constant_index: HashMap::new(),
             bool_constants: Vec::new(),  // Initialize bool constants vector
+            string_constants: Vec::new(),
             scope_stack: vec!["global".to_string()], // Initialize with global scope
-            function_directory: None
-            
+            function_directory: None,
+            address_names: OnceCell::new(),
+            call_results: HashMap::new(),
+            semantic_errors: Vec::new(),
+            warnings: Vec::new(),
+            strict: false,
+            warn_param_assignments: false,
+            warnings_as_errors: false,
+            tail_calls: false,
+            tail_position: false,
+            fold_constants: false,
+            peephole: false,
+            peephole_nops: false,
+            propagate_globals: false,
+            max_expression_depth: DEFAULT_MAX_EXPRESSION_DEPTH,
         }
     }
 
     /// Set the function directory for address resolution
     pub fn set_function_directory(&mut self, directory: FunctionDirectory) {
         self.function_directory = Some(directory);
+        self.address_names.take();
+    }
+
+    /// The function directory in use, with the start quadruples filled in by generation
+    pub fn function_directory(&self) -> Option<&FunctionDirectory> {
+        self.function_directory.as_ref()
+    }
+
+    /// Enable or disable strict mode, where an int value can't be implicitly stored in a float
+    pub fn set_strict(&mut self, strict: bool) {
+        self.strict = strict;
+    }
+
+    /// Warn when a function assigns one of its parameters. The assignment is legal, but
+    /// only changes the function's copy, which surprises readers expecting call-by-reference.
+    pub fn warn_on_parameter_assignment(&mut self) {
+        self.warn_param_assignments = true;
+    }
+
+    /// Fail the compilation on any lint warning, for strict grading. The function directory's
+    /// own warnings count too, unless they were taken from it before generation.
+    pub fn treat_warnings_as_errors(&mut self) {
+        self.warnings_as_errors = true;
+    }
+
+    /// Turn self-recursive tail calls into a jump back to the function start, so deep
+    /// tail recursion doesn't grow the call stack. Must be enabled before generation.
+    pub fn optimize_tail_calls(&mut self) {
+        self.tail_calls = true;
+    }
+
+    /// Have the peephole pass replace the quadruples it removes with NOPs, so every
+    /// quadruple keeps its index and no jump or function start needs renumbering.
+    pub fn pad_removed_quads_with_nops(&mut self) {
+        self.peephole_nops = true;
+    }
+
+    /// Replace reads of globals that main assigns a constant exactly once, before any
+    /// branch, loop or call, with that constant. Must be enabled before generation.
+    pub fn propagate_global_constants(&mut self) {
+        self.propagate_globals = true;
+    }
+
+    /// Select the optimization passes by level. Must be set before generation.
+    ///
+    /// - 0: no optimizations (the default)
+    /// - 1: constant folding
+    /// - 2: constant folding, global constant propagation, the peephole pass and
+    ///   tail-call optimization
+    pub fn set_optimization_level(&mut self, level: u8) {
+        self.fold_constants = level >= 1;
+        self.propagate_globals = level >= 2;
+        self.peephole = level >= 2;
+        self.tail_calls = level >= 2;
+    }
+
+    /// Limit how deeply expressions may nest before generation rejects them
+    pub fn set_max_expression_depth(&mut self, depth: usize) {
+        self.max_expression_depth = depth;
+    }
+
+    /// Report every expression in the statements that nests deeper than the limit, and
+    /// blocks that nest deeper than MAX_NESTING_DEPTH. The walk keeps its own stack, so it
+    /// can't overflow on the trees it rejects.
+    fn check_expression_depth(&mut self, statements: &[Statement]) -> bool {
+        let mut statement_stack: Vec<(&Statement, usize)> = statements.iter().map(|statement| (statement, 0)).collect();
+        let mut expressions: Vec<&Expression> = Vec::new();
+        let mut deepest_block = 0;
+        while let Some((statement, depth)) = statement_stack.pop() {
+            match statement {
+                Statement::Assignment(assign) => expressions.push(&assign.expression),
+                Statement::Condition(cond) => {
+                    expressions.push(&cond.condition);
+                    deepest_block = deepest_block.max(depth + 1);
+                    statement_stack.extend(cond.if_body.iter().map(|inner| (inner, depth + 1)));
+                    statement_stack.extend(cond.else_body.iter().flatten().map(|inner| (inner, depth + 1)));
+                }
+                Statement::Cycle(cycle) => {
+                    expressions.push(&cycle.condition);
+                    deepest_block = deepest_block.max(depth + 1);
+                    statement_stack.extend(cycle.body.iter().map(|inner| (inner, depth + 1)));
+                }
+                Statement::FunctionCall(call) => expressions.extend(call.arguments.iter()),
+                Statement::Print(PrintStatement::Expression(expr) | PrintStatement::Hex(expr) | PrintStatement::Binary(expr) | PrintStatement::Typed(expr))
+                    | Statement::Exit(expr)
+                    | Statement::Return(expr) => expressions.push(expr),
+                Statement::Print(PrintStatement::Items(items)) => {
+                    expressions.extend(items.iter().filter_map(|item| match item {
+                        PrintItem::Expression(expr) => Some(expr),
+                        _ => None,
+                    }));
+                }
+                Statement::Read(_) | Statement::Section(_) | Statement::Clear(_) | Statement::VarDeclaration(_) => {}
+            }
+        }
+
+        let mut all_within_limit = deepest_block <= MAX_NESTING_DEPTH;
+        if !all_within_limit {
+            self.report(CompileError::NestingTooDeep { depth: deepest_block, limit: MAX_NESTING_DEPTH });
+        }
+        for expr in expressions {
+            let mut deepest = 0;
+            let mut pending = vec![(expr, 1)];
+            while let Some((node, depth)) = pending.pop() {
+                deepest = deepest.max(depth);
+                match node {
+                    Expression::BinaryOp { left, right, .. } => {
+                        pending.push((left, depth + 1));
+                        pending.push((right, depth + 1));
+                    }
+                    Expression::BuiltinCall { arguments, .. } =>
+                        pending.extend(arguments.iter().map(|arg| (arg, depth + 1))),
+                    Expression::FunctionCall(call) =>
+                        pending.extend(call.arguments.iter().map(|arg| (arg, depth + 1))),
+                    Expression::Identifier(_) | Expression::IntegerLiteral(_) | Expression::FloatLiteral(_) | Expression::BooleanLiteral(_) => {}
+                }
+            }
+            if deepest > self.max_expression_depth {
+                self.report(CompileError::ExpressionTooDeep { depth: deepest, limit: self.max_expression_depth });
+                all_within_limit = false;
+            }
+        }
+        all_within_limit
+    }
+
+    /// Check the depth limits over main and every function body of a program
+    fn check_program_depths(&mut self, program_ast: &crate::ast::Program) -> bool {
+        let mut depths_ok = self.check_expression_depth(&program_ast.main_body);
+        for func_decl in &program_ast.funcs {
+            depths_ok &= self.check_expression_depth(&func_decl.body);
+        }
+        depths_ok
+    }
+
+    /// In strict mode, report an int value flowing into a float slot without an explicit cast
+    fn check_implicit_widening(&mut self, target_type: &Type, value_type: &Type, target: &str) {
+        if self.strict && matches!((target_type, value_type), (Type::Float, Type::Int)) {
+            self.report_error(format!("Implicit int to float conversion for {} is not allowed in strict mode; use float(...)", target));
+        }
     }
 
     /// Get the current scope from the top of the stack
@@ -235,50 +559,52 @@ impl QuadrupleGenerator {
 
     /// Get or create memory address for integer constant
     fn get_or_create_int_constant(&mut self, value: i32) -> i32 {
-        // Search for existing constant
-        for (index, &val) in self.int_constants.iter().enumerate() {
-            if val == value {
-                return MemoryAddresses::CTE_INT_START + index as i32;
-            }
+        // Reuse an existing constant, or append one; appending keeps addresses in emission order
+        let next_index = self.int_constants.len();
+        let index = *self.int_constant_index.entry(value).or_insert(next_index);
+        if index == next_index {
+            self.int_constants.push(value);
         }
-
-        // Create new constant address
-        let addr = MemoryAddresses::CTE_INT_START + self.int_constants.len() as i32;
-        self.int_constants.push(value);
-        addr
+        MemoryAddresses::CTE_INT_START + index as i32
     }
 
     /// Get or create memory address for float constant
-    fn get_or_create_float_constant(&mut self, value: f64) -> i32 {
-        // Search for existing constant
-        for (index, &val) in self.float_constants.iter().enumerate() {
-            if val == value {
-                return MemoryAddresses::CTE_FLOAT_START + index as i32;
-            }
+    /// Constants are compared by their bit pattern, so `0.0` and `-0.0` get separate
+    /// entries while repeated `NaN` literals share a single one.
+    pub fn get_or_create_float_constant(&mut self, value: f64) -> i32 {
+        // Reuse an existing constant, or append one; appending keeps addresses in emission order
+        let next_index = self.float_constants.len();
+        let index = *self.float_constant_index.entry(value.to_bits()).or_insert(next_index);
+        if index == next_index {
+            self.float_constants.push(value);
         }
-
-        // Create new constant address
-        let addr = MemoryAddresses::CTE_FLOAT_START + self.float_constants.len() as i32;
-        self.float_constants.push(value);
-        addr
+        MemoryAddresses::CTE_FLOAT_START + index as i32
     }
 
     /// Get or create memory address for boolean constant
     fn get_or_create_bool_constant(&mut self, value: bool) -> i32 {
         // Use temporary boolean memory segment instead of constant segment
-        let addr = self.temp_bool_counter;
-        self.temp_bool_counter += 1;
+        let addr = self.avail_next(Type::Bool);
         
         // Store the value for later reference
         let index = (addr - MemoryAddresses::TEMP_BOOL_START) as usize;
         while self.bool_constants.len() <= index {
-            self.bool_constants.push(false); // Pad with defaults if needed
+            self.bool_constants.push(None); // Comparison results share the segment and aren't literals
         }
-        self.bool_constants[index] = value;
+        self.bool_constants[index] = Some(value);
         
         addr
     }
 
+    /// Get or create the address of a string constant
+    fn get_or_create_string_constant(&mut self, text: &str) -> i32 {
+        if let Some(index) = self.string_constants.iter().position(|existing| existing == text) {
+            return index as i32;
+        }
+        self.string_constants.push(text.to_string());
+        (self.string_constants.len() - 1) as i32
+    }
+
     /// Generate a new temporary variable address based on type
     fn new_temp(&mut self, typ: Type) -> i32 {
         match typ {
@@ -302,7 +628,20 @@ impl QuadrupleGenerator {
 
     /// Get next available temporary
     fn avail_next(&mut self, typ: Type) -> i32 {
-        self.new_temp(typ)
+        let segment_end = match typ {
+            Type::Int => MemoryAddresses::TEMP_FLOAT_START,
+            Type::Float => MemoryAddresses::TEMP_BOOL_START,
+            Type::Bool => MemoryAddresses::TEMP_BOOL_END,
+        };
+        let temp = self.new_temp(typ.clone());
+        // Report only the first address past the end, not every one after it
+        if temp == segment_end {
+            self.report_error(format!(
+                "The program needs more {:?} temporaries than their memory segment holds; split long expressions into several assignments",
+                typ
+            ));
+        }
+        temp
     }
 
     /// Get the resulting type from an operation between two types
@@ -352,26 +691,292 @@ impl QuadrupleGenerator {
             (Type::Float, Type::Int, Operator::NotEqual) => Ok(Type::Bool),
             (Type::Bool, Type::Bool, Operator::NotEqual) => Ok(Type::Bool),
 
+            // Bitwise operations are only defined on ints
+            (Type::Int, Type::Int, Operator::BitAnd) => Ok(Type::Int),
+            (Type::Int, Type::Int, Operator::BitOr) => Ok(Type::Int),
+            (Type::Int, Type::Int, Operator::BitXor) => Ok(Type::Int),
+            (Type::Int, Type::Int, Operator::ShiftLeft) => Ok(Type::Int),
+            (Type::Int, Type::Int, Operator::ShiftRight) => Ok(Type::Int),
+
             // Invalid operations
             _ => Err(format!("Type mismatch: {:?} and {:?} cannot be used with {:?}", left_type, right_type, operator))
         }
     }
 
+    /// A message naming the function when an operator rejects an operand that is a call's
+    /// result, e.g. `isEven(x) + 1`. None when neither operand comes from a call.
+    fn call_result_mismatch(&self, left: (i32, &Type), right: (i32, &Type), operator: &Operator) -> Option<String> {
+        let accepts = |typ: &Type| match operator {
+            Operator::Plus | Operator::Minus | Operator::Multiply | Operator::Divide
+            | Operator::GreaterThan | Operator::LessThan => !matches!(typ, Type::Bool),
+            Operator::BitAnd | Operator::BitOr | Operator::BitXor
+            | Operator::ShiftLeft | Operator::ShiftRight => matches!(typ, Type::Int),
+            // Equality only fails on a bool against a number, which is either side's fault
+            Operator::Equal | Operator::NotEqual => false,
+        };
+        let (function, result_type) = [left, right].into_iter()
+            .filter(|(_, typ)| !accepts(typ))
+            .find_map(|(addr, typ)| self.call_results.get(&addr).map(|name| (name, typ)))?;
+        let symbol = match operator {
+            Operator::Plus => "+", Operator::Minus => "-", Operator::Multiply => "*", Operator::Divide => "/",
+            Operator::GreaterThan => ">", Operator::LessThan => "<", Operator::Equal => "==", Operator::NotEqual => "!=",
+            Operator::BitAnd => "&", Operator::BitOr => "|", Operator::BitXor => "^",
+            Operator::ShiftLeft => "<<", Operator::ShiftRight => ">>",
+        };
+        let verb = match operator {
+            Operator::Plus => "added".to_string(),
+            Operator::Minus => "subtracted".to_string(),
+            Operator::Multiply => "multiplied".to_string(),
+            Operator::Divide => "divided".to_string(),
+            _ => format!("used with '{}'", symbol),
+        };
+        Some(format!("Function '{}' returns a {:?}, which can't be {}: {:?} {} {:?}",
+            function, result_type, verb, left.1, symbol, right.1))
+    }
+
     /// Process a list of statements and generate quadruples
     pub fn generate_from_statements(&mut self, statements: &[Statement]) {
-        for statement in statements {
+        // Only the last statement of a block in tail position is itself in tail position
+        let block_in_tail = std::mem::take(&mut self.tail_position);
+        for (idx, statement) in statements.iter().enumerate() {
+            self.tail_position = block_in_tail && idx + 1 == statements.len();
             self.process_statement(statement);
         }
+        self.tail_position = false;
+    }
+
+    /// Generate quadruples for more statements after the ones already generated, in the
+    /// current scope. Returns the index of the first new quadruple; on a semantic error the
+    /// new quadruples are discarded and the errors are returned instead.
+    pub fn append_statements(&mut self, statements: &[Statement]) -> Result<usize, String> {
+        let first_new_quad = self.quad_queue.len();
+        if !self.check_expression_depth(statements) {
+            return Err(Self::join_errors(&std::mem::take(&mut self.semantic_errors)));
+        }
+        self.generate_from_statements(statements);
+
+        if !self.semantic_errors.is_empty() {
+            self.quad_queue.truncate(first_new_quad);
+            self.p_oper.clear();
+            self.pila_o.clear();
+            self.p_types.clear();
+            self.p_jumps.clear();
+            return Err(Self::join_errors(&std::mem::take(&mut self.semantic_errors)));
+        }
+
+        Ok(first_new_quad)
     }
 
     /// Process a single statement
     fn process_statement(&mut self, statement: &Statement) {
+        let in_tail = std::mem::take(&mut self.tail_position);
         match statement {
             Statement::Assignment(assign) => self.process_assignment(assign),
             Statement::Print(print_stmt) => self.process_print(print_stmt),
-            Statement::Condition(cond) => self.process_condition(cond),
+            Statement::Condition(cond) => self.process_condition(cond, in_tail),
             Statement::Cycle(cycle) => self.process_cycle(cycle),
-            Statement::FunctionCall(func_call) => self.process_function_call(func_call),
+            Statement::FunctionCall(func_call) => self.process_function_call(func_call, in_tail),
+            Statement::Exit(expr) => self.process_exit(expr),
+            Statement::Read(targets) => self.process_read(targets),
+            Statement::Section(text) => {
+                let addr = self.get_or_create_string_constant(text);
+                self.quad_queue.push_back(Quadruple::new(OpCode::LABEL, addr, -1, -1));
+            }
+            Statement::VarDeclaration(decls) => self.process_var_declaration(decls),
+            Statement::Clear(id) => self.process_clear(id),
+            Statement::Return(expr) => self.process_return(expr),
+        }
+    }
+
+    /// Register locals declared partway through a body in the enclosing scope.
+    /// They get static addresses like preamble locals and are visible from here on.
+    fn process_var_declaration(&mut self, decls: &[VarDeclaration]) {
+        let scope = self.current_scope();
+        for decl in decls {
+            let (result, warnings) = match self.function_directory.as_mut() {
+                Some(dir) => (dir.add_local_variable(&scope, decl), dir.take_warnings()),
+                None => return,
+            };
+            if let Err(e) = result {
+                self.report_error(e.to_string());
+            }
+            // The directory lints these locals as it does the preamble's, but only now
+            for warning in warnings {
+                self.report_warning(warning);
+            }
+        }
+        self.address_names.take();
+    }
+
+    /// Lint: assigning a parameter only changes this call's copy of the argument
+    fn lint_parameter_assignment(&mut self, id: &str) {
+        let scope = self.current_scope();
+        let is_parameter = self.function_directory.as_ref()
+            .and_then(|dir| dir.get_function(&scope))
+            .is_some_and(|info| info.parameters.iter().any(|(name, _, _)| name == id));
+        if is_parameter {
+            self.report_warning(format!(
+                "Parameter '{}' of function '{}' is reassigned; parameters are passed by value, so the caller's argument is unchanged",
+                id, scope
+            ));
+        }
+    }
+
+    /// Check that every call stages its arguments as PARAM 0, 1, ... n-1, one per
+    /// parameter of the called function, between its ERA and GOSUB. The VM fills any
+    /// gap with a placeholder, so a mistake here would otherwise only show up at run time.
+    fn check_param_indices(&mut self) {
+        let mut problems = Vec::new();
+        let mut open_calls: Vec<(i32, i32)> = Vec::new(); // (function start, next PARAM index)
+        for (idx, quad) in self.quad_queue.iter().enumerate() {
+            match quad.operation {
+                OpCode::ERA => open_calls.push((quad.arg1, 0)),
+                OpCode::PARAM => match open_calls.last_mut() {
+                    Some((_, next)) if quad.result == *next => *next += 1,
+                    Some((_, next)) => problems.push(format!("PARAM at quadruple {} has index {}, expected {}", idx, quad.result, next)),
+                    None => problems.push(format!("PARAM at quadruple {} is outside any call", idx)),
+                },
+                OpCode::GOSUB => {
+                    let Some((start, staged)) = open_calls.pop() else {
+                        problems.push(format!("GOSUB at quadruple {} has no matching ERA", idx));
+                        continue;
+                    };
+                    let expected = self.function_directory.as_ref()
+                        .and_then(|dir| dir.get_all_functions().values().find(|info| info.start_quad_idx == Some(start)))
+                        .map(|info| info.parameters.len() as i32);
+                    if start != quad.arg1 || expected.is_some_and(|count| count != staged) {
+                        problems.push(format!("Call at quadruple {} stages {} parameters, expected {:?}", idx, staged, expected));
+                    }
+                }
+                _ => {}
+            }
+        }
+        for problem in problems {
+            self.report_internal(problem);
+        }
+    }
+
+    /// Lint helper: the fixed outcome of a condition built only from literals, if any
+    fn evaluate_constant_condition(expr: &Expression) -> Option<bool> {
+        match Self::evaluate_constant(expr)? {
+            VMValue::Bool(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    /// Evaluate an expression made only of literals. Anything that reads a variable,
+    /// calls a function or would fail at runtime (overflow, division by zero) gives None.
+    fn evaluate_constant(expr: &Expression) -> Option<VMValue> {
+        match expr {
+            Expression::IntegerLiteral(value) => Some(VMValue::Int(*value)),
+            Expression::FloatLiteral(value) => Some(VMValue::Float(*value)),
+            Expression::BooleanLiteral(value) => Some(VMValue::Bool(*value)),
+            Expression::BinaryOp { left, operator, right } => {
+                let (left, right) = (Self::evaluate_constant(left)?, Self::evaluate_constant(right)?);
+                let as_float = |value: &VMValue| match value {
+                    VMValue::Int(i) => Some(*i as f64),
+                    VMValue::Float(f) => Some(*f),
+                    VMValue::Bool(_) => None,
+                };
+                match (operator, &left, &right) {
+                    (Operator::Equal, VMValue::Bool(l), VMValue::Bool(r)) => Some(VMValue::Bool(l == r)),
+                    (Operator::NotEqual, VMValue::Bool(l), VMValue::Bool(r)) => Some(VMValue::Bool(l != r)),
+                    (Operator::Plus, VMValue::Int(l), VMValue::Int(r)) => l.checked_add(*r).map(VMValue::Int),
+                    (Operator::Minus, VMValue::Int(l), VMValue::Int(r)) => l.checked_sub(*r).map(VMValue::Int),
+                    (Operator::Multiply, VMValue::Int(l), VMValue::Int(r)) => l.checked_mul(*r).map(VMValue::Int),
+                    (Operator::Divide, VMValue::Int(l), VMValue::Int(r)) => l.checked_div(*r).map(VMValue::Int),
+                    (Operator::BitAnd, VMValue::Int(l), VMValue::Int(r)) => Some(VMValue::Int(l & r)),
+                    (Operator::BitOr, VMValue::Int(l), VMValue::Int(r)) => Some(VMValue::Int(l | r)),
+                    (Operator::BitXor, VMValue::Int(l), VMValue::Int(r)) => Some(VMValue::Int(l ^ r)),
+                    (Operator::ShiftLeft, VMValue::Int(l), VMValue::Int(r)) =>
+                        u32::try_from(*r).ok().and_then(|r| l.checked_shl(r)).map(VMValue::Int),
+                    (Operator::ShiftRight, VMValue::Int(l), VMValue::Int(r)) =>
+                        u32::try_from(*r).ok().and_then(|r| l.checked_shr(r)).map(VMValue::Int),
+                    _ => {
+                        let (l, r) = (as_float(&left)?, as_float(&right)?);
+                        match operator {
+                            Operator::Plus => Some(VMValue::Float(l + r)),
+                            Operator::Minus => Some(VMValue::Float(l - r)),
+                            Operator::Multiply => Some(VMValue::Float(l * r)),
+                            Operator::Divide if r != 0.0 => Some(VMValue::Float(l / r)),
+                            Operator::Divide => None,
+                            Operator::GreaterThan => Some(VMValue::Bool(l > r)),
+                            Operator::LessThan => Some(VMValue::Bool(l < r)),
+                            Operator::Equal => Some(VMValue::Bool(l == r)),
+                            Operator::NotEqual => Some(VMValue::Bool(l != r)),
+                            Operator::BitAnd | Operator::BitOr | Operator::BitXor
+                                | Operator::ShiftLeft | Operator::ShiftRight => None,
+                        }
+                    }
+                }
+            }
+            _ => None,
+        }
+    }
+
+    /// Record a semantic error; generation continues but the program is rejected at the end
+    fn report_error(&mut self, message: String) {
+        self.semantic_errors.push(CompileError::Semantic(message));
+    }
+
+    /// Record a broken generator invariant; the program is rejected instead of the generator panicking
+    fn report_internal(&mut self, message: String) {
+        self.semantic_errors.push(CompileError::Internal(message));
+    }
+
+    /// Record a semantic error that callers may want to tell apart from the rest
+    fn report(&mut self, error: CompileError) {
+        self.semantic_errors.push(error);
+    }
+
+    /// The semantic errors of the last generation, for callers that match on the kind of error
+    pub fn compile_errors(&self) -> &[CompileError] {
+        &self.semantic_errors
+    }
+
+    fn join_errors(errors: &[CompileError]) -> String {
+        errors.iter().map(|error| error.to_string()).collect::<Vec<_>>().join("\n")
+    }
+
+    /// Whether every path through the statements ends in a `return` (or `exit`), so
+    /// execution can't fall off the end of the block
+    fn always_returns(statements: &[Statement]) -> bool {
+        statements.iter().any(|statement| match statement {
+            Statement::Return(_) | Statement::Exit(_) => true,
+            Statement::Condition(cond) => cond.else_body.as_ref()
+                .is_some_and(|else_body| Self::always_returns(&cond.if_body) && Self::always_returns(else_body)),
+            _ => false,
+        })
+    }
+
+    /// Record a non-fatal lint warning
+    fn report_warning(&mut self, message: String) {
+        self.warnings.push(message);
+    }
+
+    /// Turn every pending warning, the directory's included, into a compile error
+    fn promote_warnings(&mut self) {
+        let mut warnings = self.function_directory.as_mut().map(|dir| dir.take_warnings()).unwrap_or_default();
+        warnings.extend(self.take_warnings());
+        for warning in warnings {
+            self.report(CompileError::Warning(warning));
+        }
+    }
+
+    /// Take the warnings collected during the last generation
+    pub fn take_warnings(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.warnings)
+    }
+
+    /// Check whether an address belongs to a declared variable (global, local or parameter)
+    fn is_variable_address(address: i32) -> bool {
+        (MemoryAddresses::INT_START..MemoryAddresses::CTE_INT_START).contains(&address)
+    }
+
+    /// If `address` holds a bool literal, emit the ASSIGN that stores its value there
+    fn materialize_bool_literal(&mut self, address: i32) {
+        if let Ok(VMValue::Bool(literal)) = self.get_direct_bool_value(address) {
+            self.quad_queue.push_back(Quadruple::new(OpCode::ASSIGN, -1, if literal { 1 } else { 0 }, address));
         }
     }
 
@@ -386,6 +991,19 @@ impl QuadrupleGenerator {
 
             // Get the target variable address
             if let Some(target_addr) = self.get_address(&assign.id) {
+                if let Some(target_type) = self.get_type(&assign.id) {
+                    let is_valid = self.function_directory.as_ref()
+                        .is_some_and(|dir| dir.is_valid_assignment(&target_type, &result_type));
+                    if !is_valid {
+                        self.report_error(format!("Type mismatch in assignment to '{}'. Expected {:?}, got {:?}.", assign.id, target_type, result_type));
+                        return;
+                    }
+                    self.check_implicit_widening(&target_type, &result_type, &format!("'{}'", assign.id));
+                }
+                if self.warn_param_assignments {
+                    self.lint_parameter_assignment(&assign.id);
+                }
+
                 // Special case for boolean literals
                 if matches!(result_type, Type::Bool) {
                     // Check if we can directly determine the boolean value from the address
@@ -409,11 +1027,10 @@ impl QuadrupleGenerator {
                     target_addr
                 ));
             } else {
-                // This should ideally be caught by semantic analysis before quad generation
-                eprintln!("Error: Variable '{}' not found in current or global scope during assignment.", assign.id);
+                self.report_error(format!("Variable '{}' not found in current or global scope during assignment", assign.id));
             }
         } else {
-            eprintln!("Error: No result on operand stack for assignment to '{}'.", assign.id);
+            self.report_internal(format!("No result on operand stack for assignment to '{}'", assign.id));
         }
     }
 
@@ -427,8 +1044,8 @@ impl QuadrupleGenerator {
         // Check temporary TEMP_BOOL_START segment
         else if address >= MemoryAddresses::TEMP_BOOL_START {
             let index = (address - MemoryAddresses::TEMP_BOOL_START) as usize;
-            if index < self.bool_constants.len() {
-                return Ok(VMValue::Bool(self.bool_constants[index]));
+            if let Some(Some(value)) = self.bool_constants.get(index) {
+                return Ok(VMValue::Bool(*value));
             }
         }
         Err("Not a direct boolean constant")
@@ -443,14 +1060,174 @@ impl QuadrupleGenerator {
                     self.p_types.pop(); // Remove type from stack
                     self.quad_queue.push_back(Quadruple::new(OpCode::PRINT, value_addr, -1, -1));
                 } else {
-                    eprintln!("Error: No result on operand stack for PRINT statement.");
+                    self.report_internal("No result on operand stack for print".to_string());
+                }
+            }
+            PrintStatement::Hex(expr) => self.process_print_in_base(expr, PrintBase::HEX),
+            PrintStatement::Binary(expr) => self.process_print_in_base(expr, PrintBase::BIN),
+            PrintStatement::Typed(expr) => self.process_print_typed(expr),
+            PrintStatement::Items(items) => self.process_print_items(items),
+        }
+    }
+
+    /// Process a print of several items on one line. Separators are known statically,
+    /// so each is emitted as a text part between two items; items default to a space.
+    /// The line is printed at endl and at the end of the statement, unless it ends in endl.
+    fn process_print_items(&mut self, items: &[PrintItem]) {
+        let mut separator = " ".to_string();
+        let mut line_has_items = false;
+        for item in items {
+            match item {
+                PrintItem::Separator(text) => separator = text.clone(),
+                PrintItem::Endl => {
+                    self.quad_queue.push_back(Quadruple::new(OpCode::PRINT_LINE, -1, -1, -1));
+                    line_has_items = false;
+                }
+                PrintItem::Text(text) => {
+                    let text = if line_has_items { format!("{}{}", separator, text) } else { text.clone() };
+                    let text_addr = self.get_or_create_string_constant(&text);
+                    self.quad_queue.push_back(Quadruple::new(OpCode::PRINT_PART, -1, -1, text_addr));
+                    line_has_items = true;
                 }
+                PrintItem::Expression(expr) => {
+                    if line_has_items && !separator.is_empty() {
+                        let separator_addr = self.get_or_create_string_constant(&separator);
+                        self.quad_queue.push_back(Quadruple::new(OpCode::PRINT_PART, -1, -1, separator_addr));
+                    }
+                    self.process_expression(expr);
+                    let (Some(value_addr), Some(_)) = (self.pila_o.pop(), self.p_types.pop()) else {
+                        self.report_internal("No result on operand stack for print".to_string());
+                        return;
+                    };
+                    // Bool literals only live in the generator; write the value so the VM can read it
+                    self.materialize_bool_literal(value_addr);
+                    self.quad_queue.push_back(Quadruple::new(OpCode::PRINT_PART, value_addr, -1, -1));
+                    line_has_items = true;
+                }
+            }
+        }
+        if !matches!(items.last(), Some(PrintItem::Endl)) {
+            self.quad_queue.push_back(Quadruple::new(OpCode::PRINT_LINE, -1, -1, -1));
+        }
+    }
+
+    /// Process a printd, which prints the value followed by its static type. The
+    /// " : type" suffix is a string constant carried in the result field of PRINT.
+    fn process_print_typed(&mut self, expr: &Expression) {
+        self.process_expression(expr);
+        if let Some(value_addr) = self.pila_o.pop() {
+            let type_name = match self.p_types.pop().unwrap_or(Type::Int) {
+                Type::Int => "int",
+                Type::Float => "float",
+                Type::Bool => "bool",
+            };
+            let suffix_addr = self.get_or_create_string_constant(&format!(" : {}", type_name));
+            self.quad_queue.push_back(Quadruple::new(OpCode::PRINT, value_addr, -1, suffix_addr));
+        } else {
+            self.report_internal("No result on operand stack for print".to_string());
+        }
+    }
+
+    /// Process a print of an integer expression formatted in the given base
+    fn process_print_in_base(&mut self, expr: &Expression, base: i32) {
+        self.process_expression(expr);
+        if let Some(value_addr) = self.pila_o.pop() {
+            let value_type = self.p_types.pop().unwrap_or(Type::Int);
+            if !matches!(value_type, Type::Int) {
+                self.report_error(format!("Printing in base {} requires an int expression, got {:?}", base, value_type));
+                return;
+            }
+            self.quad_queue.push_back(Quadruple::new(OpCode::PRINT, value_addr, base, -1));
+        } else {
+            self.report_internal("No result on operand stack for print".to_string());
+        }
+    }
+
+    /// Process a read statement, emitting one READ per target.
+    /// Each quad carries its position and the total count so the VM can read all
+    /// targets of the statement from a single input line.
+    fn process_read(&mut self, targets: &[String]) {
+        let count = targets.len() as i32;
+        for (position, target) in targets.iter().enumerate() {
+            match self.get_address(target) {
+                Some(target_addr) => {
+                    self.quad_queue.push_back(Quadruple::new(OpCode::READ, position as i32, count, target_addr));
+                }
+                None => self.report_error(format!("Variable '{}' not found in scope '{}' for read", target, self.current_scope())),
+            }
+        }
+    }
+
+    /// Reset a variable to its type's zero value
+    fn process_clear(&mut self, id: &str) {
+        let (Some(target_addr), Some(target_type)) = (self.get_address(id), self.get_type(id)) else {
+            self.report_error(format!("Variable '{}' not found in scope '{}' for clear", id, self.current_scope()));
+            return;
+        };
+        let quad = match target_type {
+            Type::Int => Quadruple::new(OpCode::ASSIGN, self.get_or_create_int_constant(0), -1, target_addr),
+            Type::Float => Quadruple::new(OpCode::ASSIGN, self.get_or_create_float_constant(0.0), -1, target_addr),
+            Type::Bool => Quadruple::new(OpCode::ASSIGN, -1, 0, target_addr), // Direct boolean value, as in assignments
+        };
+        self.quad_queue.push_back(quad);
+    }
+
+    /// Store a function's result in its return cell and leave the function.
+    /// In main, the value is the program's exit status instead, as with exit.
+    fn process_return(&mut self, expr: &Expression) {
+        let scope = self.current_scope();
+        if scope == "main" {
+            self.process_exit(expr);
+            return;
+        }
+        let target = self.function_directory.as_ref()
+            .and_then(|dir| dir.get_function(&scope))
+            .and_then(|info| info.return_type.clone().zip(info.return_address));
+        let Some((return_type, return_addr)) = target else {
+            self.report_error(format!("'return' is only allowed in a function with a return type, not in '{}'", scope));
+            return;
+        };
+
+        self.process_expression(expr);
+        let (Some(value_addr), Some(value_type)) = (self.pila_o.pop(), self.p_types.pop()) else {
+            self.report_internal(format!("No result on operand stack for return in '{}'", scope));
+            return;
+        };
+        let is_valid = self.function_directory.as_ref()
+            .is_some_and(|dir| dir.is_valid_assignment(&return_type, &value_type));
+        if !is_valid {
+            self.report_error(format!("Function '{}' returns {:?}, but the returned value is {:?}", scope, return_type, value_type));
+            return;
+        }
+        self.check_implicit_widening(&return_type, &value_type, &format!("the result of function '{}'", scope));
+        self.materialize_bool_literal(value_addr);
+        self.quad_queue.push_back(Quadruple::new(OpCode::RETURN, value_addr, -1, return_addr));
+    }
+
+    /// Process an exit statement, which ends the program with an integer status code
+    fn process_exit(&mut self, expr: &Expression) {
+        self.process_expression(expr);
+        if let Some(code_addr) = self.pila_o.pop() {
+            let code_type = self.p_types.pop().unwrap_or(Type::Int);
+            if !matches!(code_type, Type::Int) {
+                self.report_error(format!("Exit code must be an int expression, got {:?}", code_type));
+                return;
             }
+            self.quad_queue.push_back(Quadruple::new(OpCode::EXIT, code_addr, -1, -1));
+        } else {
+            self.report_internal("No result on operand stack for exit".to_string());
         }
     }
 
     /// Process a conditional statement (if/else)
-    fn process_condition(&mut self, cond: &crate::ast::Condition) {
+    fn process_condition(&mut self, cond: &crate::ast::Condition, in_tail: bool) {
+        match Self::evaluate_constant_condition(&cond.condition) {
+            Some(true) if cond.else_body.is_some() => self.report_warning("Condition of 'if' is always true; the else branch is never taken".to_string()),
+            Some(true) => self.report_warning("Condition of 'if' is always true; the branch is always taken".to_string()),
+            Some(false) => self.report_warning("Condition of 'if' is always false; the branch is never taken".to_string()),
+            None => {}
+        }
+
         // 1. Process the condition expression
         self.process_expression(&cond.condition);
 
@@ -477,6 +1254,7 @@ impl QuadrupleGenerator {
             self.p_jumps.push(gotof_quad_idx);
 
             // 5. Process if-body statements
+            self.tail_position = in_tail;
             self.generate_from_statements(&cond.if_body);
 
             // Check if there's an else clause
@@ -492,32 +1270,48 @@ impl QuadrupleGenerator {
 
                 // 7. Fill the pending GOTOF jump (from step 4) with the current quad position (start of else)
                 let jump_target_for_gotof = self.quad_queue.len();
-                let gotof_jump_pos_to_fill = self.p_jumps.pop().unwrap(); // Pop GOTOF jump
+                let Some(gotof_jump_pos_to_fill) = self.p_jumps.pop() else { // Pop GOTOF jump
+                    self.report_internal("Jump stack empty when filling the if's GOTOF".to_string());
+                    return;
+                };
                 self.fill_jump(gotof_jump_pos_to_fill, jump_target_for_gotof as i32);
 
                 // 8. Push the GOTO position (from step 6) to jumps stack
                 self.p_jumps.push(goto_quad_idx);
 
                 // 9. Process else-body statements
+                self.tail_position = in_tail;
                 self.generate_from_statements(else_body);
 
                 // 10. Fill the pending GOTO jump (from step 8) with the current quad position
                 let jump_target_for_goto = self.quad_queue.len();
-                let goto_jump_pos_to_fill = self.p_jumps.pop().unwrap(); // Pop GOTO jump
+                let Some(goto_jump_pos_to_fill) = self.p_jumps.pop() else { // Pop GOTO jump
+                    self.report_internal("Jump stack empty when filling the else's GOTO".to_string());
+                    return;
+                };
                 self.fill_jump(goto_jump_pos_to_fill, jump_target_for_goto as i32);
             } else {
                 // No else clause, fill the GOTOF (from step 4) with the current quad position
                 let jump_target_for_gotof = self.quad_queue.len();
-                let gotof_jump_pos_to_fill = self.p_jumps.pop().unwrap(); // Pop GOTOF jump
+                let Some(gotof_jump_pos_to_fill) = self.p_jumps.pop() else { // Pop GOTOF jump
+                    self.report_internal("Jump stack empty when filling the if's GOTOF".to_string());
+                    return;
+                };
                 self.fill_jump(gotof_jump_pos_to_fill, jump_target_for_gotof as i32);
             }
         } else {
-            eprintln!("Error: No result on operand stack for IF condition.");
+            self.report_internal("No result on operand stack for if condition".to_string());
         }
     }
 
     /// Process a cycle statement (while)
     fn process_cycle(&mut self, cycle: &crate::ast::Cycle) {
+        match Self::evaluate_constant_condition(&cycle.condition) {
+            Some(true) => self.report_warning("Condition of 'while' is always true; the loop can only end through exit or return".to_string()),
+            Some(false) => self.report_warning("Condition of 'while' is always false; the loop body never runs".to_string()),
+            None => {}
+        }
+
         // 1. Save the position where we need to return for the next iteration (start of condition)
         let return_pos = self.quad_queue.len();
         self.p_jumps.push(return_pos); // Push return point for GOTO at end of loop body
@@ -549,8 +1343,11 @@ impl QuadrupleGenerator {
             self.generate_from_statements(&cycle.body);
 
             // 7. Generate GOTO to jump back to the condition evaluation
-            let loop_return_target = self.p_jumps.pop().unwrap(); // This should be the GOTOF index
-            let condition_start_target = self.p_jumps.pop().unwrap(); // This should be return_pos
+            // The GOTOF index, then return_pos below it
+            let (Some(loop_return_target), Some(condition_start_target)) = (self.p_jumps.pop(), self.p_jumps.pop()) else {
+                self.report_internal("Jump stack empty at the end of a while body".to_string());
+                return;
+            };
 
             self.quad_queue.push_back(Quadruple::new(
                 OpCode::GOTO,
@@ -564,7 +1361,7 @@ impl QuadrupleGenerator {
             // let gotof_jump_pos_to_fill = self.p_jumps.pop().unwrap(); // GOTOF jump was popped above
             self.fill_jump(loop_return_target, jump_target_after_loop as i32);
         } else {
-            eprintln!("Error: No result on operand stack for WHILE condition.");
+            self.report_internal("No result on operand stack for while condition".to_string());
         }
     }
 
@@ -573,43 +1370,52 @@ impl QuadrupleGenerator {
         if let Some(quad) = self.quad_queue.get_mut(quad_idx) {
             quad.result = target;
         } else {
-            eprintln!("Error: Could not fill jump. Invalid quadruple index {}", quad_idx);
+            self.report_internal(format!("Could not fill jump; invalid quadruple index {}", quad_idx));
         }
     }
 
     /// Process a function call
-    fn process_function_call(&mut self, func_call: &crate::ast::FunctionCall) {
+    fn process_function_call(&mut self, func_call: &crate::ast::FunctionCall, in_tail: bool) {
         // Extract function info first to avoid borrowing conflicts
         let func_info = match self.function_directory.as_ref() {
             Some(dir) => match dir.get_function(&func_call.id) {
                 Some(info) => info.clone(), // Clone the function info
                 None => {
-                    eprintln!("Error: Function '{}' not found in directory.", func_call.id);
+                    self.report_error(format!("Function '{}' not found", func_call.id));
                     return;
                 }
             },
             None => {
-                eprintln!("Error: Function directory not available for function call processing.");
+                self.report_internal("Function directory not available for function call processing".to_string());
                 return;
             }
         };
 
         // 1. Arity check
         if func_call.arguments.len() != func_info.parameters.len() {
-            eprintln!("Error: Function '{}' called with {} arguments, but expected {}.",
-                      func_call.id, func_call.arguments.len(), func_info.parameters.len());
-            return; // Or handle error appropriately
+            self.report_error(format!("Function '{}' called with {} arguments, but expected {}.",
+                      func_call.id, func_call.arguments.len(), func_info.parameters.len()));
+            return;
         }
 
-        // 2. Generate ERA quad
-        // The first argument to ERA will be the function's start_quad_idx, acting as an ID.
-        let func_id_for_era = func_info.start_quad_idx.unwrap_or(-1); // Should be set by now
-        if func_id_for_era == -1 {
-            eprintln!("Error: start_quad_idx not set for function '{}' before call.", func_call.id);
-        }
-        self.quad_queue.push_back(Quadruple::new(OpCode::ERA, func_id_for_era, -1, -1));
+        // A self call that is the last thing the function does can reuse the current activation
+        let is_tail_call = self.tail_calls && in_tail && self.current_scope() == func_call.id;
+        let mut tail_call_args = Vec::new();
+
+        // 2. The start index doubles as the function's ID for ERA and GOSUB.
+        // Functions are generated in declaration order, so a function declared after this
+        // call has no start yet; emitting -1 would only fail later in the VM.
+        let Some(func_start) = func_info.start_quad_idx else {
+            self.report_error(format!(
+                "Function '{}' is called before its code is generated; declare it before the function that calls it",
+                func_call.id
+            ));
+            return;
+        };
 
-        // 3. Process arguments and generate PARAM quads
+        // 3. Evaluate every argument before ERA, so a call nested in an argument
+        // (`f(1, g(2))`) runs to completion before this call starts staging parameters
+        let mut arg_addrs = Vec::with_capacity(func_call.arguments.len());
         for (k, arg_expr) in func_call.arguments.iter().enumerate() {
             self.process_expression(arg_expr); // Evaluates expression, pushes result addr to PilaO, type to PTypes
 
@@ -625,21 +1431,91 @@ impl QuadrupleGenerator {
                 };
 
                 if !is_valid {
-                    eprintln!("Error: Type mismatch for argument {} of function '{}'. Expected {:?}, got {:?}.",
-                              k + 1, func_call.id, expected_param_type, arg_type);
-                    // Error handling
+                    self.report_error(format!("Type mismatch for argument {} of function '{}'. Expected {:?}, got {:?}.",
+                              k + 1, func_call.id, expected_param_type, arg_type));
+                }
+                self.check_implicit_widening(expected_param_type, &arg_type, &format!("argument {} of function '{}'", k + 1, func_call.id));
+                // Bool literals only live in the generator; write the value so PARAM can copy it
+                self.materialize_bool_literal(arg_addr);
+                if is_tail_call {
+                    tail_call_args.push((arg_addr, arg_type));
+                } else {
+                    arg_addrs.push(arg_addr);
                 }
-                self.quad_queue.push_back(Quadruple::new(OpCode::PARAM, arg_addr, -1, k as i32));
             } else {
-                eprintln!("Error: Missing operand/type for argument {} of function '{}'.", k + 1, func_call.id);
-                // Error handling
+                self.report_internal(format!("Missing operand/type for argument {} of function '{}'", k + 1, func_call.id));
                 return;
             }
         }
 
+        if !is_tail_call {
+            self.quad_queue.push_back(Quadruple::new(OpCode::ERA, func_start, -1, -1));
+            for (k, arg_addr) in arg_addrs.into_iter().enumerate() {
+                self.quad_queue.push_back(Quadruple::new(OpCode::PARAM, arg_addr, -1, k as i32));
+            }
+        }
+
         // 4. Generate GOSUB quad
-        let func_target_quad = func_info.start_quad_idx.unwrap_or(-1); // Should be set
-        self.quad_queue.push_back(Quadruple::new(OpCode::GOSUB, func_target_quad, -1, -1));
+        if is_tail_call {
+            self.emit_tail_call(&func_info, tail_call_args, func_start);
+        } else {
+            self.quad_queue.push_back(Quadruple::new(OpCode::GOSUB, func_start, -1, -1));
+        }
+    }
+
+    /// Call a function for its value: the usual ERA/PARAM/GOSUB sequence, then a copy of
+    /// the result into a temporary, since a later call to the same function reuses the cell
+    fn process_call_expression(&mut self, func_call: &crate::ast::FunctionCall) {
+        let target = self.function_directory.as_ref()
+            .and_then(|dir| dir.get_function(&func_call.id))
+            .map(|info| info.return_type.clone().zip(info.return_address));
+        let (result_type, return_addr) = match target {
+            Some(Some(result)) => result,
+            Some(None) => {
+                self.report(CompileError::VoidInExpression { function: func_call.id.clone() });
+                self.push_placeholder_operand();
+                return;
+            }
+            None => {
+                self.report_error(format!("Function '{}' not found", func_call.id));
+                self.push_placeholder_operand();
+                return;
+            }
+        };
+
+        self.process_function_call(func_call, false);
+        let result_temp = self.avail_next(result_type.clone());
+        self.quad_queue.push_back(Quadruple::new(OpCode::ASSIGN, return_addr, -1, result_temp));
+        self.call_results.insert(result_temp, func_call.id.clone());
+        self.pila_o.push(result_temp);
+        self.p_types.push(result_type);
+    }
+
+    /// Keep the operand stack balanced after an expression that failed to compile
+    fn push_placeholder_operand(&mut self) {
+        let placeholder = self.avail_next(Type::Int);
+        self.pila_o.push(placeholder);
+        self.p_types.push(Type::Int);
+    }
+
+    /// Rebind the parameters to the new arguments and jump back to the function start.
+    /// Arguments that read variables are copied out first, since a parameter they refer to
+    /// may be overwritten by an earlier rebind (e.g. swapping two parameters).
+    fn emit_tail_call(&mut self, func_info: &FunctionInfo, args: Vec<(i32, Type)>, func_start: i32) {
+        let mut sources = Vec::new();
+        for (arg_addr, arg_type) in args {
+            if Self::is_variable_address(arg_addr) {
+                let copy_addr = self.avail_next(arg_type);
+                self.quad_queue.push_back(Quadruple::new(OpCode::ASSIGN, arg_addr, -1, copy_addr));
+                sources.push(copy_addr);
+            } else {
+                sources.push(arg_addr);
+            }
+        }
+        for (source, (_, _, param_addr)) in sources.into_iter().zip(&func_info.parameters) {
+            self.quad_queue.push_back(Quadruple::new(OpCode::ASSIGN, source, -1, *param_addr));
+        }
+        self.quad_queue.push_back(Quadruple::new(OpCode::GOTO, -1, -1, func_start));
     }
 
 
@@ -658,6 +1534,32 @@ impl QuadrupleGenerator {
         }
     }
 
+    /// Generate one quadruple stream for a program linked with modules from other files.
+    /// The modules' functions are generated before the program's own, so the program's
+    /// functions can call them. The program's main is the only entry point; the modules'
+    /// main blocks are not part of it.
+    /// The function directory must be the merged one, see FunctionDirectory::from_programs.
+    pub fn generate_for_linked_programs(&mut self, program_ast: &crate::ast::Program, modules: &[crate::ast::Program]) -> Result<(), String> {
+        if modules.is_empty() {
+            return self.generate_for_program(program_ast);
+        }
+        // Cloning recurses through the blocks too, so the depth limits go first
+        self.semantic_errors.clear();
+        let mut depths_ok = self.check_program_depths(program_ast);
+        for module in modules {
+            depths_ok &= self.check_program_depths(module);
+        }
+        if !depths_ok {
+            return Err(Self::join_errors(&self.semantic_errors));
+        }
+        let mut linked = program_ast.clone();
+        linked.funcs = modules.iter()
+            .flat_map(|module| module.funcs.iter().cloned())
+            .chain(program_ast.funcs.iter().cloned())
+            .collect();
+        self.generate_for_program(&linked)
+    }
+
     /// Generate quadruples for the entire program AST
     pub fn generate_for_program(&mut self, program_ast: &crate::ast::Program) -> Result<(), String> {
         if self.function_directory.is_none() {
@@ -665,6 +1567,11 @@ impl QuadrupleGenerator {
         }
         self.clear(); // Resets counters, stacks, and scope_stack to ["global"]
 
+        // Too-deep expressions and blocks would overflow the recursive generator, so reject them first
+        if !self.check_program_depths(program_ast) {
+            return Err(Self::join_errors(&self.semantic_errors));
+        }
+
         // 1. Add a GOTO main quadruple (quad 0). Target will be filled later.
         let goto_main_quad_idx = self.quad_queue.len();
         assert_eq!(goto_main_quad_idx, 0, "GOTO main should be the first quadruple.");
@@ -675,7 +1582,8 @@ impl QuadrupleGenerator {
             -1  // Placeholder for main's starting quadruple index
         ));
 
-        // 2. Process non-main function declarations
+        // 2. Process non-main function declarations. They always precede main in the
+        //    quadruples, whatever their place in the source, so GOTO main skips them all
         for func_decl in &program_ast.funcs {
             let func_start_idx = self.quad_queue.len() as i32;
             // Update FunctionDirectory with the start index
@@ -683,7 +1591,12 @@ impl QuadrupleGenerator {
                 dir.set_function_start_quad(&func_decl.id, func_start_idx);
             } else { return Err("Function directory lost during generation".to_string()); }
 
+            if func_decl.return_type.is_some() && !Self::always_returns(&func_decl.body) {
+                self.report(CompileError::MissingReturn { function: func_decl.id.clone() });
+            }
+
             self.enter_scope_internal(func_decl.id.clone());
+            self.tail_position = true;
             self.generate_from_statements(&func_decl.body);
             self.quad_queue.push_back(Quadruple::new(OpCode::ENDFUNC, -1, -1, -1));
             self.exit_scope_internal();
@@ -707,11 +1620,56 @@ impl QuadrupleGenerator {
         self.quad_queue.push_back(Quadruple::new(OpCode::HALT, -1, -1, -1));
         self.exit_scope_internal(); // Return to "global" scope conceptually (though stack is empty except global)
 
+        // 5. Lint functions that no GOSUB ever targets
+        self.warn_uncalled_functions(&program_ast.id);
+        self.check_param_indices();
+        if self.warnings_as_errors {
+            self.promote_warnings();
+        }
+
+        if self.propagate_globals && self.semantic_errors.is_empty() {
+            self.run_global_propagation_pass();
+        }
+        if self.peephole && self.semantic_errors.is_empty() {
+            self.run_peephole_pass();
+        }
+        if let Err(problem) = self.verify_structure() {
+            self.report_internal(problem);
+        }
+
+        if !self.semantic_errors.is_empty() {
+            return Err(Self::join_errors(&self.semantic_errors));
+        }
+
         Ok(())
     }
 
 
     
+    /// Warn about declared functions that are never called
+    fn warn_uncalled_functions(&mut self, program_id: &str) {
+        let called_starts: HashSet<i32> = self.quad_queue.iter()
+            .filter(|quad| quad.operation == OpCode::GOSUB)
+            .map(|quad| quad.arg1)
+            .collect();
+
+        let mut uncalled: Vec<String> = match self.function_directory.as_ref() {
+            Some(dir) => dir.get_all_functions().iter()
+                .filter(|(name, info)| {
+                    name.as_str() != "main" && name.as_str() != "global" && name.as_str() != program_id && !info.is_program
+                        && !info.start_quad_idx.is_some_and(|start| called_starts.contains(&start))
+                })
+                .map(|(name, _)| name.clone())
+                .collect(),
+            None => return,
+        };
+        uncalled.sort();
+
+        for name in uncalled {
+            self.report_warning(format!("Function '{}' is declared but never called", name));
+        }
+    }
+
     /// Action 1: PilaO.Push(id.name) and PTypes.Push(id.type)
     fn action_push_id(&mut self, id: &str) -> Result<i32, String> {
         // Look up the variable address and type from function directory
@@ -763,46 +1721,50 @@ impl QuadrupleGenerator {
 
     /// Action 4 and 5: Process operations based on operator precedence
     fn action_process_operation(&mut self, is_mult_div: bool) {
-        if self.p_oper.is_empty() {
+        let Some(&op) = self.p_oper.last() else {
             return;
-        }
-
-        let op = self.p_oper.last().cloned().unwrap();
+        };
 
         // Check if we should process the operation based on precedence
         let should_process = if is_mult_div {
             // For action 5 (higher precedence operations * /)
             op == OpCode::MULT || op == OpCode::DIV
         } else {
-            // For action 4 (lower precedence operations + -)
-            op == OpCode::ADD || op == OpCode::SUB
+            // For action 4 (lower precedence operations + -, and the shifts and bitwise & | ^ below them)
+            matches!(op, OpCode::ADD | OpCode::SUB | OpCode::BAND | OpCode::BOR | OpCode::BXOR | OpCode::SHL | OpCode::SHR)
         };
 
         if should_process {
             // Pop the operator
-            let operator = self.p_oper.pop().unwrap();
+            self.p_oper.pop();
+            let operator = op;
 
             // Make sure we have enough operands
-            if self.pila_o.len() < 2 {
-                eprintln!("Error: Not enough operands for operation code {}", operator);
-                // Attempt to recover by pushing dummy values, or this could panic
+            if self.pila_o.len() < 2 || self.p_types.len() < 2 {
+                self.report_internal(format!("Not enough operands for operation code {}", operator));
                 return;
             }
 
-            // Pop right operand and type
-            let right_operand = self.pila_o.pop().unwrap();
-            let right_type = self.p_types.pop().unwrap();
-
-            // Pop left operand and type
-            let left_operand = self.pila_o.pop().unwrap();
-            let left_type = self.p_types.pop().unwrap();
+            // Pop right operand and type, then left (the length check above makes these infallible)
+            let (Some(right_operand), Some(right_type), Some(left_operand), Some(left_type)) =
+                (self.pila_o.pop(), self.p_types.pop(), self.pila_o.pop(), self.p_types.pop()) else { return };
 
             // Perform type checking (semantics)
-            let op_enum = self.code_to_operator(operator); // Convert code back to Operator enum
-            let result_type_result = self.semantics(&left_type, &right_type, &op_enum);
+            let Some(op_enum) = self.code_to_operator(operator) else { // Convert code back to Operator enum
+                self.report_internal(format!("Unknown operator code {} on the operator stack", operator));
+                return;
+            };
+            let result_type_result = self.semantics(&left_type, &right_type, &op_enum)
+                .map_err(|msg| self.call_result_mismatch((left_operand, &left_type), (right_operand, &right_type), &op_enum).unwrap_or(msg));
 
             match result_type_result {
                 Ok(result_type) => {
+                    if let Some(folded_addr) = self.fold_constant_operation(operator, left_operand, right_operand) {
+                        self.pila_o.push(folded_addr);
+                        self.p_types.push(result_type);
+                        return;
+                    }
+
                     // Get next available temporary
                     let result_addr = self.avail_next(result_type.clone());
 
@@ -815,7 +1777,7 @@ impl QuadrupleGenerator {
                     self.p_types.push(result_type);
                 },
                 Err(msg) => {
-                    eprintln!("Type error: {}", msg);
+                    self.report_error(msg);
                     let result_addr = self.avail_next(Type::Int); // Default to Int on error
                     self.pila_o.push(result_addr);
                     self.p_types.push(Type::Int);
@@ -824,6 +1786,245 @@ impl QuadrupleGenerator {
         }
     }
 
+    /// Constant folding: when both operands are constants, compute the result now and
+    /// return the address of the resulting constant. Overflow and division by zero are
+    /// left for the VM to report at runtime.
+    fn fold_constant_operation(&mut self, operator: i32, left: i32, right: i32) -> Option<i32> {
+        if !self.fold_constants {
+            return None;
+        }
+
+        if let (Some(l), Some(r)) = (self.get_int_constant_value(left), self.get_int_constant_value(right)) {
+            let value = match operator {
+                OpCode::ADD => l.checked_add(r),
+                OpCode::SUB => l.checked_sub(r),
+                OpCode::MULT => l.checked_mul(r),
+                OpCode::DIV => l.checked_div(r),
+                OpCode::BAND => Some(l & r),
+                OpCode::BOR => Some(l | r),
+                OpCode::BXOR => Some(l ^ r),
+                OpCode::SHL => u32::try_from(r).ok().and_then(|r| l.checked_shl(r)),
+                OpCode::SHR => u32::try_from(r).ok().and_then(|r| l.checked_shr(r)),
+                _ => None,
+            }?;
+            return Some(self.get_or_create_int_constant(value));
+        }
+
+        let as_float = |qg: &Self, addr: i32| {
+            qg.get_float_constant_value(addr).or_else(|| qg.get_int_constant_value(addr).map(|i| i as f64))
+        };
+        let (l, r) = (as_float(self, left)?, as_float(self, right)?);
+        let value = match operator {
+            OpCode::ADD => l + r,
+            OpCode::SUB => l - r,
+            OpCode::MULT => l * r,
+            OpCode::DIV if r != 0.0 => l / r,
+            _ => return None,
+        };
+        Some(self.get_or_create_float_constant(value))
+    }
+
+    /// Global constant propagation: a global whose only write is `(ASSIGN, constant, -, g)`
+    /// in the straight-line start of main (before any jump, jump target or call) holds
+    /// that constant for the rest of the run, so later reads use the constant directly.
+    /// Globals written anywhere else, or assigned a value of the other numeric type, are
+    /// left alone. Bools have no constant cells and are never propagated.
+    fn run_global_propagation_pass(&mut self) {
+        let Some(dir) = self.function_directory.as_ref() else { return };
+        let Some(main_start) = dir.get_function("main").and_then(|info| info.start_quad_idx) else { return };
+        let main_start = main_start as usize;
+        let globals: HashSet<i32> = dir.get_global_variables()
+            .map(|vars| vars.values().map(|var| var.address).collect())
+            .unwrap_or_default();
+
+        let is_binary = |op: i32| matches!(op,
+            OpCode::ADD | OpCode::SUB | OpCode::MULT | OpCode::DIV
+                | OpCode::GT | OpCode::LT | OpCode::EQ | OpCode::NEQ | OpCode::FMOD
+                | OpCode::BAND | OpCode::BOR | OpCode::BXOR | OpCode::SHL | OpCode::SHR);
+        let reads_arg1 = |quad: &Quadruple| is_binary(quad.operation) || match quad.operation {
+            OpCode::ASSIGN => quad.arg1 != -1,
+            OpCode::PRINT | OpCode::PRINT_PART | OpCode::GOTOF | OpCode::GOTOT | OpCode::PARAM | OpCode::RETURN | OpCode::EXIT => true,
+            _ => false,
+        };
+
+        // The straight-line start of main runs exactly once, before anything else.
+        // Quad 0 jumps to main's first quad, so that target doesn't end the prefix
+        let jump_targets: HashSet<usize> = self.quad_queue.iter()
+            .filter(|quad| matches!(quad.operation, OpCode::GOTO | OpCode::GOTOF | OpCode::GOTOT))
+            .map(|quad| quad.result as usize)
+            .collect();
+        let prefix_end = (main_start..self.quad_queue.len())
+            .find(|&idx| {
+                (idx > main_start && jump_targets.contains(&idx)) || matches!(self.quad_queue[idx].operation,
+                    OpCode::GOTO | OpCode::GOTOF | OpCode::GOTOT | OpCode::ERA | OpCode::GOSUB | OpCode::EXIT | OpCode::HALT)
+            })
+            .unwrap_or(self.quad_queue.len());
+
+        let mut writes: HashMap<i32, Vec<usize>> = HashMap::new();
+        for (idx, quad) in self.quad_queue.iter().enumerate() {
+            if writes_result(quad.operation) && globals.contains(&quad.result) {
+                writes.entry(quad.result).or_default().push(idx);
+            }
+        }
+
+        for (global, write_indices) in writes {
+            let [write_idx] = write_indices[..] else { continue };
+            if !(main_start..prefix_end).contains(&write_idx) {
+                continue;
+            }
+            let write = &self.quad_queue[write_idx];
+            if write.operation != OpCode::ASSIGN {
+                continue;
+            }
+            let constant = write.arg1;
+            let same_type = if self.get_int_constant_value(constant).is_some() {
+                (MemoryAddresses::INT_START..MemoryAddresses::FLOAT_START).contains(&global)
+            } else if self.get_float_constant_value(constant).is_some() {
+                (MemoryAddresses::FLOAT_START..MemoryAddresses::BOOL_START).contains(&global)
+            } else {
+                false
+            };
+            if !same_type {
+                continue;
+            }
+
+            // Functions only run through calls, which all come after the write
+            for (idx, quad) in self.quad_queue.iter_mut().enumerate() {
+                if (main_start..=write_idx).contains(&idx) {
+                    continue;
+                }
+                if quad.arg1 == global && reads_arg1(quad) {
+                    quad.arg1 = constant;
+                }
+                if quad.arg2 == global && is_binary(quad.operation) {
+                    quad.arg2 = constant;
+                }
+            }
+        }
+    }
+
+    /// Peephole pass: an operation whose temporary result is only copied into a
+    /// variable of the same type writes straight into that variable instead, e.g.
+    /// `(ADD, a, b, t) (ASSIGN, t, -, x)` becomes `(ADD, a, b, x)`. Removed quadruples
+    /// shift the ones after them, so jump targets and function starts are renumbered,
+    /// unless pad_removed_quads_with_nops keeps them in place as NOPs.
+    fn run_peephole_pass(&mut self) {
+        let mut uses: HashMap<i32, usize> = HashMap::new();
+        let mut jump_targets = HashSet::new();
+        for quad in &self.quad_queue {
+            for addr in [quad.arg1, quad.arg2, quad.result] {
+                *uses.entry(addr).or_insert(0) += 1;
+            }
+            if matches!(quad.operation, OpCode::GOTO | OpCode::GOTOF | OpCode::GOTOT) {
+                jump_targets.insert(quad.result);
+            }
+        }
+
+        let same_segment_kind = |temp: i32, var: i32| {
+            let temp_kind = match temp {
+                t if (MemoryAddresses::TEMP_INT_START..MemoryAddresses::TEMP_FLOAT_START).contains(&t) => 0,
+                t if (MemoryAddresses::TEMP_FLOAT_START..MemoryAddresses::TEMP_BOOL_START).contains(&t) => 1,
+                t if t >= MemoryAddresses::TEMP_BOOL_START => 2,
+                _ => return false,
+            };
+            let var_kind = match var {
+                v if (MemoryAddresses::INT_START..MemoryAddresses::FLOAT_START).contains(&v) => 0,
+                v if (MemoryAddresses::FLOAT_START..MemoryAddresses::BOOL_START).contains(&v) => 1,
+                v if (MemoryAddresses::BOOL_START..MemoryAddresses::CTE_INT_START).contains(&v) => 2,
+                _ => return false,
+            };
+            temp_kind == var_kind
+        };
+
+        let mut removed = vec![false; self.quad_queue.len()];
+        for idx in 1..self.quad_queue.len() {
+            let (producer, copy) = (&self.quad_queue[idx - 1], &self.quad_queue[idx]);
+            let is_operation = matches!(
+                producer.operation,
+                OpCode::ADD | OpCode::SUB | OpCode::MULT | OpCode::DIV | OpCode::FMOD
+                    | OpCode::BAND | OpCode::BOR | OpCode::BXOR | OpCode::SHL | OpCode::SHR
+                    | OpCode::GT | OpCode::LT | OpCode::EQ | OpCode::NEQ
+            );
+            if is_operation
+                && !removed[idx - 1]
+                && copy.operation == OpCode::ASSIGN
+                && copy.arg1 == producer.result
+                && uses.get(&producer.result) == Some(&2)
+                && same_segment_kind(producer.result, copy.result)
+                && !jump_targets.contains(&(idx as i32))
+            {
+                let target = copy.result;
+                self.quad_queue[idx - 1].result = target;
+                removed[idx] = true;
+            }
+        }
+
+        if !removed.iter().any(|&r| r) {
+            return;
+        }
+        if self.peephole_nops {
+            for (quad, _) in self.quad_queue.iter_mut().zip(&removed).filter(|(_, &is_removed)| is_removed) {
+                *quad = Quadruple::new(OpCode::NOP, -1, -1, -1);
+            }
+            return;
+        }
+
+        // new_index[i] is where quadruple i (or the one that follows a removed i) ends up
+        let mut new_index = Vec::with_capacity(removed.len() + 1);
+        let mut kept = 0;
+        for &is_removed in &removed {
+            new_index.push(kept);
+            if !is_removed {
+                kept += 1;
+            }
+        }
+        new_index.push(kept);
+        let renumber = |idx: i32| -> i32 {
+            if idx >= 0 && (idx as usize) < new_index.len() { new_index[idx as usize] } else { idx }
+        };
+
+        let old_quads = std::mem::take(&mut self.quad_queue);
+        for (idx, mut quad) in old_quads.into_iter().enumerate() {
+            if removed[idx] {
+                continue;
+            }
+            if matches!(quad.operation, OpCode::GOTO | OpCode::GOTOF | OpCode::GOTOT) {
+                quad.result = renumber(quad.result);
+            }
+            self.quad_queue.push_back(quad);
+        }
+
+        let moves: HashMap<i32, i32> = self.function_directory.iter()
+            .flat_map(|dir| dir.get_all_functions().values())
+            .filter_map(|info| info.start_quad_idx.map(|start| (start, renumber(start))))
+            .collect();
+        self.patch_function_starts(&moves);
+    }
+
+    /// Point calls and the directory at moved function code.
+    /// `moves` maps an old start index to the new one; ERA/GOSUB quadruples and
+    /// `start_quad_idx` entries with other starts are left as they are.
+    /// Jumps inside the moved code are the caller's responsibility.
+    pub fn patch_function_starts(&mut self, moves: &HashMap<i32, i32>) {
+        for quad in self.quad_queue.iter_mut() {
+            if matches!(quad.operation, OpCode::ERA | OpCode::GOSUB) {
+                if let Some(&new_start) = moves.get(&quad.arg1) {
+                    quad.arg1 = new_start;
+                }
+            }
+        }
+        if let Some(ref mut dir) = self.function_directory {
+            let starts: Vec<(String, i32)> = dir.get_all_functions().iter()
+                .filter_map(|(name, info)| info.start_quad_idx.map(|start| (name.clone(), start)))
+                .collect();
+            for (name, start) in starts {
+                if let Some(&new_start) = moves.get(&start) {
+                    dir.set_function_start_quad(&name, new_start);
+                }
+            }
+        }
+    }
+
     /// Process an expression and generate appropriate quadruples
     fn process_expression(&mut self, expr: &Expression) {
         match expr {
@@ -841,6 +2042,11 @@ impl QuadrupleGenerator {
                         // Action 3: Push + or - to operator stack
                         self.action_push_add_sub_oper(operator.clone());
                     },
+                    Operator::BitAnd | Operator::BitOr | Operator::BitXor
+                        | Operator::ShiftLeft | Operator::ShiftRight => {
+                        let op_code = self.operator_to_code(operator);
+                        self.p_oper.push(op_code);
+                    },
                     // Comparison operators are pushed directly
                     Operator::GreaterThan | Operator::LessThan | Operator::Equal | Operator::NotEqual => {
                         let op_code = self.operator_to_code(operator);
@@ -864,6 +2070,11 @@ impl QuadrupleGenerator {
                         // Action 4: Process + and - operations
                         self.action_process_operation(false); // Process current ADD/SUB
                     },
+                    Operator::BitAnd | Operator::BitOr | Operator::BitXor
+                        | Operator::ShiftLeft | Operator::ShiftRight => {
+                        self.action_process_operation(true);  // Process pending MULT/DIV
+                        self.action_process_operation(false); // Process pending ADD/SUB, then this operator
+                    },
                     // Comparison operators: >, <, ==, !=
                     Operator::GreaterThan | Operator::LessThan | Operator::Equal | Operator::NotEqual => {
                         // First handle any pending arithmetic operations (MULT/DIV, then ADD/SUB)
@@ -874,78 +2085,51 @@ impl QuadrupleGenerator {
                         if let Some(op_code) = self.p_oper.pop() {
                             // Ensure it's a comparison operator code
                             if op_code >= OpCode::GT && op_code <= OpCode::NEQ {
-                                if self.pila_o.len() >= 2 && self.p_types.len() >= 2 {
-                                    let right_addr = self.pila_o.pop().unwrap();
-                                    let right_type = self.p_types.pop().unwrap();
-
-                                    let left_addr = self.pila_o.pop().unwrap();
-                                    let left_type = self.p_types.pop().unwrap();
+                                if let (Some(right_addr), Some(right_type), Some(left_addr), Some(left_type)) =
+                                    (self.pila_o.pop(), self.p_types.pop(), self.pila_o.pop(), self.p_types.pop()) {
+
+                                    // Lint: comparing a variable with itself is almost always a typo
+                                    if left_addr == right_addr && Self::is_variable_address(left_addr) {
+                                        let name = match left.as_ref() {
+                                            Expression::Identifier(id) => id.clone(),
+                                            _ => self.get_name_by_address(left_addr),
+                                        };
+                                        self.report_warning(format!("Comparison of '{}' with itself always has the same result", name));
+                                    }
 
-                                    let op_enum = self.code_to_operator(op_code);
+                                    let Some(op_enum) = self.code_to_operator(op_code) else {
+                                        self.report_internal(format!("Unknown comparison code {}", op_code));
+                                        return;
+                                    };
                                     match self.semantics(&left_type, &right_type, &op_enum) {
                                         Ok(result_type) => {
-                                            // Special case for boolean literals in comparisons
-                                            let mut special_case = false;
-                                            
-                                            // Check if we're comparing with a boolean literal
-                                            if matches!(op_enum, Operator::Equal | Operator::NotEqual) {
-                                                // Check if right operand is a boolean literal
-                                                if let Ok(vm_value) = self.get_direct_bool_value(right_addr) {
-                                                    if let VMValue::Bool(right_bool_value) = vm_value {
-                                                        // Create a quadruple with direct 0/1 for the boolean literal
-                                                        let result_temp_addr = self.avail_next(result_type.clone());
-                                                        self.quad_queue.push_back(Quadruple::new(
-                                                            op_code,
-                                                            left_addr,
-                                                            -2,  // Special marker for direct boolean value in comparison
-                                                            if right_bool_value { 1 } else { 0 }  // Store bool value directly in result
-                                                        ));
-                                                        // Push the result address to the operand stack
-                                                        self.pila_o.push(result_temp_addr);
-                                                        self.p_types.push(result_type.clone());
-                                                        special_case = true;
-                                                    }
-                                                }
-                                                // Check if left operand is a boolean literal
-                                                else if let Ok(vm_value) = self.get_direct_bool_value(left_addr) {
-                                                    if let VMValue::Bool(left_bool_value) = vm_value {
-                                                        // Create a quadruple with direct 0/1 for the boolean literal
-                                                        let result_temp_addr = self.avail_next(result_type.clone());
-                                                        self.quad_queue.push_back(Quadruple::new(
-                                                            op_code,
-                                                            -2,  // Special marker for direct boolean value in comparison
-                                                            right_addr,
-                                                            if left_bool_value { 1 } else { 0 }  // Store bool value directly in result
-                                                        ));
-                                                        // Push the result address to the operand stack
-                                                        self.pila_o.push(result_temp_addr);
-                                                        self.p_types.push(result_type.clone());
-                                                        special_case = true;
-                                                    }
-                                                }
-                                            }
-                                            
-                                            // Generate normal quadruple if not a special case
-                                            if !special_case {
-                                                let result_temp_addr = self.avail_next(result_type.clone());
-                                                let quad = Quadruple::new(op_code, left_addr, right_addr, result_temp_addr);
-                                                self.quad_queue.push_back(quad);
-                                                self.pila_o.push(result_temp_addr);
-                                                self.p_types.push(result_type);
-                                            }
+                                            // Bool literals only live in the generator; write them to their
+                                            // temporaries so the comparison reads real operands
+                                            self.materialize_bool_literal(left_addr);
+                                            self.materialize_bool_literal(right_addr);
+
+                                            let result_temp_addr = self.avail_next(result_type.clone());
+                                            let quad = Quadruple::new(op_code, left_addr, right_addr, result_temp_addr);
+                                            self.quad_queue.push_back(quad);
+                                            self.pila_o.push(result_temp_addr);
+                                            self.p_types.push(result_type);
                                         },
-                                        Err(e) => eprintln!("Type error during comparison: {}", e),
+                                        Err(e) => {
+                                            let message = self.call_result_mismatch((left_addr, &left_type), (right_addr, &right_type), &op_enum)
+                                                .unwrap_or_else(|| format!("Type error during comparison: {}", e));
+                                            self.report_error(message);
+                                        }
                                     }
                                 } else {
-                                    eprintln!("Error: Not enough operands/types for comparison op code {}", op_code);
+                                    self.report_internal(format!("Not enough operands/types for comparison op code {}", op_code));
                                 }
                             } else {
                                 // Should not happen if logic is correct, means a non-comparison op was popped
-                                eprintln!("Error: Unexpected operator {} on stack when expecting comparison.", op_code);
+                                self.report_internal(format!("Unexpected operator {} on stack when expecting comparison", op_code));
                                 self.p_oper.push(op_code); // Push it back
                             }
                         } else {
-                            eprintln!("Error: Operator stack empty when expecting comparison operator.");
+                            self.report_internal("Operator stack empty when expecting comparison operator".to_string());
                         }
                     }
                 }
@@ -954,7 +2138,7 @@ impl QuadrupleGenerator {
                 // Action 1: Push identifier to operand stack
                 match self.action_push_id(id) {
                     Ok(_) => {},
-                    Err(err) => eprintln!("Error during expression processing: {}", err),
+                    Err(err) => self.report_error(err),
                 }
             },
             Expression::IntegerLiteral(value) => {
@@ -969,9 +2153,51 @@ impl QuadrupleGenerator {
                 // Use the constant boolean memory segment
                 self.action_push_bool_constant(*value);
             }
+            Expression::BuiltinCall { function, arguments } => {
+                self.process_builtin_call(function, arguments);
+            }
+            Expression::FunctionCall(func_call) => self.process_call_expression(func_call),
         }
     }
 
+    /// Process a call to a built-in function used as an expression.
+    /// The result is always pushed to the operand stacks, even when the call is rejected.
+    fn process_builtin_call(&mut self, function: &Builtin, arguments: &[Expression]) {
+        let signature = BuiltinSignature::of(function);
+        let result_addr = self.avail_next(signature.returns.clone());
+        if arguments.len() != signature.arity {
+            self.report_error(format!("Built-in '{}' called with {} arguments, but expected {}. Signature: {}.",
+                signature.name, arguments.len(), signature.arity, signature));
+        } else {
+            let mut operands = Vec::new();
+            for (k, arg) in arguments.iter().enumerate() {
+                self.process_expression(arg);
+                let (Some(addr), Some(arg_type)) = (self.pila_o.pop(), self.p_types.pop()) else {
+                    self.report_internal(format!("Missing operand/type for argument {} of built-in '{}'", k + 1, signature.name));
+                    continue;
+                };
+                if matches!(arg_type, Type::Bool) {
+                    self.report_error(format!("Type mismatch for argument {} of built-in '{}'. Expected Int or Float, got {:?}. Signature: {}.",
+                        k + 1, signature.name, arg_type, signature));
+                }
+                operands.push((addr, arg_type));
+            }
+            let numeric = operands.iter().all(|(_, arg_type)| !matches!(arg_type, Type::Bool));
+            match (function, operands.as_slice()) {
+                (Builtin::Fmod, [(dividend, _), (divisor, _)]) if numeric => {
+                    self.quad_queue.push_back(Quadruple::new(OpCode::FMOD, *dividend, *divisor, result_addr));
+                }
+                // The VM promotes ints when storing into a float cell
+                (Builtin::FloatCast, [(addr, _)]) if numeric => {
+                    self.quad_queue.push_back(Quadruple::new(OpCode::ASSIGN, *addr, -1, result_addr));
+                }
+                _ => {}
+            }
+        }
+        self.pila_o.push(result_addr);
+        self.p_types.push(signature.returns);
+    }
+
     /// Convert operator enum to operation code
     fn operator_to_code(&self, op: &Operator) -> i32 {
         match op {
@@ -983,12 +2209,17 @@ impl QuadrupleGenerator {
             Operator::LessThan => OpCode::LT,
             Operator::Equal => OpCode::EQ,
             Operator::NotEqual => OpCode::NEQ,
+            Operator::BitAnd => OpCode::BAND,
+            Operator::BitOr => OpCode::BOR,
+            Operator::BitXor => OpCode::BXOR,
+            Operator::ShiftLeft => OpCode::SHL,
+            Operator::ShiftRight => OpCode::SHR,
         }
     }
 
     /// Convert operation code to operator enum for type checking
-    fn code_to_operator(&self, code: i32) -> Operator {
-        match code {
+    fn code_to_operator(&self, code: i32) -> Option<Operator> {
+        Some(match code {
             OpCode::ADD => Operator::Plus,
             OpCode::SUB => Operator::Minus,
             OpCode::MULT => Operator::Multiply,
@@ -997,8 +2228,13 @@ impl QuadrupleGenerator {
             OpCode::LT => Operator::LessThan,
             OpCode::EQ => Operator::Equal,
             OpCode::NEQ => Operator::NotEqual,
-            _ => panic!("Unknown operator code: {} cannot be converted to Operator enum.", code),
-        }
+            OpCode::BAND => Operator::BitAnd,
+            OpCode::BOR => Operator::BitOr,
+            OpCode::BXOR => Operator::BitXor,
+            OpCode::SHL => Operator::ShiftLeft,
+            OpCode::SHR => Operator::ShiftRight,
+            _ => return None,
+        })
     }
 
     /// Get the generated quadruples
@@ -1006,6 +2242,37 @@ impl QuadrupleGenerator {
         &self.quad_queue
     }
 
+    /// Mutable access to the quadruples, for tools that move code around (see patch_function_starts)
+    pub fn get_quadruples_mut(&mut self) -> &mut VecDeque<Quadruple> {
+        &mut self.quad_queue
+    }
+
+    /// Check the frame every program is generated in: quad 0 is a GOTO to main's first
+    /// quadruple and the last one is HALT. Passes that move code around must keep both.
+    pub fn verify_structure(&self) -> Result<(), String> {
+        let main_start = self.function_directory.as_ref()
+            .and_then(|dir| dir.get_function("main"))
+            .and_then(|info| info.start_quad_idx);
+        match self.quad_queue.front() {
+            Some(quad) if quad.operation != OpCode::GOTO => {
+                return Err(format!("Quadruple 0 should be GOTO main, found {}", quad.to_string()));
+            }
+            Some(quad) if quad.result <= 0 || quad.result as usize >= self.quad_queue.len() => {
+                return Err(format!("GOTO main at quadruple 0 jumps to {}, outside the {} quadruples", quad.result, self.quad_queue.len()));
+            }
+            Some(quad) if main_start.is_some_and(|start| start != quad.result) => {
+                return Err(format!("GOTO main at quadruple 0 jumps to {}, but main starts at {}", quad.result, main_start.unwrap_or(-1)));
+            }
+            Some(_) => {}
+            None => return Err("No quadruples were generated".to_string()),
+        }
+        match self.quad_queue.back() {
+            Some(quad) if quad.operation == OpCode::HALT => Ok(()),
+            Some(quad) => Err(format!("The last quadruple should be HALT, found {}", quad.to_string())),
+            None => Err("No quadruples were generated".to_string()),
+        }
+    }
+
     /// Get the generated quadruples and convert to string for display
     pub fn get_quadruples_as_strings(&self) -> Vec<String> {
         self.quad_queue.iter().map(|q| q.to_string()).collect()
@@ -1016,6 +2283,69 @@ impl QuadrupleGenerator {
         self.quad_queue.iter().map(|q| q.to_string_with_names(self)).collect()
     }
 
+    /// The lowering of the last `while` in the generated code, read back from the quads:
+    /// the last backward GOTO with a GOTOF between its target and itself that exits just
+    /// past it. Backward GOTOs without one are tail calls, not loops.
+    pub fn last_loop(&self) -> Option<LoopLowering> {
+        let quads = &self.quad_queue;
+        (0..quads.len()).rev().find_map(|back_jump| {
+            let quad = &quads[back_jump];
+            if quad.operation != OpCode::GOTO || quad.result < 0 || quad.result as usize > back_jump {
+                return None;
+            }
+            let condition_start = quad.result as usize;
+            let exit_jump = (condition_start..back_jump).find(|&idx| {
+                quads[idx].operation == OpCode::GOTOF && quads[idx].result as usize == back_jump + 1
+            })?;
+            Some(LoopLowering { condition_start, exit_jump, exit_target: back_jump + 1, back_jump })
+        })
+    }
+
+    /// Describe in words how the last `while` was lowered to GOTOF and GOTO
+    pub fn explain_last_loop(&self) -> String {
+        let Some(lowering) = self.last_loop() else {
+            return "No while loop has been generated".to_string();
+        };
+        let mut explanation = format!("while loop lowered to quads {}..={}\n", lowering.condition_start, lowering.back_jump);
+        explanation.push_str(&format!("  {}..{}: evaluate the condition\n", lowering.condition_start, lowering.exit_jump));
+        explanation.push_str(&format!("  {}: GOTOF leaves the loop for quad {} when the condition is false\n",
+                                      lowering.exit_jump, lowering.exit_target));
+        explanation.push_str(&format!("  {}..{}: loop body\n", lowering.exit_jump + 1, lowering.back_jump));
+        explanation.push_str(&format!("  {}: GOTO returns to the condition at quad {}\n", lowering.back_jump, lowering.condition_start));
+        explanation
+    }
+
+    /// The quadruples as a table with named operands, one per line after a header.
+    /// Every column is padded to its widest entry; the index is right-aligned.
+    pub fn format_listing(&self) -> String {
+        let header = ["#", "op", "arg1", "arg2", "result"].map(String::from);
+        let rows: Vec<[String; 5]> = std::iter::once(header)
+            .chain(self.quad_queue.iter().enumerate().map(|(idx, quad)| {
+                let [op, arg1, arg2, result] = quad.named_columns(self);
+                [idx.to_string(), op, arg1, arg2, result]
+            }))
+            .collect();
+
+        let mut widths = [0; 5];
+        for row in &rows {
+            for (width, cell) in widths.iter_mut().zip(row) {
+                *width = (*width).max(cell.chars().count());
+            }
+        }
+
+        let mut listing = String::new();
+        for [idx, op, arg1, arg2, result] in &rows {
+            let line = format!(
+                "{:>w0$}  {:<w1$}  {:<w2$}  {:<w3$}  {}",
+                idx, op, arg1, arg2, result,
+                w0 = widths[0], w1 = widths[1], w2 = widths[2], w3 = widths[3]
+            );
+            listing.push_str(line.trim_end());
+            listing.push('\n');
+        }
+        listing
+    }
+
     /// Get the variables from the function directory for debugging
     /// This shows variables for the *current* scope, or global if current is not "global".
     pub fn get_variables(&self) -> Vec<(String, i32)> {
@@ -1066,9 +2396,21 @@ impl QuadrupleGenerator {
             .collect()
     }
     
+    pub fn get_string_constants(&self) -> Vec<(String, i32)> {
+        self.string_constants.iter().enumerate()
+            .map(|(index, text)| (text.clone(), index as i32))
+            .collect()
+    }
+
+    pub fn get_string_constant_value(&self, address: i32) -> Option<&str> {
+        usize::try_from(address).ok()
+            .and_then(|index| self.string_constants.get(index))
+            .map(|text| text.as_str())
+    }
+
     pub fn get_bool_constants(&self) -> Vec<(bool, i32)> {
         self.bool_constants.iter().enumerate()
-            .map(|(index, &value)| (value, MemoryAddresses::TEMP_BOOL_START + index as i32))
+            .filter_map(|(index, &value)| value.map(|value| (value, MemoryAddresses::TEMP_BOOL_START + index as i32)))
             .collect()
     }
 
@@ -1094,34 +2436,39 @@ impl QuadrupleGenerator {
     pub fn get_bool_constant_value(&self, address: i32) -> Option<bool> {
         if address >= MemoryAddresses::TEMP_BOOL_START {
             let index = (address - MemoryAddresses::TEMP_BOOL_START) as usize;
-            return self.bool_constants.get(index).copied();
+            return self.bool_constants.get(index).copied().flatten();
         }
         None
     }
 
-    /// Get variable or constant name by address
-    pub fn get_name_by_address(&self, address: i32) -> String {
-        if address == -1 {
-            return "-".to_string(); // Placeholder for unused arguments
-        }
-
-        // Iterate through all known scopes in the function directory
+    /// Index every variable and parameter of the directory by address.
+    /// A parameter that is also listed among its scope's locals is named as a local.
+    fn build_address_names(&self) -> HashMap<i32, String> {
+        let mut names = HashMap::new();
         if let Some(ref directory) = self.function_directory {
             for (scope_name, func_info) in directory.get_all_functions() {
-                // Check local variables in this scope
-                for (var_name, var_info) in &func_info.local_variables {
-                    if var_info.address == address {
-                        return format!("{}.{} ({})", scope_name, var_name, address);
-                    }
-                }
-                // Check parameters in this scope
                 for (param_name, _, param_addr) in &func_info.parameters {
-                    if *param_addr == address {
-                        return format!("{}.{} (param) ({})", scope_name, param_name, address);
-                    }
+                    names.insert(*param_addr, format!("{}.{} (param)", scope_name, param_name));
+                }
+                for (var_name, var_info) in &func_info.local_variables {
+                    names.insert(var_info.address, format!("{}.{}", scope_name, var_name));
                 }
             }
         }
+        names
+    }
+
+    /// Get variable or constant name by address.
+    /// Variables are found through an index built once per directory, so a listing costs
+    /// one hash lookup per operand instead of a scan over every function's variables.
+    pub fn get_name_by_address(&self, address: i32) -> String {
+        if address == -1 {
+            return "-".to_string(); // Placeholder for unused arguments
+        }
+
+        if let Some(name) = self.address_names.get_or_init(|| self.build_address_names()).get(&address) {
+            return format!("{} ({})", name, address);
+        }
 
         // Check if it's a temporary integer
         if address >= MemoryAddresses::TEMP_INT_START && address < MemoryAddresses::TEMP_FLOAT_START {
@@ -1169,16 +2516,259 @@ impl QuadrupleGenerator {
         None
     }
 
-    /// Clear all stacks, queues, and tables for reuse or a new compilation pass
+    /// Serialize the generated program as the text of an object file.
+    /// `program_id` is the program's own scope, which is left out of the FUNCTIONS section.
+    pub fn to_obj_content(&self, program_id: &str, source_name: &str) -> String {
+        let mut obj_content = String::new();
+        obj_content.push_str("// BabyDuck Object File\n");
+        obj_content.push_str(&format!("// Source: {}\n\n", source_name));
+
+        // Integer Constants
+        obj_content.push_str("CONSTANTS_INT:\n");
+        for (value, addr) in self.get_int_constants() {
+            obj_content.push_str(&format!("{},{}\n", value, addr));
+        }
+        obj_content.push_str("END_CONSTANTS_INT\n\n");
+
+        // Float Constants
+        obj_content.push_str("CONSTANTS_FLOAT:\n");
+        for (value, addr) in self.get_float_constants() {
+            obj_content.push_str(&format!("{},{}\n", value, addr));
+        }
+        obj_content.push_str("END_CONSTANTS_FLOAT\n\n");
+
+        // String constants (address first, since the text may contain commas)
+        obj_content.push_str("CONSTANTS_STRING:\n");
+        for (text, addr) in self.get_string_constants() {
+            obj_content.push_str(&format!("{},{}\n", addr, escape_obj_string(&text)));
+        }
+        obj_content.push_str("END_CONSTANTS_STRING\n\n");
+
+        // Boolean Constants are no longer saved to the obj file
+
+        // Functions
+        obj_content.push_str("FUNCTIONS:\n");
+        if let Some(final_function_directory) = &self.function_directory {
+            for (name, info) in final_function_directory.get_all_functions() {
+                // Skip "global" scope and the program's own name entry, as they aren't callable functions.
+                // "main" is the entry point and is included.
+                if name == "global" || name == program_id { continue; }
+                let start_idx = info.start_quad_idx.unwrap_or(-1);
+                let param_count = info.parameters.len();
+                let local_var_count = info.local_variables.len(); // This counts distinct local variable declarations.
+
+                let mut param_addrs_str = String::new();
+                for (_, _, addr) in &info.parameters { // Iterate over (param_name, param_type, param_address)
+                    param_addrs_str.push_str(&format!(",{}", addr));
+                }
+
+                obj_content.push_str(&format!("{},{},{},{}{}\n", name, start_idx, param_count, local_var_count, param_addrs_str));
+            }
+        }
+        obj_content.push_str("END_FUNCTIONS\n\n");
+
+        // Result cells of functions with a return type, for callers outside the program
+        obj_content.push_str("FUNCTION_RETURNS:\n");
+        if let Some(final_function_directory) = &self.function_directory {
+            let mut returns: Vec<(&String, i32)> = final_function_directory.get_all_functions().iter()
+                .filter_map(|(name, info)| info.return_address.map(|addr| (name, addr)))
+                .collect();
+            returns.sort();
+            for (name, addr) in returns {
+                obj_content.push_str(&format!("{},{}\n", name, addr));
+            }
+        }
+        obj_content.push_str("END_FUNCTION_RETURNS\n\n");
+
+        // The cells each call saves and its return restores, so recursion gets fresh ones
+        obj_content.push_str("FUNCTION_FRAMES:\n");
+        for (name, cells) in self.function_frames(program_id) {
+            let cells: Vec<String> = cells.iter().map(|addr| addr.to_string()).collect();
+            obj_content.push_str(&format!("{},{}\n", name, cells.join(",")));
+        }
+        obj_content.push_str("END_FUNCTION_FRAMES\n\n");
+
+        // Quadruples (Machine-readable format)
+        obj_content.push_str("QUADRUPLES:\n");
+        for quad in self.get_quadruples().iter() {
+            obj_content.push_str(&format!("{},{},{},{}\n", quad.operation, quad.arg1, quad.arg2, quad.result));
+        }
+        obj_content.push_str("END_QUADRUPLES\n");
+
+        obj_content
+    }
+
+    /// The cells one activation of each called function owns, by function name: its parameters,
+    /// its locals and the temporaries its body writes. Functions without any are left out. Cells are static, so a call saves these
+    /// and its return puts them back, or a recursive call would overwrite its caller's values.
+    pub fn function_frames(&self, program_id: &str) -> Vec<(String, Vec<i32>)> {
+        let Some(dir) = self.function_directory.as_ref() else { return Vec::new() };
+        let mut frames: Vec<(String, Vec<i32>)> = dir.get_all_functions().iter()
+            .filter(|(name, info)| name.as_str() != "global" && name.as_str() != "main" && name.as_str() != program_id && !info.is_program)
+            .filter_map(|(name, info)| {
+                let start = usize::try_from(info.start_quad_idx?).ok()?;
+                let body = self.quad_queue.iter().skip(start);
+                let temps = body
+                    .take_while(|quad| quad.operation != OpCode::ENDFUNC)
+                    .filter(|quad| writes_result(quad.operation))
+                    .map(|quad| quad.result)
+                    .filter(|addr| (MemoryAddresses::TEMP_INT_START..MemoryAddresses::TEMP_BOOL_END).contains(addr));
+                let mut cells: Vec<i32> = info.parameters.iter().map(|(_, _, addr)| *addr)
+                    .chain(info.local_variables.values().map(|var| var.address))
+                    .chain(temps)
+                    .collect();
+                cells.sort_unstable();
+                cells.dedup();
+                (!cells.is_empty()).then(|| (name.clone(), cells))
+            })
+            .collect();
+        frames.sort();
+        frames
+    }
+
+    /// Transpile the quadruples into the source of a standalone Rust function, `run`, that
+    /// executes them as a match-based loop over the instruction pointer, with no VM needed.
+    /// It prints what the VM would print and returns the program's exit status.
+    pub fn to_rust_source(&self, program_id: &str) -> String {
+        let mut source = String::new();
+        source.push_str(&format!("// BabyDuck program '{}' transpiled from its quadruples\n", program_id));
+        source.push_str(RUST_RUNTIME_PRELUDE);
+
+        for (value, addr) in self.get_int_constants() {
+            source.push_str(&format!("    mem.insert({}, V::Int({}));\n", addr, value));
+        }
+        for (value, addr) in self.get_float_constants() {
+            // Through the bit pattern, so every float reads back exactly
+            source.push_str(&format!("    mem.insert({}, V::Float(f64::from_bits({:#x}))); // {}\n", addr, value.to_bits(), value));
+        }
+
+        // Frames by start index, which is what a GOSUB names
+        let frames: HashMap<i32, Vec<i32>> = self.function_frames(program_id).into_iter()
+            .filter_map(|(name, cells)| {
+                let start = self.function_directory.as_ref()?.get_function(&name)?.start_quad_idx?;
+                Some((start, cells))
+            })
+            .collect();
+
+        source.push_str("    let mut ip: usize = 0;\n    loop {\n        ip = match ip {\n");
+        for (idx, quad) in self.get_quadruples().iter().enumerate() {
+            let arm = self.rust_arm(idx, quad, &frames);
+            source.push_str(&format!("            {} => {},\n", idx, arm));
+        }
+        source.push_str("            _ => return Err(format!(\"No instruction at IP: {}\", ip)),\n");
+        source.push_str("        };\n    }\n}\n");
+        source
+    }
+
+    /// The Rust expression one quadruple turns into: it runs the instruction and yields the next IP
+    fn rust_arm(&self, idx: usize, quad: &Quadruple, frames: &HashMap<i32, Vec<i32>>) -> String {
+        let next = idx + 1;
+        let load = |address: i32| format!("load(&mem, {})?", address);
+        // The value is computed first, as reading memory while it is borrowed for the write won't compile
+        let store = |address: i32, value: String| format!("let value = {}; {}(&mut mem, {}, value)?", value, rust_store_fn(address), address);
+        let text = |address: i32| format!("{:?}", self.get_string_constant_value(address).unwrap_or_default());
+        match quad.operation {
+            OpCode::NOP => next.to_string(),
+            OpCode::ASSIGN if quad.arg1 == -1 && (quad.arg2 == 0 || quad.arg2 == 1) => {
+                format!("{{ {}; {} }}", store(quad.result, format!("V::Bool({})", quad.arg2 == 1)), next)
+            }
+            OpCode::ASSIGN => format!("{{ {}; {} }}", store(quad.result, load(quad.arg1)), next),
+            OpCode::ADD | OpCode::SUB | OpCode::MULT | OpCode::DIV => {
+                let op = match quad.operation { OpCode::ADD => '+', OpCode::SUB => '-', OpCode::MULT => '*', _ => '/' };
+                let value = format!("arith({:?}, {}, {})?", op, load(quad.arg1), load(quad.arg2));
+                format!("{{ {}; {} }}", store(quad.result, value), next)
+            }
+            OpCode::FMOD => {
+                let value = format!("V::Float(fmod(number({})?, number({})?)?)", load(quad.arg1), load(quad.arg2));
+                format!("{{ {}; {} }}", store(quad.result, value), next)
+            }
+            OpCode::BAND | OpCode::BOR | OpCode::BXOR | OpCode::SHL | OpCode::SHR => {
+                let op = match quad.operation { OpCode::BAND => "&", OpCode::BOR => "|", OpCode::BXOR => "^", OpCode::SHL => "<<", _ => ">>" };
+                let value = format!("V::Int(bits({:?}, {}, {})?)", op, load(quad.arg1), load(quad.arg2));
+                format!("{{ {}; {} }}", store(quad.result, value), next)
+            }
+            OpCode::GT | OpCode::LT | OpCode::EQ | OpCode::NEQ => {
+                let op = match quad.operation { OpCode::GT => ">", OpCode::LT => "<", OpCode::EQ => "==", _ => "!=" };
+                let value = format!("V::Bool(compare({:?}, {}, {})?)", op, load(quad.arg1), load(quad.arg2));
+                format!("{{ {}; {} }}", store(quad.result, value), next)
+            }
+            OpCode::PRINT if quad.arg2 == PrintBase::HEX || quad.arg2 == PrintBase::BIN => {
+                let format = if quad.arg2 == PrintBase::HEX { "{:#x}" } else { "{:#b}" };
+                format!("{{ println!({:?}, int_only({})?); {} }}", format, load(quad.arg1), next)
+            }
+            OpCode::PRINT if quad.result >= 0 => {
+                format!("{{ println!(\"{{}}{{}}\", show({}), {}); {} }}", load(quad.arg1), text(quad.result), next)
+            }
+            OpCode::PRINT => format!("{{ println!(\"{{}}\", show({})); {} }}", load(quad.arg1), next),
+            OpCode::READ => {
+                // The first target of a read statement consumes a whole input line
+                let refill = if quad.arg1 == 0 { format!("read_line(&mut pending_input, {})?; ", quad.arg2) } else { String::new() };
+                let kind = rust_store_fn(quad.result).trim_start_matches("store_");
+                let value = format!("parse_input(&mut pending_input, {:?})?", kind);
+                format!("{{ {}{}; {} }}", refill, store(quad.result, value), next)
+            }
+            OpCode::PRINT_PART => {
+                let mut parts = String::new();
+                if quad.arg1 >= 0 {
+                    parts.push_str(&format!("line.push_str(&show({})); ", load(quad.arg1)));
+                }
+                if quad.result >= 0 {
+                    parts.push_str(&format!("line.push_str({}); ", text(quad.result)));
+                }
+                format!("{{ {}{} }}", parts, next)
+            }
+            OpCode::PRINT_LINE => format!("{{ println!(\"{{}}\", line); line.clear(); {} }}", next),
+            OpCode::LABEL => format!("{{ println!(\"{{}}\", {}); {} }}", text(quad.arg1), next),
+            OpCode::GOTO => quad.result.to_string(),
+            OpCode::GOTOF => format!("if truthy({})? {{ {} }} else {{ {} }}", load(quad.arg1), next, quad.result),
+            OpCode::GOTOT => format!("if truthy({})? {{ {} }} else {{ {} }}", load(quad.arg1), quad.result, next),
+            OpCode::ERA => format!("{{ staged.clear(); {} }}", next),
+            OpCode::PARAM => format!("{{ staged.push({}); {} }}", load(quad.arg1), next),
+            OpCode::GOSUB => {
+                let parameters: Vec<i32> = self.function_directory.as_ref()
+                    .and_then(|dir| dir.get_all_functions().values().find(|info| info.start_quad_idx == Some(quad.arg1)))
+                    .map(|info| info.parameters.iter().map(|(_, _, addr)| *addr).collect())
+                    .unwrap_or_default();
+                let mut call = format!("{{ if staged.len() != {} {{ return Err(format!(\"GOSUB: Expected {} staged params, got {{}}\", staged.len())); }} ", parameters.len(), parameters.len());
+                call.push_str(&format!("frames.push(save_frame(&mem, &{:?})); ", frames.get(&quad.arg1).cloned().unwrap_or_default()));
+                for (k, addr) in parameters.iter().enumerate() {
+                    call.push_str(&format!("{}; ", store(*addr, format!("staged[{}]", k))));
+                }
+                call.push_str(&format!("call(&mut call_stack, {})?; {} }}", next, quad.arg1));
+                call
+            }
+            OpCode::ENDFUNC => "{ restore_frame(&mut mem, frames.pop()); match call_stack.pop() { Some(ret) => ret, None => return Ok(0) } }".to_string(),
+            OpCode::RETURN => format!("{{ {}; restore_frame(&mut mem, frames.pop()); match call_stack.pop() {{ Some(ret) => ret, None => return Ok(0) }} }}", store(quad.result, load(quad.arg1))),
+            OpCode::HALT => "return Ok(0)".to_string(),
+            OpCode::EXIT => format!("return Ok(int_only({})?)", load(quad.arg1)),
+            other => format!("return Err(\"Unknown OpCode: {} at IP: {}\".to_string())", other, idx),
+        }
+    }
+
+    /// Start over on another program with its own directory, keeping the compile options.
+    /// One generator can compile any number of programs this way, one after another.
+    pub fn reset_for(&mut self, directory: FunctionDirectory) {
+        self.clear();
+        self.set_function_directory(directory);
+    }
+
+    /// Clear all stacks, queues, and tables for reuse or a new compilation pass.
+    /// The function directory and compile options stay; use reset_for to switch programs.
     pub fn clear(&mut self) {
         self.p_oper.clear();
         self.pila_o.clear();
         self.p_types.clear();
         self.p_jumps.clear();  // Clear jumps stack
+        self.call_results.clear();
         self.quad_queue.clear();
         self.int_constants.clear();
         self.float_constants.clear();
+        self.int_constant_index.clear();
+        self.float_constant_index.clear();
         self.bool_constants.clear();  // Clear bool constants
+        self.string_constants.clear();
+        self.semantic_errors.clear();
+        self.warnings.clear();
 
         // Reset counters
         self.temp_int_counter = MemoryAddresses::TEMP_INT_START;
@@ -1190,6 +2780,218 @@ impl QuadrupleGenerator {
     }
 }
 
+/// What a built-in function accepts and returns. Every argument is numeric (an int or a float).
+struct BuiltinSignature {
+    name: &'static str,
+    arity: usize,
+    returns: Type,
+}
+
+impl BuiltinSignature {
+    /// The signature table consulted when checking built-in calls
+    fn of(function: &Builtin) -> Self {
+        match function {
+            Builtin::Fmod => BuiltinSignature { name: "fmod", arity: 2, returns: Type::Float },
+            Builtin::FloatCast => BuiltinSignature { name: "float", arity: 1, returns: Type::Float },
+        }
+    }
+}
+
+impl std::fmt::Display for BuiltinSignature {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let parameters = vec!["Int|Float"; self.arity].join(", ");
+        write!(f, "{}({}) -> {:?}", self.name, parameters, self.returns)
+    }
+}
+
+/// Escape a string constant so it fits on one line of the object file
+pub fn escape_obj_string(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('\n', "\\n")
+}
+
+/// Whether an instruction stores a value in its result field
+fn writes_result(op: i32) -> bool {
+    matches!(op,
+        OpCode::ASSIGN | OpCode::ADD | OpCode::SUB | OpCode::MULT | OpCode::DIV
+            | OpCode::GT | OpCode::LT | OpCode::EQ | OpCode::NEQ | OpCode::FMOD
+            | OpCode::BAND | OpCode::BOR | OpCode::BXOR | OpCode::SHL | OpCode::SHR
+            | OpCode::READ | OpCode::RETURN)
+}
+
+/// The writer a transpiled program uses for an address: each segment converts values as the VM's memory does
+fn rust_store_fn(address: i32) -> &'static str {
+    match address {
+        MemoryAddresses::INT_START..MemoryAddresses::FLOAT_START
+        | MemoryAddresses::CTE_INT_START..MemoryAddresses::CTE_FLOAT_START
+        | MemoryAddresses::TEMP_INT_START..MemoryAddresses::TEMP_FLOAT_START => "store_int",
+        MemoryAddresses::FLOAT_START..MemoryAddresses::BOOL_START
+        | MemoryAddresses::CTE_FLOAT_START..MemoryAddresses::TEMP_INT_START
+        | MemoryAddresses::TEMP_FLOAT_START..MemoryAddresses::TEMP_BOOL_START => "store_float",
+        _ => "store_bool",
+    }
+}
+
+/// Everything a transpiled program needs before its first quadruple: the value type,
+/// the helpers its instructions call and the machine state
+const RUST_RUNTIME_PRELUDE: &str = r#"#[allow(dead_code, unused_mut, unused_variables, unreachable_code, clippy::all)]
+pub fn run() -> Result<i32, String> {
+    use std::collections::{HashMap, VecDeque};
+
+    #[derive(Clone, Copy, Debug)]
+    enum V { Int(i32), Float(f64), Bool(bool) }
+
+    fn load(mem: &HashMap<i32, V>, addr: i32) -> Result<V, String> {
+        mem.get(&addr).copied().ok_or_else(|| format!("Read from uninitialized memory at address {}", addr))
+    }
+    // A bool written to an int cell keeps reading back as a bool
+    fn store_int(mem: &mut HashMap<i32, V>, addr: i32, value: V) -> Result<(), String> {
+        match value {
+            V::Float(_) => return Err(format!("Type mismatch: cannot assign Float to Int address {}", addr)),
+            value => mem.insert(addr, value),
+        };
+        Ok(())
+    }
+    fn store_float(mem: &mut HashMap<i32, V>, addr: i32, value: V) -> Result<(), String> {
+        match value {
+            V::Float(f) => mem.insert(addr, V::Float(f)),
+            V::Int(i) => mem.insert(addr, V::Float(i as f64)),
+            V::Bool(_) => return Err(format!("Type mismatch: cannot assign Bool to Float address {}", addr)),
+        };
+        Ok(())
+    }
+    fn store_bool(mem: &mut HashMap<i32, V>, addr: i32, value: V) -> Result<(), String> {
+        match value {
+            V::Bool(b) => mem.insert(addr, V::Bool(b)),
+            V::Int(i) => mem.insert(addr, V::Bool(i != 0)),
+            V::Float(_) => return Err(format!("Type mismatch: cannot assign Float to Bool address {}", addr)),
+        };
+        Ok(())
+    }
+    fn number(value: V) -> Result<f64, String> {
+        match value {
+            V::Int(i) => Ok(i as f64),
+            V::Float(f) => Ok(f),
+            V::Bool(_) => Err("Expected a numeric value, found a bool".to_string()),
+        }
+    }
+    fn int_only(value: V) -> Result<i32, String> {
+        match value {
+            V::Int(i) => Ok(i),
+            other => Err(format!("Expected an int, got {:?}", other)),
+        }
+    }
+    fn arith(op: char, a: V, b: V) -> Result<V, String> {
+        match (a, b) {
+            (V::Int(x), V::Int(y)) => match op {
+                '+' => Ok(V::Int(x + y)),
+                '-' => Ok(V::Int(x - y)),
+                '*' => Ok(V::Int(x * y)),
+                _ if y == 0 => Err(format!("Division by zero: {} / {}", x, y)),
+                _ => Ok(V::Int(x / y)),
+            },
+            (V::Bool(_), _) | (_, V::Bool(_)) => Err(format!("Type mismatch: arithmetic op {} on {:?} and {:?}", op, a, b)),
+            (a, b) => {
+                let (x, y) = (number(a)?, number(b)?);
+                match op {
+                    '+' => Ok(V::Float(x + y)),
+                    '-' => Ok(V::Float(x - y)),
+                    '*' => Ok(V::Float(x * y)),
+                    _ if y == 0.0 => Err(format!("Division by zero: {} / {}", x, y)),
+                    _ => Ok(V::Float(x / y)),
+                }
+            }
+        }
+    }
+    fn fmod(x: f64, y: f64) -> Result<f64, String> {
+        if y == 0.0 { Err(format!("Division by zero: fmod({}, {})", x, y)) } else { Ok(x % y) }
+    }
+    fn bits(op: &str, a: V, b: V) -> Result<i32, String> {
+        let (x, y) = (int_only(a)?, int_only(b)?);
+        let shifted = |shift: fn(i32, u32) -> Option<i32>| if y < 0 { None } else { shift(x, y as u32) }
+            .ok_or_else(|| format!("Invalid shift amount {} for a 32-bit int", y));
+        match op {
+            "&" => Ok(x & y),
+            "|" => Ok(x | y),
+            "^" => Ok(x ^ y),
+            "<<" => shifted(i32::checked_shl),
+            _ => shifted(i32::checked_shr),
+        }
+    }
+    // Bools compare to numbers as 0 or 1, but only for equality
+    fn compare(op: &str, a: V, b: V) -> Result<bool, String> {
+        if let (V::Int(x), V::Int(y)) = (a, b) {
+            return Ok(match op { ">" => x > y, "<" => x < y, "==" => x == y, _ => x != y });
+        }
+        let ordered = matches!(op, ">" | "<");
+        if ordered && (matches!(a, V::Bool(_)) || matches!(b, V::Bool(_))) {
+            return Err(format!("Invalid comparison operator {} for {:?} and {:?}", op, a, b));
+        }
+        let as_number = |v: V| match v { V::Int(i) => i as f64, V::Float(f) => f, V::Bool(b) => b as i32 as f64 };
+        let (x, y) = (as_number(a), as_number(b));
+        Ok(match op { ">" => x > y, "<" => x < y, "==" => x == y, _ => x != y })
+    }
+    fn truthy(value: V) -> Result<bool, String> {
+        match value {
+            V::Bool(b) => Ok(b),
+            V::Int(i) => Ok(i != 0),
+            V::Float(_) => Err("Jump condition cannot be a float".to_string()),
+        }
+    }
+    fn show(value: V) -> String {
+        match value {
+            V::Int(i) => i.to_string(),
+            V::Float(f) => f.to_string(),
+            V::Bool(b) => b.to_string(),
+        }
+    }
+    fn read_line(pending: &mut VecDeque<String>, expected: usize) -> Result<(), String> {
+        let mut line = String::new();
+        if std::io::stdin().read_line(&mut line).map_err(|e| format!("READ: {}", e))? == 0 {
+            return Err("READ: Unexpected end of input".to_string());
+        }
+        *pending = line.split_whitespace().map(|token| token.to_string()).collect();
+        if pending.len() != expected {
+            return Err(format!("READ: Expected {} value(s) on the input line, got {}", expected, pending.len()));
+        }
+        Ok(())
+    }
+    fn parse_input(pending: &mut VecDeque<String>, kind: &str) -> Result<V, String> {
+        let token = pending.pop_front().ok_or_else(|| "READ: No input value left".to_string())?;
+        let parsed = match kind {
+            "int" => token.parse().ok().map(V::Int),
+            "float" => token.parse().ok().map(V::Float),
+            _ => token.parse().ok().map(V::Bool),
+        };
+        parsed.ok_or_else(|| format!("READ: '{}' is not a valid {}", token, kind))
+    }
+    fn call(call_stack: &mut Vec<usize>, return_ip: usize) -> Result<(), String> {
+        if call_stack.len() >= 10_000 {
+            return Err("Stack overflow: call depth exceeded 10000".to_string());
+        }
+        call_stack.push(return_ip);
+        Ok(())
+    }
+    // A call keeps its function's cells as they were, and its return puts them back
+    fn save_frame(mem: &HashMap<i32, V>, cells: &[i32]) -> Vec<(i32, Option<V>)> {
+        cells.iter().map(|addr| (*addr, mem.get(addr).copied())).collect()
+    }
+    fn restore_frame(mem: &mut HashMap<i32, V>, frame: Option<Vec<(i32, Option<V>)>>) {
+        for (addr, value) in frame.unwrap_or_default() {
+            match value {
+                Some(value) => mem.insert(addr, value),
+                None => mem.remove(&addr),
+            };
+        }
+    }
+
+    let mut mem: HashMap<i32, V> = HashMap::new();
+    let mut call_stack: Vec<usize> = Vec::new();
+    let mut frames: Vec<Vec<(i32, Option<V>)>> = Vec::new();
+    let mut staged: Vec<V> = Vec::new();
+    let mut line = String::new();
+    let mut pending_input: VecDeque<String> = VecDeque::new();
+"#;
+
 // Add VMValue enum for internal use in the quadruple generator
 #[derive(Debug)]
 enum VMValue {
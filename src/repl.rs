@@ -0,0 +1,160 @@
+// Interactive mode: compiles and runs BabyDuck statements one line at a time
+
+use std::io::{self, BufRead, Write};
+
+use crate::ast::{Program, VarDeclaration};
+use crate::babyduck;
+use crate::function_directory::FunctionDirectory;
+use crate::quadruples::{escape_obj_string, QuadrupleGenerator};
+use crate::vm::VM;
+
+const REPL_PROGRAM_ID: &str = "repl";
+
+/// A running session. Declarations and variable values persist between lines; every
+/// statement line is compiled into quadruples appended to the session's program and run.
+pub struct Repl {
+    vars: Vec<VarDeclaration>,
+    quad_gen: QuadrupleGenerator,
+    vm: VM,
+}
+
+impl Default for Repl {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Repl {
+    pub fn new() -> Self {
+        let mut repl = Repl {
+            vars: Vec::new(),
+            quad_gen: QuadrupleGenerator::new(),
+            vm: VM::new(),
+        };
+        repl.quad_gen.set_function_directory(Self::directory_for(&[]).unwrap());
+        repl
+    }
+
+    /// Access the session's VM, e.g. to capture its output
+    pub fn vm_mut(&mut self) -> &mut VM {
+        &mut self.vm
+    }
+
+    /// Build the directory for a program that only declares the given globals
+    fn directory_for(vars: &[VarDeclaration]) -> Result<FunctionDirectory, String> {
+        let program = Program {
+            id: REPL_PROGRAM_ID.to_string(),
+            vars: vars.to_vec(),
+            funcs: Vec::new(),
+            main_body: Vec::new(),
+        };
+        FunctionDirectory::from_program(&program).map_err(|e| e.to_string())
+    }
+
+    /// Compile and run one line: either a `var` declaration or one or more statements.
+    /// Returns the exit code if the line ended the program through `exit`.
+    pub fn eval_line(&mut self, line: &str) -> Result<Option<i32>, String> {
+        let line = line.trim();
+        if line.is_empty() {
+            return Ok(None);
+        }
+
+        if line.starts_with("var ") {
+            self.declare(line)?;
+            return Ok(None);
+        }
+
+        let source = format!("program {}; main {{ {} }} end", REPL_PROGRAM_ID, line);
+        let program = babyduck::ProgramParser::new().parse(&source)
+            .map_err(|e| format!("Parsing failed: {:?}", e))?;
+        let first_new_quad = self.quad_gen.append_statements(&program.main_body)?;
+        for warning in self.quad_gen.take_warnings() {
+            eprintln!("Warning: {}", warning);
+        }
+        if first_new_quad == self.quad_gen.get_quadruples().len() {
+            return Ok(None);
+        }
+
+        self.vm.load_obj_str(&self.build_increment_obj(first_new_quad))?;
+        match self.vm.run() {
+            Ok(exit_code) if !self.vm.is_at_end() => Ok(Some(exit_code)),
+            Ok(_) => Ok(None),
+            Err(e) => {
+                self.vm.skip_to_end();
+                Err(e)
+            }
+        }
+    }
+
+    /// Add the variables of a `var` line to the session's globals
+    fn declare(&mut self, line: &str) -> Result<(), String> {
+        let source = format!("program {}; {} main {{ }} end", REPL_PROGRAM_ID, line);
+        let program = babyduck::ProgramParser::new().parse(&source)
+            .map_err(|e| format!("Parsing failed: {:?}", e))?;
+
+        // Globals are allocated in declaration order, so earlier variables keep their addresses
+        let mut vars = self.vars.clone();
+        vars.extend(program.vars);
+        let directory = Self::directory_for(&vars)?;
+        self.quad_gen.set_function_directory(directory);
+        self.vars = vars;
+        Ok(())
+    }
+
+    /// Serialize the constants and the quadruples generated for the latest line
+    fn build_increment_obj(&self, first_new_quad: usize) -> String {
+        let mut obj_content = String::new();
+        obj_content.push_str("CONSTANTS_INT:\n");
+        for (value, addr) in self.quad_gen.get_int_constants() {
+            obj_content.push_str(&format!("{},{}\n", value, addr));
+        }
+        obj_content.push_str("END_CONSTANTS_INT\n");
+
+        obj_content.push_str("CONSTANTS_FLOAT:\n");
+        for (value, addr) in self.quad_gen.get_float_constants() {
+            obj_content.push_str(&format!("{},{}\n", value, addr));
+        }
+        obj_content.push_str("END_CONSTANTS_FLOAT\n");
+
+        obj_content.push_str("CONSTANTS_STRING:\n");
+        for (text, addr) in self.quad_gen.get_string_constants() {
+            obj_content.push_str(&format!("{},{}\n", addr, escape_obj_string(&text)));
+        }
+        obj_content.push_str("END_CONSTANTS_STRING\n");
+
+        obj_content.push_str("QUADRUPLES:\n");
+        for quad in self.quad_gen.get_quadruples().iter().skip(first_new_quad) {
+            obj_content.push_str(&format!("{},{},{},{}\n", quad.operation, quad.arg1, quad.arg2, quad.result));
+        }
+        obj_content.push_str("END_QUADRUPLES\n");
+        obj_content
+    }
+}
+
+/// Read lines from stdin until EOF or `exit`, returning the program's exit code
+pub fn run_interactive() -> i32 {
+    let mut repl = Repl::new();
+    let stdin = io::stdin();
+    println!("BabyDuck REPL. Declare with `var x: int;`, then enter statements. Ctrl-D to quit.");
+
+    loop {
+        print!("bd> ");
+        let _ = io::stdout().flush();
+
+        let mut line = String::new();
+        match stdin.lock().read_line(&mut line) {
+            Ok(0) => return 0,
+            Ok(_) => {}
+            Err(e) => {
+                eprintln!("Error reading input: {}", e);
+                return 1;
+            }
+        }
+
+        match repl.eval_line(&line) {
+            Ok(Some(exit_code)) => return exit_code,
+            Ok(None) => {}
+            Err(e) => eprintln!("Error: {}", e),
+        }
+    }
+}
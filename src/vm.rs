@@ -1,10 +1,13 @@
-use std::collections::{HashMap};
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::fs;
 use std::io::{BufRead, BufReader};
 
+use crate::Value;
+
 // Define OpCodes (consistent with quadruples.rs)
 struct OpCode;
 impl OpCode {
+    pub const NOP: i32 = 0;
     pub const ASSIGN: i32 = 1;
     pub const ADD: i32 = 4;
     pub const SUB: i32 = 5;
@@ -14,14 +17,43 @@ impl OpCode {
     pub const LT: i32 = 9;
     pub const EQ: i32 = 10;
     pub const NEQ: i32 = 11;
+    pub const FMOD: i32 = 12;
+    pub const BAND: i32 = 13;
+    pub const BOR: i32 = 14;
+    pub const BXOR: i32 = 15;
+    pub const SHL: i32 = 16;
+    pub const SHR: i32 = 17;
     pub const PRINT: i32 = 20;
+    pub const READ: i32 = 21;
+    pub const LABEL: i32 = 22;
+    pub const PRINT_PART: i32 = 23;
+    pub const PRINT_LINE: i32 = 24;
     pub const GOTO: i32 = 30;
     pub const GOTOF: i32 = 31;
+    pub const GOTOT: i32 = 32;
     pub const ERA: i32 = 40;
     pub const PARAM: i32 = 41;
     pub const GOSUB: i32 = 42;
     pub const ENDFUNC: i32 = 43;
+    pub const RETURN: i32 = 44;
     pub const HALT: i32 = 50;
+    pub const EXIT: i32 = 51;
+
+    /// Every opcode the VM understands, used to map symbolic names back to numbers
+    const ALL: &'static [i32] = &[
+        Self::NOP, Self::ASSIGN, Self::ADD, Self::SUB, Self::MULT, Self::DIV,
+        Self::GT, Self::LT, Self::EQ, Self::NEQ, Self::FMOD,
+        Self::BAND, Self::BOR, Self::BXOR, Self::SHL, Self::SHR,
+        Self::PRINT, Self::READ, Self::LABEL, Self::PRINT_PART, Self::PRINT_LINE,
+        Self::GOTO, Self::GOTOF, Self::GOTOT,
+        Self::ERA, Self::PARAM, Self::GOSUB, Self::ENDFUNC, Self::RETURN,
+        Self::HALT, Self::EXIT,
+    ];
+
+    /// Opcode for a name written by `--obj-format=names`, e.g. "GOTO"
+    fn from_name(name: &str) -> Option<i32> {
+        Self::ALL.iter().copied().find(|&op| opcode_name(op) == name)
+    }
 }
 
 // Define Memory Address Constants
@@ -34,6 +66,128 @@ const CTE_BOOL_START: i32 = 4800;
 const TEMP_INT_START: i32 = 5000;
 const TEMP_FLOAT_START: i32 = 6000;
 const TEMP_BOOL_START: i32 = 7000;
+const TEMP_BOOL_END: i32 = 8000; // First address past every segment
+
+/// Memory segment an address belongs to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Segment {
+    Int,
+    Float,
+    Bool,
+    ConstInt,
+    ConstFloat,
+    ConstBool,
+    TempInt,
+    TempFloat,
+    TempBool,
+    Unmapped,
+}
+
+/// Classify an address by the segment ranges
+pub fn classify_address(address: i32) -> Segment {
+    match address {
+        a if a < INT_START => Segment::Unmapped,
+        a if a < FLOAT_START => Segment::Int,
+        a if a < BOOL_START => Segment::Float,
+        a if a < CTE_INT_START => Segment::Bool,
+        a if a < CTE_FLOAT_START => Segment::ConstInt,
+        a if a < CTE_BOOL_START => Segment::ConstFloat,
+        a if a < TEMP_INT_START => Segment::ConstBool,
+        a if a < TEMP_FLOAT_START => Segment::TempInt,
+        a if a < TEMP_BOOL_START => Segment::TempFloat,
+        a if a < TEMP_BOOL_END => Segment::TempBool,
+        _ => Segment::Unmapped,
+    }
+}
+
+/// Parse the opcode column of a quadruple: a number, or a name written by --obj-format=names
+fn parse_opcode(text: &str) -> Result<i32, String> {
+    match text.parse::<i32>() {
+        Ok(op) => Ok(op),
+        Err(_) => OpCode::from_name(text)
+            .ok_or_else(|| format!("Unknown opcode '{}' in QUADRUPLES section", text)),
+    }
+}
+
+/// The fields of a quadruple that hold memory addresses. Jump targets, parameter
+/// indices, print bases, string constants and the -1 "unused" marker are left out.
+fn address_operands(op: i32, arg1: i32, arg2: i32, result: i32) -> Vec<i32> {
+    let fields = match op {
+        OpCode::ADD | OpCode::SUB | OpCode::MULT | OpCode::DIV | OpCode::FMOD
+            | OpCode::BAND | OpCode::BOR | OpCode::BXOR | OpCode::SHL | OpCode::SHR => vec![arg1, arg2, result],
+        // Old object files put a bool literal in the result of a comparison with a -2 operand
+        OpCode::GT | OpCode::LT | OpCode::EQ | OpCode::NEQ if arg1 == -2 || arg2 == -2 => vec![arg1, arg2],
+        OpCode::GT | OpCode::LT | OpCode::EQ | OpCode::NEQ => vec![arg1, arg2, result],
+        OpCode::ASSIGN | OpCode::RETURN => vec![arg1, result],
+        OpCode::READ => vec![result],
+        OpCode::PRINT | OpCode::PRINT_PART | OpCode::GOTOF | OpCode::GOTOT | OpCode::PARAM | OpCode::EXIT => vec![arg1],
+        _ => Vec::new(),
+    };
+    fields.into_iter().filter(|&field| field >= 0).collect()
+}
+
+// Deepest chain of nested GOSUBs before execution is aborted
+const MAX_CALL_DEPTH: usize = 10_000;
+
+// Base indicators carried in arg2 of PRINT (consistent with PrintBase in quadruples.rs)
+const PRINT_BASE_HEX: i32 = 16;
+const PRINT_BASE_BIN: i32 = 2;
+
+/// Readable name of an opcode for listings and symbolic object files
+pub fn opcode_name(op: i32) -> &'static str {
+    match op {
+        OpCode::NOP => "NOP",
+        OpCode::ASSIGN => "ASSIGN",
+        OpCode::ADD => "ADD",
+        OpCode::SUB => "SUB",
+        OpCode::MULT => "MULT",
+        OpCode::DIV => "DIV",
+        OpCode::GT => "GT",
+        OpCode::LT => "LT",
+        OpCode::EQ => "EQ",
+        OpCode::NEQ => "NEQ",
+        OpCode::FMOD => "FMOD",
+        OpCode::BAND => "BAND",
+        OpCode::BOR => "BOR",
+        OpCode::BXOR => "BXOR",
+        OpCode::SHL => "SHL",
+        OpCode::SHR => "SHR",
+        OpCode::PRINT => "PRINT",
+        OpCode::READ => "READ",
+        OpCode::LABEL => "LABEL",
+        OpCode::PRINT_PART => "PRINT_PART",
+        OpCode::PRINT_LINE => "PRINT_LINE",
+        OpCode::GOTO => "GOTO",
+        OpCode::GOTOF => "GOTOF",
+        OpCode::GOTOT => "GOTOT",
+        OpCode::ERA => "ERA",
+        OpCode::PARAM => "PARAM",
+        OpCode::GOSUB => "GOSUB",
+        OpCode::ENDFUNC => "ENDFUNC",
+        OpCode::RETURN => "RETURN",
+        OpCode::HALT => "HALT",
+        OpCode::EXIT => "EXIT",
+        _ => "UNKNOWN_OP",
+    }
+}
+
+/// Reverse of quadruples::escape_obj_string
+fn unescape_obj_string(text: &str) -> String {
+    let mut result = String::new();
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c == '\\' {
+            match chars.next() {
+                Some('n') => result.push('\n'),
+                Some(other) => result.push(other),
+                None => result.push('\\'),
+            }
+        } else {
+            result.push(c);
+        }
+    }
+    result
+}
 
 #[derive(Debug, Clone, Copy)]
 struct Quad {
@@ -43,18 +197,85 @@ struct Quad {
     result: i32,
 }
 
-#[derive(Debug, Clone)]
-enum VMValue {
+#[derive(Debug, Clone, PartialEq)]
+pub enum VMValue {
     Int(i32),
     Float(f64),
     Bool(bool),  // Add dedicated boolean type
 }
 
+impl TryFrom<Value> for VMValue {
+    type Error = String;
+
+    fn try_from(value: Value) -> Result<Self, String> {
+        match value {
+            Value::Int(i) => Ok(VMValue::Int(i)),
+            Value::Float(f) => Ok(VMValue::Float(f)),
+            Value::Bool(b) => Ok(VMValue::Bool(b)),
+            Value::String(text) => Err(format!("Text {:?} can't be stored in memory", text)),
+        }
+    }
+}
+
+impl From<VMValue> for Value {
+    fn from(value: VMValue) -> Self {
+        match value {
+            VMValue::Int(i) => Value::Int(i),
+            VMValue::Float(f) => Value::Float(f),
+            VMValue::Bool(b) => Value::Bool(b),
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 struct VMFunctionInfo {
     name: String,
     param_count: usize,
     param_addresses: Vec<i32>, // Loaded from the .obj file
+    return_address: Option<i32>, // From the FUNCTION_RETURNS section, for typed functions
+    frame_cells: Vec<i32>, // From the FUNCTION_FRAMES section: the cells each call saves and restores
+}
+
+/// One memory write recorded by write logging: the cell, what it held before (None if
+/// uninitialized) and what it holds after
+#[derive(Debug, Clone, PartialEq)]
+pub struct MemoryWrite {
+    pub address: i32,
+    pub old: Option<VMValue>,
+    pub new: VMValue,
+}
+
+/// Counters gathered while running a program, useful to compare solutions
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ExecutionReport {
+    pub instructions_executed: u64,
+    pub max_call_depth: usize,
+    pub print_count: u64,
+    // Most cells holding a value at once; returns release the callee's cells, see VM::restore_frame
+    pub peak_int_cells: usize,
+    pub peak_float_cells: usize,
+    pub peak_bool_cells: usize,
+}
+
+/// A deep copy of everything that changes while a program runs, taken by VM::snapshot.
+/// Only plain data, so it can be stored or written out and restored later.
+#[derive(Debug, Clone, PartialEq)]
+pub struct VMSnapshot {
+    pub ip: usize,
+    pub int_memory: Vec<Option<i32>>,
+    pub int_cells_holding_bools: Vec<usize>, // Sorted
+    pub float_memory: Vec<Option<f64>>,
+    pub bool_memory: Vec<Option<bool>>,
+    pub call_stack: Vec<usize>,
+    pub call_functions: Vec<i32>,
+    pub saved_frames: Vec<Vec<(i32, Option<VMValue>)>>,
+    pub staged_params: Vec<Option<VMValue>>,
+    pub pending_line: String,
+    pub pending_input: VecDeque<String>,
+    pub instructions_executed: u64,
+    pub max_call_depth: usize,
+    pub print_count: u64,
+    pub peak_cells: [usize; 3],
 }
 
 pub struct VM {
@@ -63,8 +284,13 @@ pub struct VM {
     int_memory: Vec<Option<i32>>,
     float_memory: Vec<Option<f64>>,
     bool_memory: Vec<Option<bool>>,
+    // Int cells whose last write was a bool; they hold 0 or 1 and read back as a bool
+    int_cells_holding_bools: HashSet<usize>,
     call_stack: Vec<usize>, // Stores return IPs
+    call_functions: Vec<i32>, // Start index of the function each call_stack frame entered
+    saved_frames: Vec<Vec<(i32, Option<VMValue>)>>, // The callee's cells before each call_stack frame
     functions: HashMap<i32, VMFunctionInfo>, // Map start_quad_idx to info
+    string_constants: HashMap<i32, String>, // Text printed by LABEL
 
     // Track the highest address used in each segment for dynamic sizing
     max_int_addr: i32,
@@ -77,8 +303,47 @@ pub struct VM {
     max_temp_float_addr: i32,
     max_temp_bool_addr: i32,
 
-    // For function calls
-    staged_params: Vec<VMValue>,
+    // For function calls; an index no PARAM has set yet is None
+    staged_params: Vec<Option<VMValue>>,
+
+    // Printed lines are collected here instead of stdout when capturing is enabled
+    captured_output: Option<Vec<String>>,
+    // Text added by PRINT_PART, printed as one line by PRINT_LINE
+    pending_line: String,
+
+    // Receives every printed value instead of stdout or the capture buffer when set
+    print_callback: Option<Box<dyn Fn(Value)>>,
+
+    // When set, global/local cells start as typed zeros instead of uninitialized
+    zero_init: bool,
+
+    // Upper bound on the total number of memory cells across all segments
+    max_memory_cells: Option<usize>,
+
+    // Reject object code whose ERA/GOSUB targets have no FUNCTIONS entry at load time
+    verify_function_table: bool,
+
+    // Every memory write of the current run, when write logging is enabled
+    write_log: Option<Vec<MemoryWrite>>,
+
+    // Source for READ instructions and the tokens left over from the current input line
+    input: Box<dyn BufRead>,
+    pending_input: VecDeque<String>,
+    // Values queued by provide_input; when set, READ takes from here instead of `input`
+    provided_input: Option<VecDeque<Value>>,
+
+    // Execution counters for execution_report()
+    instructions_executed: u64,
+    max_call_depth: usize,
+    print_count: u64,
+    // Int, float and bool cells in use at their highest before a return released some
+    peak_cells: [usize; 3],
+}
+
+impl Default for VM {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl VM {
@@ -89,9 +354,27 @@ impl VM {
             int_memory: Vec::new(),
             float_memory: Vec::new(),
             bool_memory: Vec::new(),  // Initialize bool memory
+            int_cells_holding_bools: HashSet::new(),
             call_stack: Vec::new(),
+            call_functions: Vec::new(),
+            saved_frames: Vec::new(),
             functions: HashMap::new(),
+            string_constants: HashMap::new(),
             staged_params: Vec::new(),
+            captured_output: None,
+            pending_line: String::new(),
+            print_callback: None,
+            zero_init: false,
+            max_memory_cells: None,
+            verify_function_table: false,
+            write_log: None,
+            input: Box::new(BufReader::new(std::io::stdin())),
+            pending_input: VecDeque::new(),
+            provided_input: None,
+            instructions_executed: 0,
+            max_call_depth: 0,
+            peak_cells: [0; 3],
+            print_count: 0,
 
             // Initialize max addresses to their respective starts (no addresses used yet)
             max_int_addr: INT_START - 1,
@@ -106,40 +389,277 @@ impl VM {
         }
     }
 
-    fn update_max_address(&mut self, address: i32) {
-        match address {
-            addr if addr >= INT_START && addr < FLOAT_START => {
-                self.max_int_addr = self.max_int_addr.max(addr);
-            }
-            addr if addr >= FLOAT_START && addr < BOOL_START => {
-                self.max_float_addr = self.max_float_addr.max(addr);
-            }
-            addr if addr >= BOOL_START && addr < CTE_INT_START => {
-                self.max_bool_addr = self.max_bool_addr.max(addr);
-            }
-            addr if addr >= CTE_INT_START && addr < CTE_FLOAT_START => {
-                self.max_cte_int_addr = self.max_cte_int_addr.max(addr);
-            }
-            addr if addr >= CTE_FLOAT_START && addr < CTE_BOOL_START => {
-                self.max_cte_float_addr = self.max_cte_float_addr.max(addr);
-            }
-            addr if addr >= CTE_BOOL_START && addr < TEMP_INT_START => {
-                self.max_cte_bool_addr = self.max_cte_bool_addr.max(addr);
-            }
-            addr if addr >= TEMP_INT_START && addr < TEMP_FLOAT_START => {
-                self.max_temp_int_addr = self.max_temp_int_addr.max(addr);
-            }
-            addr if addr >= TEMP_FLOAT_START && addr < TEMP_BOOL_START => {
-                self.max_temp_float_addr = self.max_temp_float_addr.max(addr);
-            }
-            addr if addr >= TEMP_BOOL_START => {
-                self.max_temp_bool_addr = self.max_temp_bool_addr.max(addr);
+    /// Initialize global/local cells to 0, 0.0 or false when memory is allocated.
+    /// Must be set before loading the object file; by default reading a cell
+    /// before assigning it is a runtime error.
+    pub fn set_zero_init(&mut self, enabled: bool) {
+        self.zero_init = enabled;
+    }
+
+    /// Refuse to load object code that needs more than `cells` memory cells in total,
+    /// so untrusted programs with huge addresses can't exhaust the host's memory
+    pub fn set_max_memory(&mut self, cells: usize) {
+        self.max_memory_cells = Some(cells);
+    }
+
+    /// Check after loading that every ERA/GOSUB calls a function listed in the FUNCTIONS
+    /// section, so a truncated table is reported up front instead of at the first call
+    pub fn set_verify_function_table(&mut self, enabled: bool) {
+        self.verify_function_table = enabled;
+    }
+
+    /// Record every memory write as a MemoryWrite, so a run can be compared write by write
+    /// against a reference. Off by default, as it slows every instruction down.
+    pub fn set_write_logging(&mut self, enabled: bool) {
+        self.write_log = enabled.then(Vec::new);
+    }
+
+    /// The memory writes of the current run, oldest first; empty unless write logging is enabled
+    pub fn write_log(&self) -> &[MemoryWrite] {
+        self.write_log.as_deref().unwrap_or_default()
+    }
+
+    /// Read input for READ instructions from the given reader instead of stdin
+    pub fn set_stdin(&mut self, reader: Box<dyn BufRead>) {
+        self.input = reader;
+        self.pending_input.clear();
+        self.provided_input = None;
+    }
+
+    /// Feed READ instructions from these values, one per read target, instead of
+    /// parsing lines of text. Makes runs deterministic without touching stdin.
+    pub fn provide_input(&mut self, values: Vec<Value>) {
+        self.provided_input = Some(values.into());
+    }
+
+    /// Collect printed lines in memory instead of writing them to stdout
+    pub fn capture_output(&mut self) {
+        self.captured_output = Some(Vec::new());
+    }
+
+    /// Take the lines printed so far while capturing was enabled
+    pub fn take_output(&mut self) -> Vec<String> {
+        self.captured_output.as_mut().map(std::mem::take).unwrap_or_default()
+    }
+
+    /// Deliver every printed value to `callback` as a typed event instead of printing it
+    pub fn set_print_callback(&mut self, callback: impl Fn(Value) + 'static) {
+        self.print_callback = Some(Box::new(callback));
+    }
+
+    /// Emit one value of program output
+    fn emit_output(&mut self, value: Value) {
+        self.print_count += 1;
+        if let Some(callback) = self.print_callback.as_ref() {
+            callback(value);
+            return;
+        }
+        match self.captured_output.as_mut() {
+            Some(lines) => lines.push(value.to_string()),
+            None => println!("{}", value),
+        }
+    }
+
+    /// Name of the function executing now, for debuggers; "main" outside any call
+    pub fn current_function_name(&self) -> &str {
+        self.call_functions.last()
+            .and_then(|start| self.functions.get(start))
+            .map_or("main", |info| info.name.as_str())
+    }
+
+    /// Check whether every loaded instruction has been executed
+    pub fn is_at_end(&self) -> bool {
+        self.ip >= self.quads.len()
+    }
+
+    /// Abandon the remaining instructions, e.g. after a runtime error in an interactive session
+    pub fn skip_to_end(&mut self) {
+        self.ip = self.quads.len();
+        self.call_stack.clear();
+        self.call_functions.clear();
+        self.saved_frames.clear();
+        self.staged_params.clear();
+    }
+
+    /// Symbolic listing of the loaded object code: constants, function table and quadruples
+    pub fn disassemble(&self) -> String {
+        let mut listing = String::from("CONSTANTS:\n");
+        let constant_ranges = [
+            (CTE_INT_START, self.max_cte_int_addr),
+            (CTE_FLOAT_START, self.max_cte_float_addr),
+        ];
+        for address in constant_ranges.iter().flat_map(|&(start, max)| start..=max) {
+            match self.get_value(address) {
+                Ok(VMValue::Int(i)) => listing.push_str(&format!("  {} = {}\n", address, i)),
+                Ok(VMValue::Float(f)) => listing.push_str(&format!("  {} = {:?}\n", address, f)),
+                _ => {}
             }
-            _ => {} // Unknown address range
+        }
+
+        let mut strings: Vec<(&i32, &String)> = self.string_constants.iter().collect();
+        strings.sort();
+        for (address, text) in strings {
+            listing.push_str(&format!("  str{} = {:?}\n", address, text));
+        }
+
+        listing.push_str("FUNCTIONS:\n");
+        let mut functions: Vec<(&i32, &VMFunctionInfo)> = self.functions.iter().collect();
+        functions.sort_by_key(|(start, _)| **start);
+        for (start, info) in functions {
+            let params: Vec<String> = info.param_addresses.iter().map(|addr| addr.to_string()).collect();
+            let returns = info.return_address.map(|addr| format!(" -> {}", addr)).unwrap_or_default();
+            listing.push_str(&format!("  {} @ {} ({} params: [{}]){}\n", info.name, start, info.param_count, params.join(", "), returns));
+        }
+
+        listing.push_str("QUADRUPLES:\n");
+        for (idx, quad) in self.quads.iter().enumerate() {
+            listing.push_str(&format!("  {:>4}: ({}, {}, {}, {})\n", idx, opcode_name(quad.op), quad.arg1, quad.arg2, quad.result));
+        }
+        listing
+    }
+
+    /// Statistics about the last run
+    pub fn execution_report(&self) -> ExecutionReport {
+        let [peak_int_cells, peak_float_cells, peak_bool_cells] = self.peak_cells_so_far();
+        ExecutionReport {
+            instructions_executed: self.instructions_executed,
+            max_call_depth: self.max_call_depth,
+            print_count: self.print_count,
+            peak_int_cells,
+            peak_float_cells,
+            peak_bool_cells,
+        }
+    }
+
+    /// The most int, float and bool cells that have held a value at once. Cells are only
+    /// released by returns, so the count before each release and the count now cover the peak.
+    fn peak_cells_so_far(&self) -> [usize; 3] {
+        let in_use = [
+            self.int_memory.iter().filter(|cell| cell.is_some()).count(),
+            self.float_memory.iter().filter(|cell| cell.is_some()).count(),
+            self.bool_memory.iter().filter(|cell| cell.is_some()).count(),
+        ];
+        [0, 1, 2].map(|segment| self.peak_cells[segment].max(in_use[segment]))
+    }
+
+    /// Capture the execution state so it can be brought back with restore, e.g. to
+    /// step backwards in a debugger. Output already printed is not part of it.
+    pub fn snapshot(&self) -> VMSnapshot {
+        VMSnapshot {
+            ip: self.ip,
+            int_memory: self.int_memory.clone(),
+            int_cells_holding_bools: {
+                let mut cells: Vec<usize> = self.int_cells_holding_bools.iter().copied().collect();
+                cells.sort_unstable();
+                cells
+            },
+            float_memory: self.float_memory.clone(),
+            bool_memory: self.bool_memory.clone(),
+            call_stack: self.call_stack.clone(),
+            call_functions: self.call_functions.clone(),
+            saved_frames: self.saved_frames.clone(),
+            staged_params: self.staged_params.clone(),
+            pending_line: self.pending_line.clone(),
+            pending_input: self.pending_input.clone(),
+            instructions_executed: self.instructions_executed,
+            max_call_depth: self.max_call_depth,
+            print_count: self.print_count,
+            peak_cells: self.peak_cells,
+        }
+    }
+
+    /// Return to a state taken by snapshot. The same object code must be loaded, so the
+    /// memory segments have to match the snapshot's in size.
+    pub fn restore(&mut self, snapshot: &VMSnapshot) -> Result<(), String> {
+        if snapshot.int_memory.len() != self.int_memory.len()
+            || snapshot.float_memory.len() != self.float_memory.len()
+            || snapshot.bool_memory.len() != self.bool_memory.len()
+        {
+            return Err("restore: The snapshot's memory layout doesn't match the loaded program".to_string());
+        }
+        if snapshot.ip > self.quads.len() {
+            return Err(format!("restore: Snapshot IP {} is past the end of the loaded program", snapshot.ip));
+        }
+        self.ip = snapshot.ip;
+        self.int_memory = snapshot.int_memory.clone();
+        self.int_cells_holding_bools = snapshot.int_cells_holding_bools.iter().copied().collect();
+        self.float_memory = snapshot.float_memory.clone();
+        self.bool_memory = snapshot.bool_memory.clone();
+        self.call_stack = snapshot.call_stack.clone();
+        self.call_functions = snapshot.call_functions.clone();
+        self.saved_frames = snapshot.saved_frames.clone();
+        self.staged_params = snapshot.staged_params.clone();
+        self.pending_line = snapshot.pending_line.clone();
+        self.pending_input = snapshot.pending_input.clone();
+        self.instructions_executed = snapshot.instructions_executed;
+        self.max_call_depth = snapshot.max_call_depth;
+        self.print_count = snapshot.print_count;
+        self.peak_cells = snapshot.peak_cells;
+        Ok(())
+    }
+
+    /// Prepare the loaded program to run again from the start.
+    /// Constants are kept; variables, temporaries and execution counters are cleared.
+    pub fn reset(&mut self) {
+        self.ip = 0;
+        self.call_stack.clear();
+        self.call_functions.clear();
+        self.saved_frames.clear();
+        self.staged_params.clear();
+        self.pending_input.clear();
+        self.pending_line.clear();
+        if let Some(log) = self.write_log.as_mut() {
+            log.clear();
+        }
+        self.instructions_executed = 0;
+        self.max_call_depth = 0;
+        self.print_count = 0;
+        self.peak_cells = [0; 3];
+
+        let int_local_size = (self.max_int_addr - INT_START + 1) as usize;
+        let cte_int_size = (self.max_cte_int_addr - CTE_INT_START + 1) as usize;
+        let float_local_size = (self.max_float_addr - FLOAT_START + 1) as usize;
+        let cte_float_size = (self.max_cte_float_addr - CTE_FLOAT_START + 1) as usize;
+        let bool_local_size = (self.max_bool_addr - BOOL_START + 1) as usize;
+        let cte_bool_size = (self.max_cte_bool_addr - CTE_BOOL_START + 1) as usize;
+
+        let (int_zero, float_zero, bool_zero) = if self.zero_init {
+            (Some(0), Some(0.0), Some(false))
+        } else {
+            (None, None, None)
+        };
+        if let Some(locals) = self.int_memory.get_mut(..int_local_size) { locals.fill(int_zero); }
+        if let Some(temps) = self.int_memory.get_mut(int_local_size + cte_int_size..) { temps.fill(None); }
+        self.int_cells_holding_bools.clear();
+        if let Some(locals) = self.float_memory.get_mut(..float_local_size) { locals.fill(float_zero); }
+        if let Some(temps) = self.float_memory.get_mut(float_local_size + cte_float_size..) { temps.fill(None); }
+        if let Some(locals) = self.bool_memory.get_mut(..bool_local_size) { locals.fill(bool_zero); }
+        if let Some(temps) = self.bool_memory.get_mut(bool_local_size + cte_bool_size..) { temps.fill(None); }
+    }
+
+    /// Highest address seen so far in the given segment
+    fn max_address_mut(&mut self, segment: Segment) -> Option<&mut i32> {
+        match segment {
+            Segment::Int => Some(&mut self.max_int_addr),
+            Segment::Float => Some(&mut self.max_float_addr),
+            Segment::Bool => Some(&mut self.max_bool_addr),
+            Segment::ConstInt => Some(&mut self.max_cte_int_addr),
+            Segment::ConstFloat => Some(&mut self.max_cte_float_addr),
+            Segment::ConstBool => Some(&mut self.max_cte_bool_addr),
+            Segment::TempInt => Some(&mut self.max_temp_int_addr),
+            Segment::TempFloat => Some(&mut self.max_temp_float_addr),
+            Segment::TempBool => Some(&mut self.max_temp_bool_addr),
+            Segment::Unmapped => None,
+        }
+    }
+
+    fn update_max_address(&mut self, address: i32) {
+        if let Some(max_addr) = self.max_address_mut(classify_address(address)) {
+            *max_addr = (*max_addr).max(address);
         }
     }
 
-    fn resize_memory(&mut self) {
+    fn resize_memory(&mut self) -> Result<(), String> {
         // Calculate required sizes for each segment
         let int_local_size = if self.max_int_addr >= INT_START {
             (self.max_int_addr - INT_START + 1) as usize
@@ -177,6 +697,15 @@ impl VM {
             (self.max_temp_bool_addr - TEMP_BOOL_START + 1) as usize
         } else { 0 };
 
+        if let Some(max_cells) = self.max_memory_cells {
+            let requested = int_local_size + cte_int_size + temp_int_size
+                + float_local_size + cte_float_size + temp_float_size
+                + bool_local_size + cte_bool_size + temp_bool_size;
+            if requested > max_cells {
+                return Err(format!("Memory limit exceeded: program needs {} cells, limit is {}", requested, max_cells));
+            }
+        }
+
         // Resize int_memory
         let total_int_size = int_local_size + cte_int_size + temp_int_size;
         if total_int_size > 0 {
@@ -194,6 +723,14 @@ impl VM {
         if total_bool_size > 0 {
             self.bool_memory.resize(total_bool_size, None);
         }
+
+        // Global/local cells come first in each memory vector
+        if self.zero_init {
+            self.int_memory[..int_local_size].fill(Some(0));
+            self.float_memory[..float_local_size].fill(Some(0.0));
+            self.bool_memory[..bool_local_size].fill(Some(false));
+        }
+        Ok(())
     }
 
     fn get_int_idx(&self, address: i32) -> Result<usize, String> {
@@ -205,15 +742,15 @@ impl VM {
             (self.max_cte_int_addr - CTE_INT_START + 1) as usize
         } else { 0 };
 
-        match address {
-            addr if addr >= INT_START && addr <= self.max_int_addr => {
-                Ok((addr - INT_START) as usize)
+        match classify_address(address) {
+            Segment::Int if address <= self.max_int_addr => {
+                Ok((address - INT_START) as usize)
             }
-            addr if addr >= CTE_INT_START && addr <= self.max_cte_int_addr => {
-                Ok((addr - CTE_INT_START) as usize + int_local_size)
+            Segment::ConstInt if address <= self.max_cte_int_addr => {
+                Ok((address - CTE_INT_START) as usize + int_local_size)
             }
-            addr if addr >= TEMP_INT_START && addr <= self.max_temp_int_addr => {
-                Ok((addr - TEMP_INT_START) as usize + int_local_size + cte_int_size)
+            Segment::TempInt if address <= self.max_temp_int_addr => {
+                Ok((address - TEMP_INT_START) as usize + int_local_size + cte_int_size)
             }
             _ => Err(format!("Invalid or unmapped integer address: {}", address)),
         }
@@ -228,15 +765,15 @@ impl VM {
             (self.max_cte_float_addr - CTE_FLOAT_START + 1) as usize
         } else { 0 };
 
-        match address {
-            addr if addr >= FLOAT_START && addr <= self.max_float_addr => {
-                Ok((addr - FLOAT_START) as usize)
+        match classify_address(address) {
+            Segment::Float if address <= self.max_float_addr => {
+                Ok((address - FLOAT_START) as usize)
             }
-            addr if addr >= CTE_FLOAT_START && addr <= self.max_cte_float_addr => {
-                Ok((addr - CTE_FLOAT_START) as usize + float_local_size)
+            Segment::ConstFloat if address <= self.max_cte_float_addr => {
+                Ok((address - CTE_FLOAT_START) as usize + float_local_size)
             }
-            addr if addr >= TEMP_FLOAT_START && addr <= self.max_temp_float_addr => {
-                Ok((addr - TEMP_FLOAT_START) as usize + float_local_size + cte_float_size)
+            Segment::TempFloat if address <= self.max_temp_float_addr => {
+                Ok((address - TEMP_FLOAT_START) as usize + float_local_size + cte_float_size)
             }
             _ => Err(format!("Invalid or unmapped float address: {}", address)),
         }
@@ -251,15 +788,15 @@ impl VM {
             (self.max_cte_bool_addr - CTE_BOOL_START + 1) as usize
         } else { 0 };
 
-        match address {
-            addr if addr >= BOOL_START && addr <= self.max_bool_addr => {
-                Ok((addr - BOOL_START) as usize)
+        match classify_address(address) {
+            Segment::Bool if address <= self.max_bool_addr => {
+                Ok((address - BOOL_START) as usize)
             }
-            addr if addr >= CTE_BOOL_START && addr <= self.max_cte_bool_addr => {
-                Ok((addr - CTE_BOOL_START) as usize + bool_local_size)
+            Segment::ConstBool if address <= self.max_cte_bool_addr => {
+                Ok((address - CTE_BOOL_START) as usize + bool_local_size)
             }
-            addr if addr >= TEMP_BOOL_START && addr <= self.max_temp_bool_addr => {
-                Ok((addr - TEMP_BOOL_START) as usize + bool_local_size + cte_bool_size)
+            Segment::TempBool if address <= self.max_temp_bool_addr => {
+                Ok((address - TEMP_BOOL_START) as usize + bool_local_size + cte_bool_size)
             }
             _ => Err(format!("Invalid or unmapped bool address: {}", address)),
         }
@@ -272,6 +809,10 @@ impl VM {
         if let Ok(idx) = self.get_int_idx(address) {
             if idx < self.int_memory.len() {
                 if let Some(val) = self.int_memory[idx] {
+                    // The cell remembers a bool written into it, rather than reading back 0 or 1
+                    if self.int_cells_holding_bools.contains(&idx) {
+                        return Ok(VMValue::Bool(val != 0));
+                    }
                     return Ok(VMValue::Int(val));
                 } else {
                     return Err(format!("Read from uninitialized integer memory at address {}, mapped to idx {}", address, idx));
@@ -310,7 +851,76 @@ impl VM {
         Err(format!("Address {} does not map to any known memory segment for get_value", address))
     }
 
+    /// The value at an address as PRINT shows it
+    fn printed_value(&self, address: i32) -> Result<Value, String> {
+        // Bools are told apart by their cells (bool segments, or int cells tagged when a bool
+        // was written to them), never by the value or the address of an int
+        self.get_value(address).map(Value::from)
+    }
+
+    /// Read an int or float cell as a float
+    fn get_numeric_value(&self, address: i32) -> Result<f64, String> {
+        match self.get_value(address)? {
+            VMValue::Int(i) => Ok(i as f64),
+            VMValue::Float(f) => Ok(f),
+            VMValue::Bool(_) => Err(format!("Expected a numeric value at address {}, found a bool", address)),
+        }
+    }
+
+    /// The current contents of a function's cells; None for one not written yet
+    fn save_frame(&self, cells: &[i32]) -> Vec<(i32, Option<VMValue>)> {
+        cells.iter().map(|&address| (address, self.get_value(address).ok())).collect()
+    }
+
+    /// Put back cells saved by save_frame when the call that saved them returns
+    fn restore_frame(&mut self, frame: Vec<(i32, Option<VMValue>)>) -> Result<(), String> {
+        if frame.iter().any(|(_, value)| value.is_none()) {
+            self.peak_cells = self.peak_cells_so_far();
+        }
+        for (address, value) in frame {
+            match value {
+                Some(value) => self.store_value(address, value)?,
+                None => self.clear_value(address)?,
+            }
+        }
+        Ok(())
+    }
+
+    /// Return a cell to its unwritten state
+    fn clear_value(&mut self, address: i32) -> Result<(), String> {
+        if let Ok(idx) = self.get_int_idx(address) {
+            if let Some(cell) = self.int_memory.get_mut(idx) {
+                *cell = None;
+            }
+            self.int_cells_holding_bools.remove(&idx);
+        } else if let Ok(idx) = self.get_float_idx(address) {
+            if let Some(cell) = self.float_memory.get_mut(idx) {
+                *cell = None;
+            }
+        } else {
+            let idx = self.get_bool_idx(address)?;
+            if let Some(cell) = self.bool_memory.get_mut(idx) {
+                *cell = None;
+            }
+        }
+        Ok(())
+    }
+
     fn set_value(&mut self, address: i32, value: VMValue) -> Result<(), String> {
+        if self.write_log.is_none() {
+            return self.store_value(address, value);
+        }
+        let old = self.get_value(address).ok();
+        self.store_value(address, value)?;
+        // Logged as it reads back, after the cell's own conversion
+        let new = self.get_value(address)?;
+        if let Some(log) = self.write_log.as_mut() {
+            log.push(MemoryWrite { address, old, new });
+        }
+        Ok(())
+    }
+
+    fn store_value(&mut self, address: i32, value: VMValue) -> Result<(), String> {
         if address == -1 { return Err("Attempted to write to -1 address".to_string());}
         
         // First try int memory
@@ -319,9 +929,15 @@ impl VM {
                 return Err(format!("Index {} out of bounds for int_memory (size {})", idx, self.int_memory.len()));
             }
             match value {
-                VMValue::Int(i) => self.int_memory[idx] = Some(i),
+                VMValue::Int(i) => {
+                    self.int_memory[idx] = Some(i);
+                    self.int_cells_holding_bools.remove(&idx);
+                }
                 VMValue::Float(_) => return Err(format!("Type mismatch: cannot assign Float to Int address {}", address)),
-                VMValue::Bool(b) => self.int_memory[idx] = Some(if b { 1 } else { 0 }), // Convert bool to int
+                VMValue::Bool(b) => {
+                    self.int_memory[idx] = Some(if b { 1 } else { 0 }); // Stored as an int, tagged as a bool
+                    self.int_cells_holding_bools.insert(idx);
+                }
             }
             return Ok(());
         }
@@ -355,21 +971,70 @@ impl VM {
         Err(format!("Address {} does not map to any known memory segment for set_value", address))
     }
 
+    /// Convert an input token according to the segment of the address it will be stored in
+    fn parse_input_value(&self, token: &str, address: i32) -> Result<VMValue, String> {
+        if self.get_int_idx(address).is_ok() {
+            token.parse::<i32>().map(VMValue::Int).map_err(|_| format!("READ: '{}' is not a valid int", token))
+        } else if self.get_float_idx(address).is_ok() {
+            token.parse::<f64>().map(VMValue::Float).map_err(|_| format!("READ: '{}' is not a valid float", token))
+        } else if self.get_bool_idx(address).is_ok() {
+            token.parse::<bool>().map(VMValue::Bool).map_err(|_| format!("READ: '{}' is not a valid bool", token))
+        } else {
+            Err(format!("READ: Address {} does not map to any known memory segment", address))
+        }
+    }
+
+    /// Check a provided input value against the variable it is read into.
+    /// As in assignments, an int may be read into a float.
+    fn check_input_value(value: Value, address: i32) -> Result<VMValue, String> {
+        match (classify_address(address), value) {
+            (Segment::Int, Value::Int(i)) => Ok(VMValue::Int(i)),
+            (Segment::Float, Value::Float(f)) => Ok(VMValue::Float(f)),
+            (Segment::Float, Value::Int(i)) => Ok(VMValue::Float(i as f64)),
+            (Segment::Bool, Value::Bool(b)) => Ok(VMValue::Bool(b)),
+            (segment, value) => Err(format!("READ: Cannot read {:?} into {:?} address {}", value, segment, address)),
+        }
+    }
+
+    /// Collect every initialized cell together with its address
+    fn snapshot_memory(&self) -> Vec<(i32, VMValue)> {
+        let segments = [
+            (INT_START, self.max_int_addr),
+            (FLOAT_START, self.max_float_addr),
+            (BOOL_START, self.max_bool_addr),
+            (CTE_INT_START, self.max_cte_int_addr),
+            (CTE_FLOAT_START, self.max_cte_float_addr),
+            (CTE_BOOL_START, self.max_cte_bool_addr),
+            (TEMP_INT_START, self.max_temp_int_addr),
+            (TEMP_FLOAT_START, self.max_temp_float_addr),
+            (TEMP_BOOL_START, self.max_temp_bool_addr),
+        ];
+        segments.iter()
+            .flat_map(|&(start, max)| start..=max)
+            .filter_map(|address| self.get_value(address).ok().map(|value| (address, value)))
+            .collect()
+    }
+
     pub fn load_obj_file(&mut self, filepath: &str) -> Result<(), String> {
-        let file = fs::File::open(filepath).map_err(|e| e.to_string())?;
-        let reader = BufReader::new(file);
+        let content = fs::read_to_string(filepath).map_err(|e| e.to_string())?;
+        self.load_obj_str(&content)
+    }
+
+    /// Load an object file from its text content
+    pub fn load_obj_str(&mut self, content: &str) -> Result<(), String> {
         let mut current_section = "".to_string();
 
         // First pass: collect all addresses to determine memory requirements
         let mut addresses_to_track = Vec::new();
 
-        for line_result in reader.lines() {
-            let line = line_result.map_err(|e| e.to_string())?.trim().to_string();
+        for raw_line in content.lines() {
+            let line = raw_line.trim().to_string();
             if line.starts_with("//") || line.is_empty() {
                 continue;
             }
 
-            if line.ends_with(':') {
+            // Data lines always contain a comma, even string constants ending in ':'
+            if line.ends_with(':') && !line.contains(',') {
                 current_section = line.trim_end_matches(':').to_string();
                 continue;
             }
@@ -383,6 +1048,9 @@ impl VM {
                     let parts: Vec<&str> = line.split(',').collect();
                     if parts.len() == 2 {
                         let addr = parts[1].parse::<i32>().map_err(|e| format!("{}", e))?;
+                        if classify_address(addr) != Segment::ConstInt {
+                            return Err(format!("CONSTANTS_INT line '{}': address {} is outside the int constant segment", line, addr));
+                        }
                         addresses_to_track.push(addr);
                     }
                 }
@@ -390,6 +1058,9 @@ impl VM {
                     let parts: Vec<&str> = line.split(',').collect();
                     if parts.len() == 2 {
                         let addr = parts[1].parse::<i32>().map_err(|e| format!("{}", e))?;
+                        if classify_address(addr) != Segment::ConstFloat {
+                            return Err(format!("CONSTANTS_FLOAT line '{}': address {} is outside the float constant segment", line, addr));
+                        }
                         addresses_to_track.push(addr);
                     }
                 }
@@ -401,21 +1072,37 @@ impl VM {
                         for i in 0..param_count {
                             if 4 + i < parts.len() {
                                 let param_addr = parts[4 + i].parse::<i32>().map_err(|e| format!("{}", e))?;
+                                if classify_address(param_addr) == Segment::Unmapped {
+                                    return Err(format!("FUNCTIONS line '{}': parameter address {} is outside every memory segment", line, param_addr));
+                                }
                                 addresses_to_track.push(param_addr);
                             }
                         }
                     }
                 }
+                "FUNCTION_FRAMES" => {
+                    for addr in line.split(',').skip(1) {
+                        let addr = addr.parse::<i32>().map_err(|e| format!("{}", e))?;
+                        if classify_address(addr) == Segment::Unmapped {
+                            return Err(format!("FUNCTION_FRAMES line '{}': address {} is outside every memory segment", line, addr));
+                        }
+                        addresses_to_track.push(addr);
+                    }
+                }
                 "QUADRUPLES" => {
                     let parts: Vec<&str> = line.split(',').collect();
                     if parts.len() == 4 {
-                        // Collect addresses from quadruples (arg1, arg2, result)
-                        for i in 1..4 {
-                            if let Ok(addr) = parts[i].parse::<i32>() {
-                                if addr != -1 { // Skip invalid addresses
-                                    addresses_to_track.push(addr);
-                                }
+                        // Collect the addresses a quadruple reads or writes
+                        let op = parse_opcode(parts[0])?;
+                        let mut fields = [0; 3];
+                        for (field, text) in fields.iter_mut().zip(&parts[1..]) {
+                            *field = text.parse::<i32>().map_err(|e| format!("{}", e))?;
+                        }
+                        for addr in address_operands(op, fields[0], fields[1], fields[2]) {
+                            if classify_address(addr) == Segment::Unmapped {
+                                return Err(format!("QUADRUPLES line '{}': address {} is outside every memory segment", line, addr));
                             }
+                            addresses_to_track.push(addr);
                         }
                     }
                 }
@@ -423,26 +1110,38 @@ impl VM {
             }
         }
 
+        // Growing a segment shifts the cells behind it, so keep the values already in memory
+        // when more object code is loaded on top of a previous one
+        let existing_values = self.snapshot_memory();
+
         // Update max addresses based on collected addresses
         for addr in addresses_to_track {
             self.update_max_address(addr);
         }
 
         // Resize memory based on discovered addresses
-        self.resize_memory();
+        if !existing_values.is_empty() {
+            self.int_memory.clear();
+            self.int_cells_holding_bools.clear();
+            self.float_memory.clear();
+            self.bool_memory.clear();
+        }
+        self.resize_memory()?;
+        for (address, value) in existing_values {
+            self.store_value(address, value)?;
+        }
 
         // Second pass: actually load the data
-        let file = fs::File::open(filepath).map_err(|e| e.to_string())?;
-        let reader = BufReader::new(file);
         let mut current_section = "".to_string();
 
-        for line_result in reader.lines() {
-            let line = line_result.map_err(|e| e.to_string())?.trim().to_string();
+        for raw_line in content.lines() {
+            let line = raw_line.trim().to_string();
             if line.starts_with("//") || line.is_empty() {
                 continue;
             }
 
-            if line.ends_with(':') {
+            // Data lines always contain a comma, even string constants ending in ':'
+            if line.ends_with(':') && !line.contains(',') {
                 current_section = line.trim_end_matches(':').to_string();
                 continue;
             }
@@ -461,6 +1160,13 @@ impl VM {
                         self.int_memory[idx] = Some(val);
                     }
                 }
+                "CONSTANTS_STRING" => {
+                    // Use the untrimmed line so leading/trailing spaces of the text survive
+                    if let Some((addr, text)) = raw_line.trim_start().split_once(',') {
+                        let addr = addr.parse::<i32>().map_err(|e| format!("{}", e))?;
+                        self.string_constants.insert(addr, unescape_obj_string(text));
+                    }
+                }
                 "CONSTANTS_FLOAT" => {
                     let parts: Vec<&str> = line.split(',').collect();
                     if parts.len() == 2 {
@@ -494,15 +1200,36 @@ impl VM {
                             name,
                             param_count,
                             param_addresses,
+                            return_address: None,
+                            frame_cells: Vec::new(),
                         });
                     } else {
                         return Err(format!("Invalid line in FUNCTIONS section: '{}'. Expected at least 4 comma-separated values.", line));
                     }
                 }
+                "FUNCTION_RETURNS" => {
+                    // name,result cell - listed after FUNCTIONS for functions with a return type
+                    let (name, addr) = line.split_once(',')
+                        .ok_or_else(|| format!("Invalid line in FUNCTION_RETURNS section: '{}'", line))?;
+                    let addr = addr.parse::<i32>().map_err(|e| format!("{}", e))?;
+                    let info = self.functions.values_mut().find(|info| info.name == name)
+                        .ok_or_else(|| format!("FUNCTION_RETURNS: Unknown function '{}'", name))?;
+                    info.return_address = Some(addr);
+                }
+                "FUNCTION_FRAMES" => {
+                    // name,cell,cell,... - listed after FUNCTIONS for every called function
+                    let mut parts = line.split(',');
+                    let name = parts.next().unwrap_or_default();
+                    let cells = parts.map(|addr| addr.parse::<i32>().map_err(|e| format!("{}", e)))
+                        .collect::<Result<Vec<_>, _>>()?;
+                    let info = self.functions.values_mut().find(|info| info.name == name)
+                        .ok_or_else(|| format!("FUNCTION_FRAMES: Unknown function '{}'", name))?;
+                    info.frame_cells = cells;
+                }
                 "QUADRUPLES" => {
                     let parts: Vec<&str> = line.split(',').collect();
                     if parts.len() == 4 {
-                        let op = parts[0].parse().map_err(|e| format!("{}", e))?;
+                        let op = parse_opcode(parts[0])?;
                         let arg1 = parts[1].parse().map_err(|e| format!("{}", e))?;
                         let arg2 = parts[2].parse().map_err(|e| format!("{}", e))?;
                         let result = parts[3].parse().map_err(|e| format!("{}", e))?;
@@ -518,414 +1245,563 @@ impl VM {
                 _ => {} // Unknown section or content within a section
             }
         }
+
+        if self.verify_function_table {
+            let mut missing: Vec<i32> = self.quads.iter()
+                .filter(|quad| matches!(quad.op, OpCode::ERA | OpCode::GOSUB))
+                .map(|quad| quad.arg1)
+                .filter(|start| !self.functions.contains_key(start))
+                .collect();
+            missing.sort();
+            missing.dedup();
+            if !missing.is_empty() {
+                let missing: Vec<String> = missing.iter().map(|start| start.to_string()).collect();
+                return Err(format!("FUNCTIONS section has no entry for the functions called at start indices {}", missing.join(", ")));
+            }
+        }
         Ok(())
     }
 
-    pub fn run(&mut self) -> Result<(), String> {
+    /// Run the loaded program, returning its exit code
+    pub fn run(&mut self) -> Result<i32, String> {
         if self.quads.is_empty() {
             return Err("No quadruples loaded to run.".to_string());
         }
 
         while self.ip < self.quads.len() {
-            let quad = self.quads[self.ip];
-            // println!("Executing IP: {}, Quad: {:?}", self.ip, quad); // Debug print
-
-            match quad.op {
-                OpCode::ASSIGN => {
-                    // Handle special case for boolean assignment where arg2 indicates true/false
-                    if quad.arg1 == -1 && (quad.arg2 == 0 || quad.arg2 == 1) {
-                        // This is our special case for boolean literals (arg1 = -1, arg2 = 0 or 1)
-                        if let Ok(idx) = self.get_bool_idx(quad.result) {
-                            self.bool_memory[idx] = Some(quad.arg2 == 1);
-                            self.ip += 1;
-                            continue;
-                        } else if let Ok(idx) = self.get_int_idx(quad.result) {
-                            // Handle case where bool is assigned to int variable
-                            self.int_memory[idx] = Some(quad.arg2);
-                            self.ip += 1;
-                            continue;
-                        }
+            if let Some(exit_code) = self.run_step()? {
+                return Ok(exit_code);
+            }
+        }
+
+        // If we exit the loop without hitting HALT or ENDFUNC, check if this is normal termination
+        if self.call_stack.is_empty() {
+            Ok(0) // Normal program termination
+        } else {
+            Err("Program ended without proper return from function calls".to_string())
+        }
+    }
+
+    /// Call a loaded function directly with the given arguments and run it until it
+    /// returns. Gives back the function's result, or None for a void function.
+    pub fn call_function(&mut self, name: &str, args: &[Value]) -> Result<Option<Value>, String> {
+        let (start, info) = self.functions.iter()
+            .find(|(_, info)| info.name == name)
+            .map(|(start, info)| (*start, info.clone()))
+            .ok_or_else(|| format!("call_function: Function '{}' not found", name))?;
+        if args.len() != info.param_count {
+            return Err(format!("call_function: Function '{}' expects {} arguments, got {}", name, info.param_count, args.len()));
+        }
+        if start < 0 || start as usize >= self.quads.len() {
+            return Err(format!("call_function: Invalid start index {} for function '{}'", start, name));
+        }
+
+        let frame = self.save_frame(&info.frame_cells);
+        for (addr, value) in info.param_addresses.iter().zip(args) {
+            let value = VMValue::try_from(value.clone()).map_err(|e| format!("call_function: {}", e))?;
+            self.set_value(*addr, value)?;
+        }
+
+        // Return past the last quad, so the function's ENDFUNC or RETURN hands control back here
+        let saved_ip = self.ip;
+        let base_depth = self.call_stack.len();
+        self.call_stack.push(self.quads.len());
+        self.call_functions.push(start);
+        self.saved_frames.push(frame);
+        self.ip = start as usize;
+        let outcome = loop {
+            match self.run_step() {
+                Ok(None) if self.call_stack.len() == base_depth => break Ok(()),
+                Ok(None) => {}
+                Ok(Some(code)) => break Err(format!("call_function: Program exited with code {} inside '{}'", code, name)),
+                Err(e) => break Err(e),
+            }
+        };
+        self.call_stack.truncate(base_depth);
+        self.call_functions.truncate(base_depth);
+        self.saved_frames.truncate(base_depth);
+        self.ip = saved_ip;
+        outcome?;
+
+        info.return_address.map(|addr| self.get_value(addr).map(Value::from)).transpose()
+    }
+
+    /// Execute the instruction at the current IP.
+    /// Returns the exit code once the program terminates, or None if execution can continue.
+    pub fn run_step(&mut self) -> Result<Option<i32>, String> {
+        if self.ip >= self.quads.len() {
+            return Err(format!("No instruction at IP: {}", self.ip));
+        }
+        let quad = self.quads[self.ip];
+        self.instructions_executed += 1;
+        // println!("Executing IP: {}, Quad: {:?}", self.ip, quad); // Debug print
+
+        match quad.op {
+            OpCode::NOP => {
+                self.ip += 1;
+            }
+            OpCode::ASSIGN => {
+                // Handle special case for boolean assignment where arg2 indicates true/false
+                if quad.arg1 == -1 && (quad.arg2 == 0 || quad.arg2 == 1) {
+                    // This is our special case for boolean literals (arg1 = -1, arg2 = 0 or 1)
+                    // An int target stores 0 or 1, tagged as a bool
+                    if self.get_bool_idx(quad.result).is_ok() || self.get_int_idx(quad.result).is_ok() {
+                        self.set_value(quad.result, VMValue::Bool(quad.arg2 == 1))?;
+                        self.ip += 1;
+                        return Ok(None);
                     }
-                    
-                    // Normal assignment for all other types
-                    let val = self.get_value(quad.arg1)?;
-                    self.set_value(quad.result, val)?;
-                    self.ip += 1;
                 }
-                OpCode::ADD | OpCode::SUB | OpCode::MULT | OpCode::DIV => {
-                    let v1 = self.get_value(quad.arg1)?;
-                    let v2 = self.get_value(quad.arg2)?;
-                    let result_val = match (v1, v2) {
-                        (VMValue::Int(i1), VMValue::Int(i2)) => match quad.op {
-                            OpCode::ADD => VMValue::Int(i1 + i2),
-                            OpCode::SUB => VMValue::Int(i1 - i2),
-                            OpCode::MULT => VMValue::Int(i1 * i2),
-                            OpCode::DIV => if i2 == 0 { return Err(format!("Division by zero: {} / {}", i1, i2))} else {VMValue::Int(i1 / i2)},
-                            _ => unreachable!(),
-                        },
-                        (VMValue::Float(f1), VMValue::Float(f2)) => match quad.op {
+                
+                // Normal assignment for all other types
+                let val = self.get_value(quad.arg1)?;
+                self.set_value(quad.result, val)?;
+                self.ip += 1;
+            }
+            OpCode::ADD | OpCode::SUB | OpCode::MULT | OpCode::DIV => {
+                let v1 = self.get_value(quad.arg1)?;
+                let v2 = self.get_value(quad.arg2)?;
+                let result_val = match (v1, v2) {
+                    (VMValue::Int(i1), VMValue::Int(i2)) => match quad.op {
+                        OpCode::ADD => VMValue::Int(i1 + i2),
+                        OpCode::SUB => VMValue::Int(i1 - i2),
+                        OpCode::MULT => VMValue::Int(i1 * i2),
+                        OpCode::DIV => if i2 == 0 { return Err(format!("Division by zero: {} / {}", i1, i2))} else {VMValue::Int(i1 / i2)},
+                        _ => unreachable!(),
+                    },
+                    (VMValue::Float(f1), VMValue::Float(f2)) => match quad.op {
+                        OpCode::ADD => VMValue::Float(f1 + f2),
+                        OpCode::SUB => VMValue::Float(f1 - f2),
+                        OpCode::MULT => VMValue::Float(f1 * f2),
+                        OpCode::DIV => if f2 == 0.0 { return Err(format!("Division by zero: {} / {}", f1, f2))} else {VMValue::Float(f1 / f2)},
+                        _ => unreachable!(),
+                    },
+                    (VMValue::Int(i1), VMValue::Float(f2)) => {
+                        let f1 = i1 as f64;
+                        match quad.op {
                             OpCode::ADD => VMValue::Float(f1 + f2),
                             OpCode::SUB => VMValue::Float(f1 - f2),
                             OpCode::MULT => VMValue::Float(f1 * f2),
                             OpCode::DIV => if f2 == 0.0 { return Err(format!("Division by zero: {} / {}", f1, f2))} else {VMValue::Float(f1 / f2)},
                             _ => unreachable!(),
-                        },
-                        (VMValue::Int(i1), VMValue::Float(f2)) => {
-                            let f1 = i1 as f64;
-                            match quad.op {
-                                OpCode::ADD => VMValue::Float(f1 + f2),
-                                OpCode::SUB => VMValue::Float(f1 - f2),
-                                OpCode::MULT => VMValue::Float(f1 * f2),
-                                OpCode::DIV => if f2 == 0.0 { return Err(format!("Division by zero: {} / {}", f1, f2))} else {VMValue::Float(f1 / f2)},
-                                _ => unreachable!(),
-                            }
-                        },
-                        (VMValue::Float(f1), VMValue::Int(i2)) => {
-                            let f2 = i2 as f64;
-                            match quad.op {
-                                OpCode::ADD => VMValue::Float(f1 + f2),
-                                OpCode::SUB => VMValue::Float(f1 - f2),
-                                OpCode::MULT => VMValue::Float(f1 * f2),
-                                OpCode::DIV => if f2 == 0.0 { return Err(format!("Division by zero: {} / {}", f1, f2))} else {VMValue::Float(f1 / f2)},
-                                _ => unreachable!(),
-                            }
-                        },
-                        (VMValue::Bool(b1), VMValue::Bool(b2)) => match quad.op {
-                            OpCode::ADD => VMValue::Int((b1 as i32) + (b2 as i32)),
-                            OpCode::SUB => VMValue::Int((b1 as i32) - (b2 as i32)),
-                            OpCode::MULT => VMValue::Int((b1 as i32) * (b2 as i32)),
-                            OpCode::DIV => {
-                                if !b2 { return Err("Division by zero (false)".to_string()); }
-                                VMValue::Int((b1 as i32) / (b2 as i32))
-                            },
+                        }
+                    },
+                    (VMValue::Float(f1), VMValue::Int(i2)) => {
+                        let f2 = i2 as f64;
+                        match quad.op {
+                            OpCode::ADD => VMValue::Float(f1 + f2),
+                            OpCode::SUB => VMValue::Float(f1 - f2),
+                            OpCode::MULT => VMValue::Float(f1 * f2),
+                            OpCode::DIV => if f2 == 0.0 { return Err(format!("Division by zero: {} / {}", f1, f2))} else {VMValue::Float(f1 / f2)},
                             _ => unreachable!(),
+                        }
+                    },
+                    // Bools are not numbers; the generator rejects this, so only a malformed object file gets here
+                    (v1, v2) => {
+                        return Err(format!("Type mismatch: arithmetic op {} on {:?} and {:?} at IP: {}", quad.op, v1, v2, self.ip));
+                    }
+                };
+                self.set_value(quad.result, result_val)?;
+                self.ip += 1;
+            }
+            OpCode::FMOD => {
+                let dividend = self.get_numeric_value(quad.arg1)?;
+                let divisor = self.get_numeric_value(quad.arg2)?;
+                if divisor == 0.0 {
+                    return Err(format!("Division by zero: fmod({}, {})", dividend, divisor));
+                }
+                self.set_value(quad.result, VMValue::Float(dividend % divisor))?;
+                self.ip += 1;
+            }
+            OpCode::BAND | OpCode::BOR | OpCode::BXOR => {
+                let (VMValue::Int(i1), VMValue::Int(i2)) = (self.get_value(quad.arg1)?, self.get_value(quad.arg2)?) else {
+                    return Err(format!("Type mismatch: bitwise op {} needs two ints at IP: {}", opcode_name(quad.op), self.ip));
+                };
+                let result = match quad.op {
+                    OpCode::BAND => i1 & i2,
+                    OpCode::BOR => i1 | i2,
+                    _ => i1 ^ i2,
+                };
+                self.set_value(quad.result, VMValue::Int(result))?;
+                self.ip += 1;
+            }
+            OpCode::SHL | OpCode::SHR => {
+                let (VMValue::Int(value), VMValue::Int(amount)) = (self.get_value(quad.arg1)?, self.get_value(quad.arg2)?) else {
+                    return Err(format!("Type mismatch: shift op {} needs two ints at IP: {}", opcode_name(quad.op), self.ip));
+                };
+                // Shifting an i32 by a negative amount or by 32 or more has no defined result
+                let shifted = u32::try_from(amount).ok().and_then(|amount| match quad.op {
+                    OpCode::SHL => value.checked_shl(amount),
+                    _ => value.checked_shr(amount),
+                });
+                let Some(result) = shifted else {
+                    return Err(format!("Invalid shift amount {} for a 32-bit int at IP: {}", amount, self.ip));
+                };
+                self.set_value(quad.result, VMValue::Int(result))?;
+                self.ip += 1;
+            }
+            OpCode::GT | OpCode::LT | OpCode::EQ | OpCode::NEQ => {
+                // Handle special case for direct boolean value in comparison
+                if quad.arg1 == -2 {
+                    // Direct boolean literal in left operand, value in result field
+                    let left_bool_value = quad.result == 1; // true if 1, false if 0
+                    let v2 = self.get_value(quad.arg2)?;
+                    
+                    let bool_result = match v2 {
+                        VMValue::Bool(b2) => match quad.op {
+                            OpCode::EQ => left_bool_value == b2,
+                            OpCode::NEQ => left_bool_value != b2,
+                            _ => return Err(format!("Invalid comparison operator {} for boolean values", quad.op)),
                         },
-                        (VMValue::Bool(b1), VMValue::Int(i2)) => {
-                            let i1 = b1 as i32;
-                            match quad.op {
-                                OpCode::ADD => VMValue::Int(i1 + i2),
-                                OpCode::SUB => VMValue::Int(i1 - i2),
-                                OpCode::MULT => VMValue::Int(i1 * i2),
-                                OpCode::DIV => if i2 == 0 { return Err(format!("Division by zero: {} / {}", i1, i2))} else {VMValue::Int(i1 / i2)},
-                                _ => unreachable!(),
-                            }
-                        },
-                        (VMValue::Int(i1), VMValue::Bool(b2)) => {
-                            let i2 = b2 as i32;
-                            match quad.op {
-                                OpCode::ADD => VMValue::Int(i1 + i2),
-                                OpCode::SUB => VMValue::Int(i1 - i2),
-                                OpCode::MULT => VMValue::Int(i1 * i2),
-                                OpCode::DIV => if !b2 { return Err(format!("Division by zero: {} / false", i1))} else {VMValue::Int(i1 / i2)},
-                                _ => unreachable!(),
-                            }
-                        },
-                        (VMValue::Bool(b1), VMValue::Float(f2)) => {
-                            let f1 = (b1 as i32) as f64;
-                            match quad.op {
-                                OpCode::ADD => VMValue::Float(f1 + f2),
-                                OpCode::SUB => VMValue::Float(f1 - f2),
-                                OpCode::MULT => VMValue::Float(f1 * f2),
-                                OpCode::DIV => if f2 == 0.0 { return Err(format!("Division by zero: {} / {}", f1, f2))} else {VMValue::Float(f1 / f2)},
-                                _ => unreachable!(),
-                            }
+                        VMValue::Int(i2) => match quad.op {
+                            OpCode::EQ => (left_bool_value as i32) == i2,
+                            OpCode::NEQ => (left_bool_value as i32) != i2,
+                            _ => return Err(format!("Invalid comparison operator {} between Bool and Int", quad.op)),
                         },
-                        (VMValue::Float(f1), VMValue::Bool(b2)) => {
-                            let f2 = (b2 as i32) as f64;
-                            match quad.op {
-                                OpCode::ADD => VMValue::Float(f1 + f2),
-                                OpCode::SUB => VMValue::Float(f1 - f2),
-                                OpCode::MULT => VMValue::Float(f1 * f2),
-                                OpCode::DIV => if !b2 { return Err(format!("Division by zero: {} / false", f1))} else {VMValue::Float(f1 / f2)},
-                                _ => unreachable!(),
-                            }
+                        VMValue::Float(f2) => match quad.op {
+                            OpCode::EQ => (left_bool_value as i32) as f64 == f2,
+                            OpCode::NEQ => (left_bool_value as i32) as f64 != f2,
+                            _ => return Err(format!("Invalid comparison operator {} between Bool and Float", quad.op)),
                         }
                     };
-                    self.set_value(quad.result, result_val)?;
-                    self.ip += 1;
-                }
-                OpCode::GT | OpCode::LT | OpCode::EQ | OpCode::NEQ => {
-                    // Handle special case for direct boolean value in comparison
-                    if quad.arg1 == -2 {
-                        // Direct boolean literal in left operand, value in result field
-                        let left_bool_value = quad.result == 1; // true if 1, false if 0
-                        let v2 = self.get_value(quad.arg2)?;
-                        
-                        let bool_result = match v2 {
-                            VMValue::Bool(b2) => match quad.op {
-                                OpCode::EQ => left_bool_value == b2,
-                                OpCode::NEQ => left_bool_value != b2,
-                                _ => return Err(format!("Invalid comparison operator {} for boolean values", quad.op)),
-                            },
-                            VMValue::Int(i2) => match quad.op {
-                                OpCode::EQ => (left_bool_value as i32) == i2,
-                                OpCode::NEQ => (left_bool_value as i32) != i2,
-                                _ => return Err(format!("Invalid comparison operator {} between Bool and Int", quad.op)),
-                            },
-                            VMValue::Float(f2) => match quad.op {
-                                OpCode::EQ => (left_bool_value as i32) as f64 == f2,
-                                OpCode::NEQ => (left_bool_value as i32) as f64 != f2,
-                                _ => return Err(format!("Invalid comparison operator {} between Bool and Float", quad.op)),
-                            }
-                        };
-                        
-                        // Find the next instruction - need to figure out the result address since we're using result field for the boolean value
-                        // The result address is typically stored in pila_o before this operation, so we need to extract it from a different place
-                        let next_quad = if self.ip + 1 < self.quads.len() { Some(&self.quads[self.ip + 1]) } else { None };
-                        if let Some(next_q) = next_quad {
-                            if next_q.op == OpCode::GOTOF && next_q.arg1 >= TEMP_BOOL_START {
-                                // Likely a conditional jump that uses our comparison result
-                                self.set_value(next_q.arg1, VMValue::Bool(bool_result))?;
-                            } else {
-                                // Create a temporary address and store the result
-                                let temp_addr = TEMP_BOOL_START + self.bool_memory.len() as i32;
-                                let idx = self.get_bool_idx(temp_addr)?;
-                                if idx >= self.bool_memory.len() {
-                                    self.bool_memory.resize(idx + 1, None);
-                                }
-                                self.bool_memory[idx] = Some(bool_result);
-                            }
-                        }
-                        self.ip += 1;
-                        continue;
-                    }
-                    else if quad.arg2 == -2 {
-                        // Direct boolean literal in right operand, value in result field
-                        let v1 = self.get_value(quad.arg1)?;
-                        let right_bool_value = quad.result == 1; // true if 1, false if 0
-                        
-                        let bool_result = match v1 {
-                            VMValue::Bool(b1) => match quad.op {
-                                OpCode::EQ => b1 == right_bool_value,
-                                OpCode::NEQ => b1 != right_bool_value,
-                                _ => return Err(format!("Invalid comparison operator {} for boolean values", quad.op)),
-                            },
-                            VMValue::Int(i1) => match quad.op {
-                                OpCode::EQ => i1 == (right_bool_value as i32),
-                                OpCode::NEQ => i1 != (right_bool_value as i32),
-                                _ => return Err(format!("Invalid comparison operator {} between Int and Bool", quad.op)),
-                            },
-                            VMValue::Float(f1) => match quad.op {
-                                OpCode::EQ => f1 == (right_bool_value as i32) as f64,
-                                OpCode::NEQ => f1 != (right_bool_value as i32) as f64,
-                                _ => return Err(format!("Invalid comparison operator {} between Float and Bool", quad.op)),
-                            }
-                        };
-                        
-                        // Find the next instruction (same approach as above)
-                        let next_quad = if self.ip + 1 < self.quads.len() { Some(&self.quads[self.ip + 1]) } else { None };
-                        if let Some(next_q) = next_quad {
-                            if next_q.op == OpCode::GOTOF && next_q.arg1 >= TEMP_BOOL_START {
-                                // Likely a conditional jump that uses our comparison result
-                                self.set_value(next_q.arg1, VMValue::Bool(bool_result))?;
-                            } else {
-                                // Create a temporary address and store the result
-                                let temp_addr = TEMP_BOOL_START + self.bool_memory.len() as i32;
-                                let idx = self.get_bool_idx(temp_addr)?;
-                                if idx >= self.bool_memory.len() {
-                                    self.bool_memory.resize(idx + 1, None);
-                                }
-                                self.bool_memory[idx] = Some(bool_result);
+                    
+                    // Find the next instruction - need to figure out the result address since we're using result field for the boolean value
+                    // The result address is typically stored in pila_o before this operation, so we need to extract it from a different place
+                    let next_quad = if self.ip + 1 < self.quads.len() { Some(&self.quads[self.ip + 1]) } else { None };
+                    if let Some(next_q) = next_quad {
+                        if next_q.op == OpCode::GOTOF && next_q.arg1 >= TEMP_BOOL_START {
+                            // Likely a conditional jump that uses our comparison result
+                            self.set_value(next_q.arg1, VMValue::Bool(bool_result))?;
+                        } else {
+                            // Create a temporary address and store the result
+                            let temp_addr = TEMP_BOOL_START + self.bool_memory.len() as i32;
+                            let idx = self.get_bool_idx(temp_addr)?;
+                            if idx >= self.bool_memory.len() {
+                                self.bool_memory.resize(idx + 1, None);
                             }
+                            self.bool_memory[idx] = Some(bool_result);
                         }
-                        self.ip += 1;
-                        continue;
                     }
-
-                    // Regular comparison handling
+                    self.ip += 1;
+                    return Ok(None);
+                }
+                else if quad.arg2 == -2 {
+                    // Direct boolean literal in right operand, value in result field
                     let v1 = self.get_value(quad.arg1)?;
-                    let v2 = self.get_value(quad.arg2)?;
-                    let bool_result = match (v1, v2) {
-                        (VMValue::Int(i1), VMValue::Int(i2)) => match quad.op {
-                            OpCode::GT => i1 > i2, 
-                            OpCode::LT => i1 < i2, 
-                            OpCode::EQ => i1 == i2, 
-                            OpCode::NEQ => i1 != i2, 
-                            _ => unreachable!(),
-                        },
-                        (VMValue::Float(f1), VMValue::Float(f2)) => match quad.op {
-                            OpCode::GT => f1 > f2, 
-                            OpCode::LT => f1 < f2, 
-                            OpCode::EQ => f1 == f2, 
-                            OpCode::NEQ => f1 != f2, 
-                            _ => unreachable!(),
-                        },
-                        (VMValue::Int(i1), VMValue::Float(f2)) => {
-                            let f1 = i1 as f64; 
-                            match quad.op {
-                                OpCode::GT => f1 > f2, 
-                                OpCode::LT => f1 < f2, 
-                                OpCode::EQ => f1 == f2, 
-                                OpCode::NEQ => f1 != f2, 
-                                _ => unreachable!(),
-                            }
-                        },
-                        (VMValue::Float(f1), VMValue::Int(i2)) => {
-                            let f2 = i2 as f64; 
-                            match quad.op {
-                                OpCode::GT => f1 > f2, 
-                                OpCode::LT => f1 < f2, 
-                                OpCode::EQ => f1 == f2, 
-                                OpCode::NEQ => f1 != f2, 
-                                _ => unreachable!(),
-                            }
-                        },
-                        (VMValue::Bool(b1), VMValue::Bool(b2)) => match quad.op {
-                            OpCode::EQ => b1 == b2, 
-                            OpCode::NEQ => b1 != b2,
-                            OpCode::GT | OpCode::LT => return Err(format!("Invalid comparison operator {} for boolean values", quad.op)),
-                            _ => unreachable!(),
-                        },
-                        (VMValue::Bool(b1), VMValue::Int(i2)) => match quad.op {
-                            OpCode::EQ => (b1 as i32) == i2, 
-                            OpCode::NEQ => (b1 as i32) != i2,
-                            _ => return Err(format!("Invalid comparison operator {} between Bool and Int", quad.op)),
+                    let right_bool_value = quad.result == 1; // true if 1, false if 0
+                    
+                    let bool_result = match v1 {
+                        VMValue::Bool(b1) => match quad.op {
+                            OpCode::EQ => b1 == right_bool_value,
+                            OpCode::NEQ => b1 != right_bool_value,
+                            _ => return Err(format!("Invalid comparison operator {} for boolean values", quad.op)),
                         },
-                        (VMValue::Int(i1), VMValue::Bool(b2)) => match quad.op {
-                            OpCode::EQ => i1 == (b2 as i32), 
-                            OpCode::NEQ => i1 != (b2 as i32),
+                        VMValue::Int(i1) => match quad.op {
+                            OpCode::EQ => i1 == (right_bool_value as i32),
+                            OpCode::NEQ => i1 != (right_bool_value as i32),
                             _ => return Err(format!("Invalid comparison operator {} between Int and Bool", quad.op)),
                         },
-                        (VMValue::Bool(b1), VMValue::Float(f2)) => match quad.op {
-                            OpCode::EQ => (b1 as i32) as f64 == f2,
-                            OpCode::NEQ => (b1 as i32) as f64 != f2,
-                            _ => return Err(format!("Invalid comparison operator {} between Bool and Float", quad.op)),
-                        },
-                        (VMValue::Float(f1), VMValue::Bool(b2)) => match quad.op {
-                            OpCode::EQ => f1 == (b2 as i32) as f64,
-                            OpCode::NEQ => f1 != (b2 as i32) as f64,
+                        VMValue::Float(f1) => match quad.op {
+                            OpCode::EQ => f1 == (right_bool_value as i32) as f64,
+                            OpCode::NEQ => f1 != (right_bool_value as i32) as f64,
                             _ => return Err(format!("Invalid comparison operator {} between Float and Bool", quad.op)),
                         }
                     };
-                    self.set_value(quad.result, VMValue::Bool(bool_result))?;
-                    self.ip += 1;
-                }
-                OpCode::PRINT => {
-                    let val = self.get_value(quad.arg1)?;
-                    match val {
-                        VMValue::Int(i) => {
-                            // Heuristic: If the value came from a TEMP_BOOL address, print true/false
-                            if quad.arg1 >= TEMP_BOOL_START && quad.arg1 <= self.max_temp_bool_addr {
-                                println!("{}", if i == 0 { "false" } else { "true" });
-                            } else {
-                                println!("{}", i);
+                    
+                    // Find the next instruction (same approach as above)
+                    let next_quad = if self.ip + 1 < self.quads.len() { Some(&self.quads[self.ip + 1]) } else { None };
+                    if let Some(next_q) = next_quad {
+                        if next_q.op == OpCode::GOTOF && next_q.arg1 >= TEMP_BOOL_START {
+                            // Likely a conditional jump that uses our comparison result
+                            self.set_value(next_q.arg1, VMValue::Bool(bool_result))?;
+                        } else {
+                            // Create a temporary address and store the result
+                            let temp_addr = TEMP_BOOL_START + self.bool_memory.len() as i32;
+                            let idx = self.get_bool_idx(temp_addr)?;
+                            if idx >= self.bool_memory.len() {
+                                self.bool_memory.resize(idx + 1, None);
                             }
+                            self.bool_memory[idx] = Some(bool_result);
                         }
-                        VMValue::Float(f) => println!("{}", f),
-                        VMValue::Bool(b) => println!("{}", b),
                     }
                     self.ip += 1;
+                    return Ok(None);
                 }
-                OpCode::GOTO => {
-                    // quad.result contains the target IP
-                    if quad.result < 0 || quad.result as usize >= self.quads.len() {
-                        return Err(format!("GOTO: Invalid jump target {}", quad.result));
-                    }
-                    self.ip = quad.result as usize;
-                }
-                OpCode::GOTOF => {
-                    let cond_val = self.get_value(quad.arg1)?;
-                    let is_false = match cond_val {
-                        VMValue::Bool(b) => !b,
-                        VMValue::Int(i) => i == 0,
-                        VMValue::Float(_) => return Err("GOTOF condition cannot be a float".to_string()),
-                    };
-                    
-                    if is_false {
-                        // Condition is false, jump to target
-                        if quad.result < 0 || quad.result as usize >= self.quads.len() {
-                            return Err(format!("GOTOF: Invalid jump target {}", quad.result));
+
+                // Regular comparison handling
+                let v1 = self.get_value(quad.arg1)?;
+                let v2 = self.get_value(quad.arg2)?;
+                let bool_result = match (v1, v2) {
+                    (VMValue::Int(i1), VMValue::Int(i2)) => match quad.op {
+                        OpCode::GT => i1 > i2, 
+                        OpCode::LT => i1 < i2, 
+                        OpCode::EQ => i1 == i2, 
+                        OpCode::NEQ => i1 != i2, 
+                        _ => unreachable!(),
+                    },
+                    (VMValue::Float(f1), VMValue::Float(f2)) => match quad.op {
+                        OpCode::GT => f1 > f2, 
+                        OpCode::LT => f1 < f2, 
+                        OpCode::EQ => f1 == f2, 
+                        OpCode::NEQ => f1 != f2, 
+                        _ => unreachable!(),
+                    },
+                    (VMValue::Int(i1), VMValue::Float(f2)) => {
+                        let f1 = i1 as f64; 
+                        match quad.op {
+                            OpCode::GT => f1 > f2, 
+                            OpCode::LT => f1 < f2, 
+                            OpCode::EQ => f1 == f2, 
+                            OpCode::NEQ => f1 != f2, 
+                            _ => unreachable!(),
                         }
-                        self.ip = quad.result as usize;
-                    } else {
-                        // Condition is true, continue to next instruction
-                        self.ip += 1;
+                    },
+                    (VMValue::Float(f1), VMValue::Int(i2)) => {
+                        let f2 = i2 as f64; 
+                        match quad.op {
+                            OpCode::GT => f1 > f2, 
+                            OpCode::LT => f1 < f2, 
+                            OpCode::EQ => f1 == f2, 
+                            OpCode::NEQ => f1 != f2, 
+                            _ => unreachable!(),
+                        }
+                    },
+                    (VMValue::Bool(b1), VMValue::Bool(b2)) => match quad.op {
+                        OpCode::EQ => b1 == b2, 
+                        OpCode::NEQ => b1 != b2,
+                        OpCode::GT | OpCode::LT => return Err(format!("Invalid comparison operator {} for boolean values", quad.op)),
+                        _ => unreachable!(),
+                    },
+                    (VMValue::Bool(b1), VMValue::Int(i2)) => match quad.op {
+                        OpCode::EQ => (b1 as i32) == i2, 
+                        OpCode::NEQ => (b1 as i32) != i2,
+                        _ => return Err(format!("Invalid comparison operator {} between Bool and Int", quad.op)),
+                    },
+                    (VMValue::Int(i1), VMValue::Bool(b2)) => match quad.op {
+                        OpCode::EQ => i1 == (b2 as i32), 
+                        OpCode::NEQ => i1 != (b2 as i32),
+                        _ => return Err(format!("Invalid comparison operator {} between Int and Bool", quad.op)),
+                    },
+                    (VMValue::Bool(b1), VMValue::Float(f2)) => match quad.op {
+                        OpCode::EQ => (b1 as i32) as f64 == f2,
+                        OpCode::NEQ => (b1 as i32) as f64 != f2,
+                        _ => return Err(format!("Invalid comparison operator {} between Bool and Float", quad.op)),
+                    },
+                    (VMValue::Float(f1), VMValue::Bool(b2)) => match quad.op {
+                        OpCode::EQ => f1 == (b2 as i32) as f64,
+                        OpCode::NEQ => f1 != (b2 as i32) as f64,
+                        _ => return Err(format!("Invalid comparison operator {} between Float and Bool", quad.op)),
                     }
+                };
+                self.set_value(quad.result, VMValue::Bool(bool_result))?;
+                self.ip += 1;
+            }
+            OpCode::PRINT if quad.arg2 == PRINT_BASE_HEX || quad.arg2 == PRINT_BASE_BIN => {
+                let val = match self.get_value(quad.arg1)? {
+                    VMValue::Int(i) => i,
+                    other => return Err(format!("PRINT: Only ints can be printed in base {}, got {:?}", quad.arg2, other)),
+                };
+                if quad.arg2 == PRINT_BASE_HEX {
+                    self.emit_output(Value::String(format!("{:#x}", val)));
+                } else {
+                    self.emit_output(Value::String(format!("{:#b}", val)));
                 }
-                OpCode::ERA => {
-                    // quad.arg1 is the start_quad_idx of the function being called
-                    if let Some(func_info) = self.functions.get(&quad.arg1) {
-                        self.staged_params = Vec::with_capacity(func_info.param_count);
-                    } else {
-                        return Err(format!("ERA: Function with start_idx {} not found.", quad.arg1));
+                self.ip += 1;
+            }
+            OpCode::PRINT => {
+                let printed = self.printed_value(quad.arg1)?;
+                // A string constant in the result field is printed right after the value (printd)
+                if quad.result >= 0 {
+                    let suffix = self.string_constants.get(&quad.result)
+                        .ok_or_else(|| format!("PRINT: Unknown string constant {}", quad.result))?;
+                    let line = format!("{}{}", printed, suffix);
+                    self.emit_output(Value::String(line));
+                } else {
+                    self.emit_output(printed);
+                }
+                self.ip += 1;
+            }
+            OpCode::READ if self.provided_input.is_some() => {
+                let value = self.provided_input.as_mut().and_then(|values| values.pop_front())
+                    .ok_or_else(|| format!("READ: No provided input value left for target address {}", quad.result))?;
+                let value = Self::check_input_value(value, quad.result)?;
+                self.set_value(quad.result, value)?;
+                self.ip += 1;
+            }
+            OpCode::READ => {
+                // The first target of a read statement consumes a whole input line
+                if quad.arg1 == 0 {
+                    let mut line = String::new();
+                    let bytes = self.input.read_line(&mut line).map_err(|e| format!("READ: {}", e))?;
+                    if bytes == 0 {
+                        return Err("READ: Unexpected end of input".to_string());
                     }
-                    self.ip += 1;
+                    self.pending_input = line.split_whitespace().map(|token| token.to_string()).collect();
+                    if self.pending_input.len() != quad.arg2 as usize {
+                        return Err(format!("READ: Expected {} value(s) on the input line, got {}", quad.arg2, self.pending_input.len()));
+                    }
+                }
+                let token = self.pending_input.pop_front()
+                    .ok_or_else(|| format!("READ: No input value left for target address {}", quad.result))?;
+                let value = self.parse_input_value(&token, quad.result)?;
+                self.set_value(quad.result, value)?;
+                self.ip += 1;
+            }
+            OpCode::PRINT_PART => {
+                if quad.arg1 >= 0 {
+                    let printed = self.printed_value(quad.arg1)?;
+                    self.pending_line.push_str(&printed.to_string());
+                }
+                if quad.result >= 0 {
+                    let text = self.string_constants.get(&quad.result)
+                        .ok_or_else(|| format!("PRINT_PART: Unknown string constant {}", quad.result))?;
+                    self.pending_line.push_str(text);
+                }
+                self.ip += 1;
+            }
+            OpCode::PRINT_LINE => {
+                let line = std::mem::take(&mut self.pending_line);
+                self.emit_output(Value::String(line));
+                self.ip += 1;
+            }
+            OpCode::LABEL => {
+                let text = self.string_constants.get(&quad.arg1)
+                    .cloned()
+                    .ok_or_else(|| format!("LABEL: Unknown string constant {}", quad.arg1))?;
+                self.emit_output(Value::String(text));
+                self.ip += 1;
+            }
+            OpCode::GOTO => {
+                // quad.result contains the target IP; jumping just past the last quad ends the program
+                if quad.result < 0 || quad.result as usize > self.quads.len() {
+                    return Err(format!("GOTO: Invalid jump target {}", quad.result));
                 }
-                OpCode::PARAM => {
-                    let arg_val = self.get_value(quad.arg1)?; // Value to be passed
-                    let param_k_idx = quad.result as usize;    // k-th parameter (0-indexed)
+                self.ip = quad.result as usize;
+            }
+            OpCode::GOTOF | OpCode::GOTOT => {
+                let op_name = opcode_name(quad.op);
+                let cond_val = self.get_value(quad.arg1)?;
+                let is_true = match cond_val {
+                    VMValue::Bool(b) => b,
+                    VMValue::Int(i) => i != 0,
+                    VMValue::Float(_) => return Err(format!("{} condition cannot be a float", op_name)),
+                };
 
-                    // Ensure staged_params is large enough. This handles cases where PARAMs might not be strictly sequential.
-                    if param_k_idx >= self.staged_params.len() {
-                        self.staged_params.resize_with(param_k_idx + 1, || VMValue::Int(-999)); // Dummy placeholder
+                // GOTOF jumps when the condition is false, GOTOT when it is true
+                if is_true == (quad.op == OpCode::GOTOT) {
+                    if quad.result < 0 || quad.result as usize > self.quads.len() {
+                        return Err(format!("{}: Invalid jump target {}", op_name, quad.result));
                     }
-                    self.staged_params[param_k_idx] = arg_val;
+                    self.ip = quad.result as usize;
+                } else {
                     self.ip += 1;
                 }
-                OpCode::GOSUB => {
-                    let target_func_start_idx = quad.arg1; // This is the func's start quad index
+            }
+            OpCode::ERA => {
+                // quad.arg1 is the start_quad_idx of the function being called
+                if let Some(func_info) = self.functions.get(&quad.arg1) {
+                    self.staged_params = Vec::with_capacity(func_info.param_count);
+                } else {
+                    return Err(format!("ERA: Function with start_idx {} not found.", quad.arg1));
+                }
+                self.ip += 1;
+            }
+            OpCode::PARAM => {
+                let arg_val = self.get_value(quad.arg1)?; // Value to be passed
+                let param_k_idx = quad.result as usize;    // k-th parameter (0-indexed)
 
-                    // First, validate the function exists and get the required info
-                    let (func_name, param_count, param_addresses) = if let Some(func_info) = self.functions.get(&target_func_start_idx) {
-                        (func_info.name.clone(), func_info.param_count, func_info.param_addresses.clone())
-                    } else {
-                        return Err(format!("GOSUB: Function with start_idx {} not found.", target_func_start_idx));
-                    };
+                // Ensure staged_params is large enough. This handles cases where PARAMs might not be strictly sequential.
+                if param_k_idx >= self.staged_params.len() {
+                    self.staged_params.resize(param_k_idx + 1, None);
+                }
+                self.staged_params[param_k_idx] = Some(arg_val);
+                self.ip += 1;
+            }
+            OpCode::GOSUB => {
+                let target_func_start_idx = quad.arg1; // This is the func's start quad index
 
-                    // Validate parameter count
-                    if param_count != self.staged_params.len() {
-                        return Err(format!("GOSUB: Mismatched param count for function '{}' (start_idx {}). Expected {}, got {} staged params.", func_name, target_func_start_idx, param_count, self.staged_params.len()));
-                    }
+                // First, validate the function exists and get the required info
+                let (func_name, param_count, param_addresses, frame_cells) = if let Some(func_info) = self.functions.get(&target_func_start_idx) {
+                    (func_info.name.clone(), func_info.param_count, func_info.param_addresses.clone(), func_info.frame_cells.clone())
+                } else {
+                    return Err(format!("GOSUB: Function with start_idx {} not found.", target_func_start_idx));
+                };
 
-                    // Clone staged_params to avoid borrowing issues
-                    let staged_params_copy = self.staged_params.clone();
+                // Validate parameter count
+                if param_count != self.staged_params.len() {
+                    return Err(format!("GOSUB: Mismatched param count for function '{}' (start_idx {}). Expected {}, got {} staged params.", func_name, target_func_start_idx, param_count, self.staged_params.len()));
+                }
 
-                    // Copy parameters to their destination addresses
-                    for (k_idx, staged_val) in staged_params_copy.iter().enumerate() {
-                        if k_idx < param_addresses.len() {
-                            let param_dest_addr = param_addresses[k_idx];
-                            self.set_value(param_dest_addr, staged_val.clone())?;
-                        } else {
-                            return Err(format!("GOSUB: Not enough destination addresses provided for function '{}' for param index {}.", func_name, k_idx));
-                        }
-                    }
+                // Clone staged_params to avoid borrowing issues
+                let staged_params_copy = self.staged_params.clone();
 
-                    // Push return address (next instruction after GOSUB)
-                    self.call_stack.push(self.ip + 1);
+                // Keep the callee's cells as the caller left them, before the parameters overwrite
+                // some; when the callee is already running, they hold that activation's values
+                let frame = self.save_frame(&frame_cells);
 
-                    // Jump to function start
-                    if target_func_start_idx < 0 || target_func_start_idx as usize >= self.quads.len() {
-                        return Err(format!("GOSUB: Invalid function start index {}", target_func_start_idx));
+                // Copy parameters to their destination addresses
+                for (k_idx, staged_val) in staged_params_copy.iter().enumerate() {
+                    let Some(staged_val) = staged_val else {
+                        return Err(format!("GOSUB: Parameter {} of function '{}' was never set by a PARAM", k_idx, func_name));
+                    };
+                    if k_idx < param_addresses.len() {
+                        let param_dest_addr = param_addresses[k_idx];
+                        self.set_value(param_dest_addr, staged_val.clone())?;
+                    } else {
+                        return Err(format!("GOSUB: Not enough destination addresses provided for function '{}' for param index {}.", func_name, k_idx));
                     }
-                    self.ip = target_func_start_idx as usize;
+                }
 
-                    self.staged_params.clear(); // Clear after use
+                // Push return address (next instruction after GOSUB)
+                if self.call_stack.len() >= MAX_CALL_DEPTH {
+                    return Err(format!("Stack overflow: call depth exceeded {} in function '{}'", MAX_CALL_DEPTH, func_name));
                 }
-                OpCode::ENDFUNC => {
-                    if let Some(ret_ip) = self.call_stack.pop() {
-                        if ret_ip >= self.quads.len() {
-                            return Err(format!("ENDFUNC: Invalid return address {}", ret_ip));
-                        }
-                        self.ip = ret_ip;
-                    } else {
-                        // If call stack is empty and we hit ENDFUNC, this means we're returning from main
-                        // In this case, we should treat it as program termination
-                        return Ok(());
-                    }
+                self.call_stack.push(self.ip + 1);
+                self.call_functions.push(target_func_start_idx);
+                self.saved_frames.push(frame);
+                self.max_call_depth = self.max_call_depth.max(self.call_stack.len());
+
+                // Jump to function start
+                if target_func_start_idx < 0 || target_func_start_idx as usize >= self.quads.len() {
+                    return Err(format!("GOSUB: Invalid function start index {}", target_func_start_idx));
+                }
+                self.ip = target_func_start_idx as usize;
+
+                self.staged_params.clear(); // Clear after use
+            }
+            OpCode::ENDFUNC | OpCode::RETURN => {
+                if quad.op == OpCode::RETURN {
+                    let result = self.get_value(quad.arg1)?;
+                    self.set_value(quad.result, result)?;
                 }
-                OpCode::HALT => {
-                    // println!("Program halted at IP: {}.", self.ip);
-                    return Ok(()); // End execution
+                if let Some(ret_ip) = self.call_stack.pop() {
+                    self.call_functions.pop();
+                    if let Some(frame) = self.saved_frames.pop() {
+                        self.restore_frame(frame)?;
+                    }
+                    // Returning just past the last quad is how call_function regains control
+                    if ret_ip > self.quads.len() {
+                        return Err(format!("{}: Invalid return address {}", opcode_name(quad.op), ret_ip));
+                    }
+                    self.ip = ret_ip;
+                } else {
+                    // If call stack is empty and we hit ENDFUNC, this means we're returning from main
+                    // In this case, we should treat it as program termination
+                    return Ok(Some(0));
                 }
-                _ => return Err(format!("Unknown OpCode: {} at IP: {}", quad.op, self.ip)),
             }
+            OpCode::HALT => {
+                // println!("Program halted at IP: {}.", self.ip);
+                return Ok(Some(0)); // End execution
+            }
+            OpCode::EXIT => {
+                return match self.get_value(quad.arg1)? {
+                    VMValue::Int(code) => Ok(Some(code)),
+                    other => Err(format!("EXIT: Exit code must be an int, got {:?}", other)),
+                };
+            }
+            _ => return Err(format!("Unknown OpCode: {} at IP: {}", quad.op, self.ip)),
         }
 
-        // If we exit the loop without hitting HALT or ENDFUNC, check if this is normal termination
-        if self.call_stack.is_empty() {
-            Ok(()) // Normal program termination
-        } else {
-            Err("Program ended without proper return from function calls".to_string())
-        }
+        Ok(None)
     }
 }